@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.28;
+
+import "@openzeppelin/contracts/token/ERC721/IERC721.sol";
+import "@openzeppelin/contracts/token/ERC20/IERC20.sol";
+import "@openzeppelin/contracts/token/ERC20/utils/SafeERC20.sol";
+import "@openzeppelin/contracts/interfaces/IERC2981.sol";
+import "@openzeppelin/contracts/utils/ReentrancyGuard.sol";
+import "@openzeppelin/contracts/utils/cryptography/SignatureChecker.sol";
+import "@openzeppelin/contracts/utils/cryptography/EIP712.sol";
+import "@openzeppelin/contracts/access/Ownable.sol";
+
+// Sellers keep their tokens and approve the marketplace; bids are held in escrow
+// until they are accepted or withdrawn. Native ETH is represented by address(0).
+contract GenesisMarketplace is ReentrancyGuard, EIP712, Ownable {
+    using SafeERC20 for IERC20;
+
+    struct Listing {
+        address seller;
+        uint256 price;
+        address currency;
+    }
+
+    struct Bid {
+        uint256 amount;
+        address currency;
+    }
+
+    struct Order {
+        address maker;
+        address collection;
+        uint256 tokenId;
+        uint256 price;
+        address currency;
+        uint256 expiry;
+        uint256 nonce;
+        uint8 side;
+    }
+
+    bytes32 public constant ORDER_TYPEHASH = keccak256(
+        "Order(address maker,address collection,uint256 tokenId,uint256 price,address currency,uint256 expiry,uint256 nonce,uint8 side)"
+    );
+    uint8 public constant SIDE_SELL = 0;
+    uint8 public constant SIDE_BUY = 1;
+
+    IERC721 public immutable collection;
+    address public feeRecipient;
+    uint96 public feeBps;
+
+    mapping(uint256 => Listing) public listings;
+    mapping(uint256 => mapping(address => Bid)) public bids;
+    mapping(address => mapping(uint256 => bool)) public nonceUsed;
+
+    event Listed(uint256 indexed tokenId, address indexed seller, uint256 price, address currency);
+    event ListingCancelled(uint256 indexed tokenId, address indexed seller);
+    event BidPlaced(uint256 indexed tokenId, address indexed bidder, uint256 amount, address currency);
+    event BidWithdrawn(uint256 indexed tokenId, address indexed bidder, uint256 amount);
+    event OrderCancelled(address indexed maker, uint256 nonce);
+    event Sold(
+        uint256 indexed tokenId,
+        address indexed seller,
+        address indexed buyer,
+        uint256 price,
+        address currency,
+        uint256 royalty,
+        uint256 fee
+    );
+
+    error NotSeller();
+    error NotListed();
+    error NotApproved();
+    error NoBid();
+    error InvalidPayment();
+    error InvalidOrder();
+    error PaymentFailed();
+    error FeeTooHigh();
+
+    constructor(
+        address initialOwner,
+        address collection_,
+        address feeRecipient_,
+        uint96 feeBps_
+    ) EIP712("GenesisMarketplace", "1") Ownable(initialOwner) {
+        if (feeBps_ > 10_000) revert FeeTooHigh();
+
+        collection = IERC721(collection_);
+        feeRecipient = feeRecipient_;
+        feeBps = feeBps_;
+    }
+
+    function setFee(address feeRecipient_, uint96 feeBps_) external onlyOwner {
+        if (feeBps_ > 10_000) revert FeeTooHigh();
+
+        feeRecipient = feeRecipient_;
+        feeBps = feeBps_;
+    }
+
+    function list(uint256 tokenId, uint256 price, address currency) external {
+        if (collection.ownerOf(tokenId) != msg.sender) revert NotSeller();
+        if (!_isApproved(msg.sender, tokenId)) revert NotApproved();
+
+        listings[tokenId] = Listing(msg.sender, price, currency);
+        emit Listed(tokenId, msg.sender, price, currency);
+    }
+
+    function updatePrice(uint256 tokenId, uint256 price) external {
+        Listing storage listing = listings[tokenId];
+        if (listing.seller != msg.sender) revert NotSeller();
+
+        listing.price = price;
+        emit Listed(tokenId, msg.sender, price, listing.currency);
+    }
+
+    function cancel(uint256 tokenId) external {
+        if (listings[tokenId].seller != msg.sender) revert NotSeller();
+
+        delete listings[tokenId];
+        emit ListingCancelled(tokenId, msg.sender);
+    }
+
+    function buy(uint256 tokenId) external payable nonReentrant {
+        Listing memory listing = listings[tokenId];
+        if (listing.seller == address(0)) revert NotListed();
+
+        delete listings[tokenId];
+        _collect(listing.currency, msg.sender, listing.price);
+        _settle(tokenId, listing.seller, msg.sender, listing.price, listing.currency);
+    }
+
+    // placing a new bid refunds the bidder's previous bid on the same token
+    function bid(uint256 tokenId, uint256 amount) external payable nonReentrant {
+        Listing memory listing = listings[tokenId];
+        if (listing.seller == address(0)) revert NotListed();
+        if (amount == 0) revert InvalidPayment();
+
+        Bid memory previous = bids[tokenId][msg.sender];
+        delete bids[tokenId][msg.sender];
+        _pay(previous.currency, msg.sender, previous.amount);
+
+        _collect(listing.currency, msg.sender, amount);
+        bids[tokenId][msg.sender] = Bid(amount, listing.currency);
+        emit BidPlaced(tokenId, msg.sender, amount, listing.currency);
+    }
+
+    function withdrawBid(uint256 tokenId) external nonReentrant {
+        Bid memory withdrawn = bids[tokenId][msg.sender];
+        if (withdrawn.amount == 0) revert NoBid();
+
+        delete bids[tokenId][msg.sender];
+        _pay(withdrawn.currency, msg.sender, withdrawn.amount);
+        emit BidWithdrawn(tokenId, msg.sender, withdrawn.amount);
+    }
+
+    function acceptBid(uint256 tokenId, address bidder) external nonReentrant {
+        if (listings[tokenId].seller != msg.sender) revert NotSeller();
+
+        Bid memory accepted = bids[tokenId][bidder];
+        if (accepted.amount == 0) revert NoBid();
+
+        delete listings[tokenId];
+        delete bids[tokenId][bidder];
+        _settle(tokenId, msg.sender, bidder, accepted.amount, accepted.currency);
+    }
+
+    // fills an order signed off-chain: a sell order by the token owner, paid by the
+    // caller, or a buy order (an offer) paid by its maker, the caller selling the token.
+    // Payment and token change hands in the same transaction or not at all
+    function fillOrder(Order calldata order, bytes calldata signature) external payable nonReentrant {
+        if (
+            order.side > SIDE_BUY ||
+            order.collection != address(collection) ||
+            order.expiry < block.timestamp ||
+            nonceUsed[order.maker][order.nonce]
+        ) revert InvalidOrder();
+
+        // an EOA's ECDSA signature, or EIP-1271 when the maker is a contract wallet
+        if (!SignatureChecker.isValidSignatureNow(order.maker, hashOrder(order), signature)) {
+            revert InvalidOrder();
+        }
+
+        (address seller, address buyer) = (order.maker, msg.sender);
+        if (order.side == SIDE_BUY) (seller, buyer) = (msg.sender, order.maker);
+
+        // native ETH can't be pulled from the maker of a buy order
+        if (order.side == SIDE_BUY && order.currency == address(0)) revert InvalidPayment();
+        if (collection.ownerOf(order.tokenId) != seller) revert NotSeller();
+        if (!_isApproved(seller, order.tokenId)) revert NotApproved();
+
+        nonceUsed[order.maker][order.nonce] = true;
+        delete listings[order.tokenId];
+
+        _collect(order.currency, buyer, order.price);
+        _settle(order.tokenId, seller, buyer, order.price, order.currency);
+    }
+
+    function cancelOrder(uint256 nonce) external {
+        nonceUsed[msg.sender][nonce] = true;
+        emit OrderCancelled(msg.sender, nonce);
+    }
+
+    function hashOrder(Order calldata order) public view returns (bytes32) {
+        return _hashTypedDataV4(
+            keccak256(
+                abi.encode(
+                    ORDER_TYPEHASH,
+                    order.maker,
+                    order.collection,
+                    order.tokenId,
+                    order.price,
+                    order.currency,
+                    order.expiry,
+                    order.nonce,
+                    order.side
+                )
+            )
+        );
+    }
+
+    function _isApproved(address owner, uint256 tokenId) internal view returns (bool) {
+        return collection.isApprovedForAll(owner, address(this)) ||
+            collection.getApproved(tokenId) == address(this);
+    }
+
+    function _collect(address currency, address from, uint256 amount) internal {
+        if (currency == address(0)) {
+            if (msg.value != amount) revert InvalidPayment();
+        } else {
+            if (msg.value != 0) revert InvalidPayment();
+            IERC20(currency).safeTransferFrom(from, address(this), amount);
+        }
+    }
+
+    function _pay(address currency, address to, uint256 amount) internal {
+        if (amount == 0) return;
+
+        if (currency == address(0)) {
+            (bool sent, ) = payable(to).call{value: amount}("");
+            if (!sent) revert PaymentFailed();
+        } else {
+            IERC20(currency).safeTransfer(to, amount);
+        }
+    }
+
+    function _settle(
+        uint256 tokenId,
+        address seller,
+        address buyer,
+        uint256 price,
+        address currency
+    ) internal {
+        uint256 fee = (price * feeBps) / 10_000;
+        (address creator, uint256 royalty) = _royalty(tokenId, price);
+        if (royalty > price - fee) royalty = price - fee;
+
+        collection.safeTransferFrom(seller, buyer, tokenId);
+
+        _pay(currency, feeRecipient, fee);
+        _pay(currency, creator, royalty);
+        _pay(currency, seller, price - fee - royalty);
+
+        emit Sold(tokenId, seller, buyer, price, currency, royalty, fee);
+    }
+
+    function _royalty(uint256 tokenId, uint256 price) internal view returns (address, uint256) {
+        try IERC2981(address(collection)).royaltyInfo(tokenId, price) returns (
+            address receiver,
+            uint256 amount
+        ) {
+            return (receiver, amount);
+        } catch {
+            return (address(0), 0);
+        }
+    }
+}
@@ -4,9 +4,10 @@ pragma solidity ^0.8.28;
 import "@openzeppelin/contracts/token/ERC721/ERC721.sol";
 import "@openzeppelin/contracts/token/ERC721/extensions/ERC721URIStorage.sol";
 import "@openzeppelin/contracts/token/ERC721/extensions/ERC721Burnable.sol";
+import "@openzeppelin/contracts/token/common/ERC2981.sol";
 import "@openzeppelin/contracts/access/Ownable.sol";
 
-contract GenesisToken is ERC721, ERC721URIStorage, ERC721Burnable, Ownable {
+contract GenesisToken is ERC721, ERC721URIStorage, ERC721Burnable, ERC2981, Ownable {
     constructor(address initialOwner)
         ERC721("GenesisToken", "GTK")
         Ownable(initialOwner)
@@ -20,6 +21,18 @@ contract GenesisToken is ERC721, ERC721URIStorage, ERC721Burnable, Ownable {
         _setTokenURI(tokenId, uri);
     }
 
+    function safeMintWithRoyalty(
+        address to,
+        uint256 tokenId,
+        string memory uri,
+        address royaltyReceiver,
+        uint96 royaltyFraction
+    ) public onlyOwner {
+        _safeMint(to, tokenId);
+        _setTokenURI(tokenId, uri);
+        _setTokenRoyalty(tokenId, royaltyReceiver, royaltyFraction);
+    }
+
     function tokenURI(uint256 tokenId)
         public
         view
@@ -32,7 +45,7 @@ contract GenesisToken is ERC721, ERC721URIStorage, ERC721Burnable, Ownable {
     function supportsInterface(bytes4 interfaceId)
         public
         view
-        override(ERC721, ERC721URIStorage)
+        override(ERC721, ERC721URIStorage, ERC2981)
         returns (bool)
     {
         return super.supportsInterface(interfaceId);
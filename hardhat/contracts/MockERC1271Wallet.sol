@@ -0,0 +1,36 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.28;
+
+import "@openzeppelin/contracts/interfaces/IERC1271.sol";
+import "@openzeppelin/contracts/utils/cryptography/ECDSA.sol";
+
+// test-only contract wallet accepting signatures of its owner key
+contract MockERC1271Wallet is IERC1271 {
+    address public immutable owner;
+
+    constructor(address owner_) {
+        owner = owner_;
+    }
+
+    function isValidSignature(bytes32 hash, bytes memory signature)
+        public
+        view
+        override
+        returns (bytes4)
+    {
+        (address signer, ECDSA.RecoverError error, ) = ECDSA.tryRecover(hash, signature);
+        if (error == ECDSA.RecoverError.NoError && signer == owner) {
+            return IERC1271.isValidSignature.selector;
+        }
+        return 0xffffffff;
+    }
+
+    // lets the owner act as the wallet, e.g. to approve the marketplace
+    function execute(address target, bytes calldata data) external returns (bytes memory) {
+        require(msg.sender == owner, "not the owner");
+
+        (bool success, bytes memory result) = target.call(data);
+        require(success, "call failed");
+        return result;
+    }
+}
@@ -12,10 +12,13 @@ module.exports = {
     localhost: {
       url: "http://127.0.0.1:8545",
     },
-    sepolia:{
-      url: NETWORK_URL,
-      accounts: [PRIVATE_KEY]
-    }
+    // only with a .env, so the contracts also compile without one
+    ...(NETWORK_URL && PRIVATE_KEY && {
+      sepolia: {
+        url: NETWORK_URL,
+        accounts: [PRIVATE_KEY]
+      }
+    })
   },
   paths: {
     artifacts: "../artifacts"
@@ -0,0 +1,25 @@
+// Deploys the escrow marketplace for an already deployed GenesisToken.
+
+const { buildModule } = require("@nomicfoundation/hardhat-ignition/modules");
+require('dotenv').config({ path: '../../../.env' })
+
+const INITIAL_OWNER = process.env.INITIAL_OWNER;
+const NFT_CONTRACT_ADDRESS = process.env.NFT_CONTRACT_ADDRESS;
+const PLATFORM_FEE_ADDRESS = process.env.PLATFORM_FEE_ADDRESS || INITIAL_OWNER;
+const PLATFORM_FEE_BPS = process.env.PLATFORM_FEE_BPS || 0;
+
+module.exports = buildModule("GenesisMarketplaceModule", (m) => {
+  const initialOwner = m.getParameter("initialOwner", INITIAL_OWNER);
+  const collection = m.getParameter("collection", NFT_CONTRACT_ADDRESS);
+  const feeRecipient = m.getParameter("feeRecipient", PLATFORM_FEE_ADDRESS);
+  const feeBps = m.getParameter("feeBps", PLATFORM_FEE_BPS);
+
+  const genesisMarketplace = m.contract("GenesisMarketplace", [
+    initialOwner,
+    collection,
+    feeRecipient,
+    feeBps,
+  ]);
+
+  return { genesisMarketplace };
+});
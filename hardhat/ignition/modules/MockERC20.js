@@ -0,0 +1,13 @@
+// Deploys a mintable ERC-20 used as a marketplace payment currency on local networks.
+
+const { buildModule } = require("@nomicfoundation/hardhat-ignition/modules");
+
+module.exports = buildModule("MockERC20Module", (m) => {
+  const name = m.getParameter("name", "Mock USD");
+  const symbol = m.getParameter("symbol", "mUSD");
+  const decimals = m.getParameter("decimals", 6);
+
+  const mockERC20 = m.contract("MockERC20", [name, symbol, decimals]);
+
+  return { mockERC20 };
+});
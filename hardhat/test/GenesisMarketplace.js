@@ -0,0 +1,274 @@
+const { loadFixture, time } = require("@nomicfoundation/hardhat-toolbox/network-helpers");
+const { expect } = require("chai");
+const { ethers } = require("hardhat");
+
+const SIDE_SELL = 0;
+const SIDE_BUY = 1;
+const FEE_BPS = 250n;
+const ROYALTY_BPS = 500n;
+const TOKEN_ID = 1n;
+
+const ORDER_TYPES = {
+  Order: [
+    { name: "maker", type: "address" },
+    { name: "collection", type: "address" },
+    { name: "tokenId", type: "uint256" },
+    { name: "price", type: "uint256" },
+    { name: "currency", type: "address" },
+    { name: "expiry", type: "uint256" },
+    { name: "nonce", type: "uint256" },
+    { name: "side", type: "uint8" },
+  ],
+};
+
+describe("GenesisMarketplace", function () {
+  async function deployFixture() {
+    const [owner, seller, buyer, bidder, feeRecipient, creator] = await ethers.getSigners();
+
+    const token = await ethers.deployContract("GenesisToken", [owner.address]);
+    const marketplace = await ethers.deployContract("GenesisMarketplace", [
+      owner.address,
+      await token.getAddress(),
+      feeRecipient.address,
+      FEE_BPS,
+    ]);
+    const usd = await ethers.deployContract("MockERC20", ["Mock USD", "mUSD", 6]);
+
+    await token.safeMintWithRoyalty(seller.address, TOKEN_ID, "ipfs://token-1", creator.address, ROYALTY_BPS);
+    await token.connect(seller).setApprovalForAll(await marketplace.getAddress(), true);
+    await usd.mint(buyer.address, 1_000_000_000n);
+    await usd.mint(bidder.address, 1_000_000_000n);
+
+    return { token, marketplace, usd, owner, seller, buyer, bidder, feeRecipient, creator };
+  }
+
+  // the proceeds of a sale at `price` as [seller, fee recipient, creator]
+  function split(price) {
+    const fee = (price * FEE_BPS) / 10_000n;
+    const royalty = (price * ROYALTY_BPS) / 10_000n;
+    return [price - fee - royalty, fee, royalty];
+  }
+
+  async function signOrder(signer, marketplace, order) {
+    const domain = {
+      name: "GenesisMarketplace",
+      version: "1",
+      chainId: (await ethers.provider.getNetwork()).chainId,
+      verifyingContract: await marketplace.getAddress(),
+    };
+
+    return signer.signTypedData(domain, ORDER_TYPES, order);
+  }
+
+  async function order(maker, token, overrides) {
+    return {
+      maker: maker.address,
+      collection: await token.getAddress(),
+      tokenId: TOKEN_ID,
+      price: ethers.parseEther("1"),
+      currency: ethers.ZeroAddress,
+      expiry: BigInt(await time.latest()) + 3600n,
+      nonce: 1n,
+      side: SIDE_SELL,
+      ...overrides,
+    };
+  }
+
+  describe("listings", function () {
+    it("sells a listed token for ETH and splits the proceeds", async function () {
+      const { token, marketplace, seller, buyer, feeRecipient, creator } = await loadFixture(deployFixture);
+      const price = ethers.parseEther("1");
+
+      await expect(marketplace.connect(seller).list(TOKEN_ID, price, ethers.ZeroAddress))
+        .to.emit(marketplace, "Listed")
+        .withArgs(TOKEN_ID, seller.address, price, ethers.ZeroAddress);
+
+      const [sellerAmount, fee, royalty] = split(price);
+      await expect(marketplace.connect(buyer).buy(TOKEN_ID, { value: price }))
+        .to.emit(marketplace, "Sold")
+        .withArgs(TOKEN_ID, seller.address, buyer.address, price, ethers.ZeroAddress, royalty, fee)
+        .and.to.changeEtherBalances([seller, feeRecipient, creator], [sellerAmount, fee, royalty]);
+
+      expect(await token.ownerOf(TOKEN_ID)).to.equal(buyer.address);
+      expect((await marketplace.listings(TOKEN_ID)).seller).to.equal(ethers.ZeroAddress);
+    });
+
+    it("only lets the owner list, update and cancel", async function () {
+      const { marketplace, seller, buyer } = await loadFixture(deployFixture);
+      const price = ethers.parseEther("1");
+
+      await expect(marketplace.connect(buyer).list(TOKEN_ID, price, ethers.ZeroAddress))
+        .to.be.revertedWithCustomError(marketplace, "NotSeller");
+
+      await marketplace.connect(seller).list(TOKEN_ID, price, ethers.ZeroAddress);
+      await expect(marketplace.connect(buyer).updatePrice(TOKEN_ID, 1n))
+        .to.be.revertedWithCustomError(marketplace, "NotSeller");
+      await expect(marketplace.connect(buyer).cancel(TOKEN_ID))
+        .to.be.revertedWithCustomError(marketplace, "NotSeller");
+
+      await expect(marketplace.connect(seller).cancel(TOKEN_ID))
+        .to.emit(marketplace, "ListingCancelled")
+        .withArgs(TOKEN_ID, seller.address);
+      await expect(marketplace.connect(buyer).buy(TOKEN_ID, { value: price }))
+        .to.be.revertedWithCustomError(marketplace, "NotListed");
+    });
+
+    it("rejects a wrong payment", async function () {
+      const { marketplace, usd, seller, buyer } = await loadFixture(deployFixture);
+
+      await marketplace.connect(seller).list(TOKEN_ID, ethers.parseEther("1"), ethers.ZeroAddress);
+      await expect(marketplace.connect(buyer).buy(TOKEN_ID, { value: ethers.parseEther("0.5") }))
+        .to.be.revertedWithCustomError(marketplace, "InvalidPayment");
+
+      // ERC-20 listings don't take ETH
+      await marketplace.connect(seller).list(TOKEN_ID, 10_000_000n, await usd.getAddress());
+      await usd.connect(buyer).approve(await marketplace.getAddress(), 10_000_000n);
+      await expect(marketplace.connect(buyer).buy(TOKEN_ID, { value: 1n }))
+        .to.be.revertedWithCustomError(marketplace, "InvalidPayment");
+    });
+  });
+
+  describe("bids", function () {
+    it("escrows bids, refunds a replaced bid and withdraws", async function () {
+      const { marketplace, seller, bidder } = await loadFixture(deployFixture);
+      const first = ethers.parseEther("0.5");
+      const second = ethers.parseEther("0.8");
+
+      await marketplace.connect(seller).list(TOKEN_ID, ethers.parseEther("1"), ethers.ZeroAddress);
+
+      await expect(marketplace.connect(bidder).bid(TOKEN_ID, first, { value: first }))
+        .to.changeEtherBalances([bidder, marketplace], [-first, first]);
+      await expect(marketplace.connect(bidder).bid(TOKEN_ID, second, { value: second }))
+        .to.changeEtherBalances([bidder, marketplace], [first - second, second - first]);
+
+      await expect(marketplace.connect(bidder).withdrawBid(TOKEN_ID))
+        .to.emit(marketplace, "BidWithdrawn")
+        .withArgs(TOKEN_ID, bidder.address, second)
+        .and.to.changeEtherBalances([bidder, marketplace], [second, -second]);
+      await expect(marketplace.connect(bidder).withdrawBid(TOKEN_ID))
+        .to.be.revertedWithCustomError(marketplace, "NoBid");
+    });
+
+    it("settles an accepted ERC-20 bid", async function () {
+      const { token, marketplace, usd, seller, bidder, feeRecipient, creator } = await loadFixture(deployFixture);
+      const amount = 80_000_000n;
+
+      await marketplace.connect(seller).list(TOKEN_ID, 100_000_000n, await usd.getAddress());
+      await usd.connect(bidder).approve(await marketplace.getAddress(), amount);
+      await marketplace.connect(bidder).bid(TOKEN_ID, amount);
+
+      await expect(marketplace.connect(bidder).acceptBid(TOKEN_ID, bidder.address))
+        .to.be.revertedWithCustomError(marketplace, "NotSeller");
+
+      const [sellerAmount, fee, royalty] = split(amount);
+      await expect(marketplace.connect(seller).acceptBid(TOKEN_ID, bidder.address))
+        .to.changeTokenBalances(usd, [seller, feeRecipient, creator, marketplace], [sellerAmount, fee, royalty, -amount]);
+
+      expect(await token.ownerOf(TOKEN_ID)).to.equal(bidder.address);
+      expect((await marketplace.bids(TOKEN_ID, bidder.address)).amount).to.equal(0n);
+    });
+  });
+
+  describe("orders", function () {
+    it("fills a signed sell order once", async function () {
+      const { token, marketplace, seller, buyer } = await loadFixture(deployFixture);
+      const sell = await order(seller, token);
+      const signature = await signOrder(seller, marketplace, sell);
+
+      await marketplace.connect(buyer).fillOrder(sell, signature, { value: sell.price });
+      expect(await token.ownerOf(TOKEN_ID)).to.equal(buyer.address);
+      expect(await marketplace.nonceUsed(seller.address, sell.nonce)).to.equal(true);
+
+      await token.connect(buyer).transferFrom(buyer.address, seller.address, TOKEN_ID);
+      await expect(marketplace.connect(buyer).fillOrder(sell, signature, { value: sell.price }))
+        .to.be.revertedWithCustomError(marketplace, "InvalidOrder");
+    });
+
+    it("fills a signed ERC-20 buy order in one transaction", async function () {
+      const { token, marketplace, usd, seller, buyer, feeRecipient, creator } = await loadFixture(deployFixture);
+      const buy = await order(buyer, token, {
+        price: 50_000_000n,
+        currency: await usd.getAddress(),
+        side: SIDE_BUY,
+      });
+      const signature = await signOrder(buyer, marketplace, buy);
+
+      // without the buyer's allowance nothing moves
+      await expect(marketplace.connect(seller).fillOrder(buy, signature)).to.be.reverted;
+      expect(await token.ownerOf(TOKEN_ID)).to.equal(seller.address);
+
+      await usd.connect(buyer).approve(await marketplace.getAddress(), buy.price);
+      await expect(marketplace.connect(buyer).fillOrder(buy, signature))
+        .to.be.revertedWithCustomError(marketplace, "NotSeller");
+
+      const [sellerAmount, fee, royalty] = split(buy.price);
+      await expect(marketplace.connect(seller).fillOrder(buy, signature))
+        .to.changeTokenBalances(usd, [buyer, seller, feeRecipient, creator], [-buy.price, sellerAmount, fee, royalty]);
+      expect(await token.ownerOf(TOKEN_ID)).to.equal(buyer.address);
+    });
+
+    it("fills a buy order of a contract wallet signed by its owner", async function () {
+      const { token, marketplace, usd, seller, buyer, bidder } = await loadFixture(deployFixture);
+      const wallet = await ethers.deployContract("MockERC1271Wallet", [buyer.address]);
+      const walletAddress = await wallet.getAddress();
+      await usd.mint(walletAddress, 1_000_000_000n);
+
+      const buy = await order({ address: walletAddress }, token, {
+        price: 50_000_000n,
+        currency: await usd.getAddress(),
+        side: SIDE_BUY,
+      });
+      const approve = usd.interface.encodeFunctionData("approve", [await marketplace.getAddress(), buy.price]);
+      await wallet.connect(buyer).execute(await usd.getAddress(), approve);
+
+      // the wallet only accepts its owner's signature
+      await expect(marketplace.connect(seller).fillOrder(buy, await signOrder(bidder, marketplace, buy)))
+        .to.be.revertedWithCustomError(marketplace, "InvalidOrder");
+
+      await expect(marketplace.connect(seller).fillOrder(buy, await signOrder(buyer, marketplace, buy)))
+        .to.changeTokenBalance(usd, wallet, -buy.price);
+      expect(await token.ownerOf(TOKEN_ID)).to.equal(walletAddress);
+    });
+
+    it("rejects ETH buy orders, expired, cancelled and forged orders", async function () {
+      const { token, marketplace, seller, buyer } = await loadFixture(deployFixture);
+
+      const ethBuy = await order(buyer, token, { side: SIDE_BUY });
+      await expect(marketplace.connect(seller).fillOrder(ethBuy, await signOrder(buyer, marketplace, ethBuy)))
+        .to.be.revertedWithCustomError(marketplace, "InvalidPayment");
+
+      const expired = await order(seller, token, { expiry: BigInt(await time.latest()) - 1n });
+      await expect(
+        marketplace.connect(buyer).fillOrder(expired, await signOrder(seller, marketplace, expired), { value: expired.price })
+      ).to.be.revertedWithCustomError(marketplace, "InvalidOrder");
+
+      const cancelled = await order(seller, token, { nonce: 7n });
+      await expect(marketplace.connect(seller).cancelOrder(7n))
+        .to.emit(marketplace, "OrderCancelled")
+        .withArgs(seller.address, 7n);
+      await expect(
+        marketplace.connect(buyer).fillOrder(cancelled, await signOrder(seller, marketplace, cancelled), { value: cancelled.price })
+      ).to.be.revertedWithCustomError(marketplace, "InvalidOrder");
+
+      // signed by someone other than the maker
+      const forged = await order(seller, token);
+      await expect(
+        marketplace.connect(buyer).fillOrder(forged, await signOrder(buyer, marketplace, forged), { value: forged.price })
+      ).to.be.revertedWithCustomError(marketplace, "InvalidOrder");
+    });
+  });
+
+  describe("fees", function () {
+    it("only lets the owner set a fee of at most 100%", async function () {
+      const { marketplace, owner, buyer, feeRecipient } = await loadFixture(deployFixture);
+
+      await expect(marketplace.connect(buyer).setFee(buyer.address, 100n))
+        .to.be.revertedWithCustomError(marketplace, "OwnableUnauthorizedAccount");
+      await expect(marketplace.connect(owner).setFee(feeRecipient.address, 10_001n))
+        .to.be.revertedWithCustomError(marketplace, "FeeTooHigh");
+
+      await marketplace.connect(owner).setFee(feeRecipient.address, 100n);
+      expect(await marketplace.feeBps()).to.equal(100n);
+    });
+  });
+});
@@ -0,0 +1,148 @@
+use super::{
+    authentication::AuthenticationGuard,
+    key_management,
+    types::{ActixContext, AddressLabel, WalletAddress},
+};
+use crate::key_shares::SecretKind;
+use actix_web::{HttpResponse, Responder, web};
+use serde_json::json;
+
+pub const DEFAULT_ADDRESS_LABEL: &str = "default";
+const MAX_ADDRESSES: usize = 20;
+const MAX_LABEL_LEN: usize = 32;
+
+fn is_valid_label(label: &str) -> bool {
+    !label.trim().is_empty() && label.chars().count() <= MAX_LABEL_LEN
+}
+
+#[actix_web::get("/me/addresses")]
+async fn get_addresses(auth_guard: AuthenticationGuard) -> impl Responder {
+    HttpResponse::Ok().json(auth_guard.user.addresses)
+}
+
+// derives the next address from the user's seed, e.g. a separate "vault" address
+#[actix_web::post("/me/addresses")]
+async fn create_address(
+    auth_guard: AuthenticationGuard,
+    context: web::Data<ActixContext>,
+    input: web::Json<AddressLabel>,
+) -> impl Responder {
+    let user = auth_guard.user;
+    let label = input.label.trim().to_string();
+
+    if !is_valid_label(&label) {
+        return HttpResponse::BadRequest()
+            .json(json!({"status": "fail", "message": "Invalid label"}));
+    }
+
+    if !user
+        .key_shares
+        .as_ref()
+        .is_some_and(|key_shares| key_shares.secret_kind == SecretKind::HdSeed)
+    {
+        return HttpResponse::BadRequest()
+            .json(json!({"status": "fail", "message": "This wallet can't derive more addresses"}));
+    }
+
+    if user.addresses.len() >= MAX_ADDRESSES {
+        return HttpResponse::Conflict()
+            .json(json!({"status": "fail", "message": "Address limit reached"}));
+    }
+
+    let index = user
+        .addresses
+        .iter()
+        .map(|wallet| wallet.index)
+        .max()
+        .map_or(0, |index| index + 1);
+
+    let address = match key_management::derive_address_key(&context, &user, index).await {
+        Ok(key) => match key.address() {
+            Ok(address) => address.to_string(),
+            Err(err) => {
+                println!("deriving address {} of {} failed: {}", index, user.id, err);
+                return HttpResponse::InternalServerError().finish();
+            }
+        },
+        Err(err) => {
+            println!("deriving address {} of {} failed: {}", index, user.id, err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut users = super::USERS.lock().unwrap();
+    let Some(user) = users.iter_mut().find(|stored| stored.id == user.id) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    // checked again under the lock, a concurrent request may have taken the index or label
+    if user
+        .addresses
+        .iter()
+        .any(|wallet| wallet.index == index || wallet.label == label)
+    {
+        return HttpResponse::Conflict()
+            .json(json!({"status": "fail", "message": "Label already in use"}));
+    }
+
+    let wallet = WalletAddress {
+        index,
+        label,
+        address,
+    };
+    user.addresses.push(wallet.clone());
+
+    HttpResponse::Created().json(wallet)
+}
+
+#[actix_web::put("/me/addresses/{address}")]
+async fn label_address(
+    auth_guard: AuthenticationGuard,
+    address: web::Path<String>,
+    input: web::Json<AddressLabel>,
+) -> impl Responder {
+    let label = input.label.trim().to_string();
+
+    if !is_valid_label(&label) {
+        return HttpResponse::BadRequest()
+            .json(json!({"status": "fail", "message": "Invalid label"}));
+    }
+
+    let mut users = super::USERS.lock().unwrap();
+    let Some(user) = users.iter_mut().find(|user| user.id == auth_guard.user.id) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if user
+        .addresses
+        .iter()
+        .any(|wallet| wallet.label == label && !wallet.address.eq_ignore_ascii_case(&address))
+    {
+        return HttpResponse::Conflict()
+            .json(json!({"status": "fail", "message": "Label already in use"}));
+    }
+
+    match user
+        .addresses
+        .iter_mut()
+        .find(|wallet| wallet.address.eq_ignore_ascii_case(&address))
+    {
+        Some(wallet) => {
+            wallet.label = label;
+            HttpResponse::Ok().json(wallet.clone())
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_validation() {
+        assert!(is_valid_label("vault"));
+        assert!(!is_valid_label("   "));
+        assert!(!is_valid_label(&"x".repeat(MAX_LABEL_LEN + 1)));
+    }
+}
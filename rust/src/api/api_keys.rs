@@ -0,0 +1,302 @@
+use super::{
+    Result,
+    roles::{Admin, RequireRole, roles_of},
+    types::{ApiKeyInfo, ApiKeySummary, Scope, TokenClaims, User},
+};
+use actix_web::{HttpRequest, HttpResponse, Responder, http::Method, web};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+const API_KEY_PREFIX: &str = "gtk";
+
+pub struct ApiKey {
+    pub id: String, // public prefix of the key
+    pub name: String,
+    pub user_id: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used: Option<i64>,
+    pub revoked: bool,
+    key_hash: String,
+}
+
+// Todo - move to db
+static API_KEYS: Mutex<Vec<ApiKey>> = Mutex::new(Vec::new());
+
+// routes reachable with an API key and the scope they need, anything else is session only
+const API_KEY_ROUTES: &[(Method, &str, Scope)] = &[
+    (Method::POST, "/mint", Scope::Mint),
+    (Method::GET, "/", Scope::Read),
+    (Method::GET, "/owner/{token_id}", Scope::Read),
+    (Method::GET, "/metadata/{token_id}", Scope::Read),
+    (Method::GET, "/listings", Scope::Read),
+    (Method::GET, "/offers/{token_id}", Scope::Read),
+    (Method::GET, "/orders/domain", Scope::Read),
+    (Method::GET, "/sales", Scope::Read),
+    (Method::POST, "/list", Scope::Marketplace),
+    (Method::PUT, "/updateListing", Scope::Marketplace),
+    (
+        Method::DELETE,
+        "/cancelListing/{token_id}",
+        Scope::Marketplace,
+    ),
+    (Method::POST, "/bid/{token_id}", Scope::Marketplace),
+    (Method::DELETE, "/bid/{token_id}", Scope::Marketplace),
+    (
+        Method::POST,
+        "/acceptBid/{token_id}/{bid_index}",
+        Scope::Marketplace,
+    ),
+    (Method::POST, "/buy/{token_id}", Scope::Marketplace),
+    (Method::POST, "/offer/{token_id}", Scope::Marketplace),
+    (Method::POST, "/acceptOffer/{offer_id}", Scope::Marketplace),
+    (
+        Method::DELETE,
+        "/rejectOffer/{offer_id}",
+        Scope::Marketplace,
+    ),
+];
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub fn route_scope(method: &Method, pattern: &str) -> Option<Scope> {
+    API_KEY_ROUTES
+        .iter()
+        .find(|(route_method, route_pattern, _)| {
+            route_method == method && *route_pattern == pattern
+        })
+        .map(|(_, _, scope)| *scope)
+}
+
+// keys look like gtk_<id>_<secret>, only the hash is kept
+pub fn create_api_key(
+    name: &str,
+    user_id: &str,
+    scopes: Vec<Scope>,
+    expires_at: Option<i64>,
+) -> (String, ApiKeySummary) {
+    let id = uuid::Uuid::new_v4().simple().to_string()[..12].to_string();
+    let secret = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let key = format!("{API_KEY_PREFIX}_{id}_{secret}");
+
+    let api_key = ApiKey {
+        id,
+        name: name.to_string(),
+        user_id: user_id.to_string(),
+        scopes,
+        created_at: chrono::Utc::now().timestamp(),
+        expires_at,
+        last_used: None,
+        revoked: false,
+        key_hash: hash_key(&key),
+    };
+
+    let summary = ApiKeySummary::from(&api_key);
+    API_KEYS.lock().unwrap().push(api_key);
+
+    (key, summary)
+}
+
+pub fn revoke_api_key(id: &str) -> bool {
+    let mut api_keys = API_KEYS.lock().unwrap();
+
+    match api_keys.iter_mut().find(|api_key| api_key.id == id) {
+        Some(api_key) => {
+            api_key.revoked = true;
+            true
+        }
+        None => false,
+    }
+}
+
+// returns (user id, key id, scopes, expires_at) of a valid key
+fn verify_api_key(key: &str, now: i64) -> Result<(String, String, Vec<Scope>, Option<i64>)> {
+    let id = key
+        .strip_prefix(API_KEY_PREFIX)
+        .and_then(|rest| rest.strip_prefix('_'))
+        .and_then(|rest| rest.split_once('_'))
+        .map(|(id, _)| id)
+        .ok_or("malformed api key")?;
+
+    let mut api_keys = API_KEYS.lock().unwrap();
+    let api_key = api_keys
+        .iter_mut()
+        .find(|api_key| api_key.id == id && api_key.key_hash == hash_key(key))
+        .ok_or("unknown api key")?;
+
+    if api_key.revoked {
+        return Err("api key revoked".into());
+    }
+
+    if api_key
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= now)
+    {
+        return Err("api key expired".into());
+    }
+
+    api_key.last_used = Some(now);
+
+    Ok((
+        api_key.user_id.clone(),
+        api_key.id.clone(),
+        api_key.scopes.clone(),
+        api_key.expires_at,
+    ))
+}
+
+// resolves the key's owner if the key is valid and scoped for the matched route
+pub fn authenticate(req: &HttpRequest, key: &str) -> Result<(User, TokenClaims)> {
+    let now = chrono::Utc::now().timestamp();
+    let (user_id, key_id, scopes, expires_at) = verify_api_key(key, now)?;
+
+    let scope = req
+        .match_pattern()
+        .and_then(|pattern| route_scope(req.method(), &pattern))
+        .ok_or("route not available to api keys")?;
+
+    if !scopes.contains(&scope) {
+        return Err("api key is missing the required scope".into());
+    }
+
+    let user = super::USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|user| user.id == user_id)
+        .cloned()
+        .ok_or("api key owner no longer exists")?;
+
+    // api keys have no session, the key id stands in for it
+    let claims = TokenClaims {
+        sub: user.id.clone(),
+        jti: key_id.clone(),
+        sid: key_id,
+        roles: roles_of(&user.id),
+        iat: now as usize,
+        exp: expires_at.unwrap_or(i64::MAX) as usize,
+    };
+
+    Ok((user, claims))
+}
+
+impl From<&ApiKey> for ApiKeySummary {
+    fn from(api_key: &ApiKey) -> Self {
+        ApiKeySummary {
+            id: api_key.id.clone(),
+            name: api_key.name.clone(),
+            user_id: api_key.user_id.clone(),
+            scopes: api_key.scopes.clone(),
+            created_at: api_key.created_at,
+            expires_at: api_key.expires_at,
+            last_used: api_key.last_used,
+            revoked: api_key.revoked,
+        }
+    }
+}
+
+#[actix_web::post("/admin/apiKeys")]
+async fn create_key(admin: RequireRole<Admin>, input: web::Json<ApiKeyInfo>) -> impl Responder {
+    if input.scopes.is_empty() {
+        return HttpResponse::BadRequest()
+            .json(json!({"status": "fail", "message": "At least one scope is required"}));
+    }
+
+    // keys act on behalf of a service user or, by default, the creating admin
+    let user_id = input.user_id.clone().unwrap_or(admin.user.id);
+
+    if !super::USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|user| user.id == user_id)
+    {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let expires_at = input
+        .expires_in
+        .map(|days| (chrono::Utc::now() + chrono::Duration::days(days)).timestamp());
+
+    let (key, summary) = create_api_key(&input.name, &user_id, input.scopes.clone(), expires_at);
+
+    // the key itself is only ever shown here
+    HttpResponse::Created().json(json!({"key": key, "api_key": summary}))
+}
+
+#[actix_web::get("/admin/apiKeys")]
+async fn get_keys(_admin: RequireRole<Admin>) -> impl Responder {
+    let api_keys: Vec<ApiKeySummary> = API_KEYS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(ApiKeySummary::from)
+        .collect();
+
+    HttpResponse::Ok().json(api_keys)
+}
+
+#[actix_web::delete("/admin/apiKeys/{key_id}")]
+async fn delete_key(_admin: RequireRole<Admin>, key_id: web::Path<String>) -> impl Responder {
+    if revoke_api_key(&key_id) {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_key_lifecycle() {
+        let now = chrono::Utc::now().timestamp();
+        let (key, summary) = create_api_key("indexer", "service-user", vec![Scope::Read], None);
+
+        assert!(key.starts_with(&format!("gtk_{}_", summary.id)));
+
+        let (user_id, key_id, scopes, _) = verify_api_key(&key, now).unwrap();
+        assert_eq!(user_id, "service-user");
+        assert_eq!(key_id, summary.id);
+        assert_eq!(scopes, vec![Scope::Read]);
+
+        // same id, wrong secret
+        let forged = format!("gtk_{}_{}", summary.id, "0".repeat(64));
+        assert!(verify_api_key(&forged, now).is_err());
+
+        assert!(revoke_api_key(&summary.id));
+        assert!(verify_api_key(&key, now).is_err());
+    }
+
+    #[test]
+    fn test_api_key_expiry() {
+        let now = chrono::Utc::now().timestamp();
+        let (key, _) = create_api_key("minter", "service-user", vec![Scope::Mint], Some(now + 60));
+
+        assert!(verify_api_key(&key, now).is_ok());
+        assert!(verify_api_key(&key, now + 60).is_err());
+    }
+
+    #[test]
+    fn test_route_scope() {
+        assert_eq!(route_scope(&Method::POST, "/mint"), Some(Scope::Mint));
+        assert_eq!(route_scope(&Method::GET, "/listings"), Some(Scope::Read));
+        assert_eq!(
+            route_scope(&Method::DELETE, "/bid/{token_id}"),
+            Some(Scope::Marketplace)
+        );
+        assert_eq!(route_scope(&Method::PUT, "/transfer"), None);
+        assert_eq!(route_scope(&Method::GET, "/me/sessions"), None);
+        assert_eq!(route_scope(&Method::GET, "/admin/apiKeys"), None);
+    }
+}
@@ -0,0 +1,60 @@
+use super::{
+    roles::{Admin, RequireRole},
+    sessions,
+};
+use crate::audit_log::{self, AuditQuery, RequestScope};
+use actix_web::{
+    HttpResponse, Responder,
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    web,
+};
+use serde_json::json;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_AUDIT_EVENTS: usize = 1000;
+
+// runs every request in an audit scope, so events recorded anywhere while
+// handling it carry its id and endpoint. The id is returned to the caller
+pub async fn request_scope(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    // forwarding headers only count from TRUSTED_PROXIES, like for sessions
+    let scope = RequestScope::new(
+        format!("{} {}", req.method(), req.path()),
+        sessions::client_ip(req.request()),
+    );
+    let request_id = scope.request_id.clone();
+
+    let mut response = audit_log::in_request(scope, next.call(req)).await?;
+    if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), request_id);
+    }
+
+    Ok(response)
+}
+
+// e.g. /admin/audit?user_id=...&action=key.recover_secret&since=1700000000
+#[actix_web::get("/admin/audit")]
+async fn get_audit_events(
+    _admin: RequireRole<Admin>,
+    query: web::Query<AuditQuery>,
+) -> impl Responder {
+    let mut query = query.into_inner();
+    query.limit = Some(
+        query
+            .limit
+            .unwrap_or(MAX_AUDIT_EVENTS)
+            .min(MAX_AUDIT_EVENTS),
+    );
+
+    HttpResponse::Ok().json(json!({
+        "head": audit_log::head(),
+        "events": audit_log::query(&query),
+    }))
+}
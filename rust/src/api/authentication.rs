@@ -1,11 +1,148 @@
-use super::types::User;
-use actix_web::{FromRequest, HttpRequest, dev::Payload, error as actix_error, http};
+use super::{
+    api_keys::{self, API_KEY_HEADER},
+    sessions,
+    types::{TokenClaims, User},
+};
+use crate::audit_log;
+use actix_web::{
+    FromRequest, HttpRequest, HttpResponse,
+    dev::Payload,
+    error as actix_error,
+    http::header::{AUTHORIZATION, HeaderValue, WWW_AUTHENTICATE},
+};
 use jsonwebtoken::{self as jwt, Algorithm, DecodingKey, Validation};
 use serde_json::json;
 use std::future;
 
+const AUTH_REALM: &str = "genesis";
+
 pub struct AuthenticationGuard {
     pub user: User,
+    pub claims: TokenClaims,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Credential {
+    Jwt(String),
+    ApiKey(String),
+}
+
+// RFC 6750 token68: alphanumerics and -._~+/ followed by optional padding
+fn is_token68(token: &str) -> bool {
+    let unpadded = token.trim_end_matches('=');
+
+    !unpadded.is_empty()
+        && unpadded
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~+/".contains(&b))
+}
+
+// schemes are case insensitive: `Bearer <jwt>`, `Session <jwt>` and `ApiKey <key>`
+pub fn parse_authorization(header: &HeaderValue) -> Result<Credential, &'static str> {
+    let value = header
+        .to_str()
+        .map_err(|_| "malformed authorization header")?;
+    let (scheme, token) = value
+        .split_once(' ')
+        .ok_or("malformed authorization header")?;
+    let token = token.trim_start_matches(' ');
+
+    if !is_token68(token) {
+        return Err("malformed credentials");
+    }
+
+    match scheme.to_ascii_lowercase().as_str() {
+        "bearer" | "session" => Ok(Credential::Jwt(token.to_string())),
+        "apikey" => Ok(Credential::ApiKey(token.to_string())),
+        _ => Err("unsupported authorization scheme"),
+    }
+}
+
+// explicit headers win over the ambient cookie: Authorization, then X-API-Key,
+// then the "token" cookie. A malformed header is an error, not a fallback.
+pub fn select_credential(
+    authorization: Option<&HeaderValue>,
+    api_key: Option<&HeaderValue>,
+    cookie: Option<&str>,
+) -> Result<Option<Credential>, &'static str> {
+    if let Some(authorization) = authorization {
+        return parse_authorization(authorization).map(Some);
+    }
+
+    if let Some(api_key) = api_key {
+        return match api_key.to_str() {
+            Ok(key) if is_token68(key) => Ok(Some(Credential::ApiKey(key.to_string()))),
+            _ => Err("malformed api key"),
+        };
+    }
+
+    Ok(cookie
+        .filter(|token| !token.is_empty())
+        .map(|token| Credential::Jwt(token.to_string())))
+}
+
+// 401 with a WWW-Authenticate challenge (RFC 6750 section 3)
+fn unauthorized(message: &str, error: Option<&str>) -> actix_error::Error {
+    let challenge = match error {
+        Some(error) => format!("Bearer realm=\"{AUTH_REALM}\", error=\"{error}\""),
+        None => format!("Bearer realm=\"{AUTH_REALM}\""),
+    };
+
+    let response = HttpResponse::Unauthorized()
+        .insert_header((WWW_AUTHENTICATE, challenge))
+        .json(json!({"status": "fail", "message": message}));
+
+    actix_error::InternalError::from_response(message.to_string(), response).into()
+}
+
+fn authenticate_jwt(token: &str) -> Result<AuthenticationGuard, actix_error::Error> {
+    // Todo : have a config in the state
+    let jwt_secret = std::env::var("JWT_SECRET").map_err(|_| {
+        actix_error::ErrorInternalServerError(
+            json!({"status": "fail", "message": "Internal Server Error"}),
+        )
+    })?;
+
+    let token = jwt::decode::<TokenClaims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret.as_ref()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .map_err(|_| {
+        unauthorized(
+            "Invalid token or user doesn't exists",
+            Some("invalid_token"),
+        )
+    })?;
+
+    if sessions::is_revoked(&token.claims) {
+        return Err(unauthorized(
+            "Token has been revoked",
+            Some("invalid_token"),
+        ));
+    }
+
+    sessions::touch_session(&token.claims.sid);
+
+    let user = super::USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|user| user.id == token.claims.sub)
+        .cloned()
+        .ok_or_else(|| {
+            unauthorized(
+                "User belonging to this token no logger exists",
+                Some("invalid_token"),
+            )
+        })?;
+
+    audit_log::set_actor(&user.id);
+
+    Ok(AuthenticationGuard {
+        user,
+        claims: token.claims,
+    })
 }
 
 impl FromRequest for AuthenticationGuard {
@@ -13,54 +150,154 @@ impl FromRequest for AuthenticationGuard {
     type Future = future::Ready<std::result::Result<Self, Self::Error>>;
 
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        let token = req
-            .cookie("token")
-            .map(|c| c.value().to_string())
-            .or_else(|| {
-                req.headers()
-                    .get(http::header::AUTHORIZATION)
-                    .map(|h| h.to_str().unwrap().split_at(7).1.to_string())
-            });
-
-        if token.is_none() {
-            return future::ready(Err(actix_error::ErrorUnauthorized(
-                json!({"status": "fail", "message": "You are not logged in, please provide token"}),
-            )));
+        let cookie = req.cookie("token");
+        let credential = select_credential(
+            req.headers().get(AUTHORIZATION),
+            req.headers().get(API_KEY_HEADER),
+            cookie.as_ref().map(|c| c.value()),
+        );
+
+        future::ready(match credential {
+            Ok(Some(Credential::Jwt(token))) => authenticate_jwt(&token),
+            // server-to-server callers authenticate with an API key instead of a session
+            Ok(Some(Credential::ApiKey(key))) => match api_keys::authenticate(req, &key) {
+                Ok((user, claims)) => {
+                    audit_log::set_actor(&user.id);
+                    Ok(AuthenticationGuard { user, claims })
+                }
+                Err(err) => Err(unauthorized(
+                    &format!("Invalid API key: {err}"),
+                    Some("invalid_token"),
+                )),
+            },
+            Ok(None) => Err(unauthorized(
+                "You are not logged in, please provide token",
+                None,
+            )),
+            Err(err) => Err(unauthorized(err, Some("invalid_request"))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{cookie::Cookie, test::TestRequest};
+    use proptest::prelude::*;
+
+    fn header(value: &str) -> HeaderValue {
+        HeaderValue::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn test_parse_authorization_schemes() {
+        assert_eq!(
+            parse_authorization(&header("Bearer abc.def.ghi")),
+            Ok(Credential::Jwt("abc.def.ghi".to_string()))
+        );
+        assert_eq!(
+            parse_authorization(&header("bearer  abc.def.ghi")),
+            Ok(Credential::Jwt("abc.def.ghi".to_string()))
+        );
+        assert_eq!(
+            parse_authorization(&header("Session abc.def.ghi")),
+            Ok(Credential::Jwt("abc.def.ghi".to_string()))
+        );
+        assert_eq!(
+            parse_authorization(&header("ApiKey gtk_1234_abcd")),
+            Ok(Credential::ApiKey("gtk_1234_abcd".to_string()))
+        );
+
+        assert!(parse_authorization(&header("Basic dXNlcjpwYXNz")).is_err());
+        assert!(parse_authorization(&header("Bearer")).is_err());
+        assert!(parse_authorization(&header("Bearer ")).is_err());
+        assert!(parse_authorization(&header("Bear")).is_err());
+        assert!(parse_authorization(&header("Bearer a b")).is_err());
+        assert!(
+            parse_authorization(&HeaderValue::from_bytes(b"Bearer \xff\xfe").unwrap()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_credential_precedence() {
+        let bearer = header("Bearer from.header.jwt");
+        let api_key = header("gtk_1234_abcd");
+
+        assert_eq!(
+            select_credential(Some(&bearer), Some(&api_key), Some("from.cookie.jwt")),
+            Ok(Some(Credential::Jwt("from.header.jwt".to_string())))
+        );
+        assert_eq!(
+            select_credential(None, Some(&api_key), Some("from.cookie.jwt")),
+            Ok(Some(Credential::ApiKey("gtk_1234_abcd".to_string())))
+        );
+        assert_eq!(
+            select_credential(None, None, Some("from.cookie.jwt")),
+            Ok(Some(Credential::Jwt("from.cookie.jwt".to_string())))
+        );
+        assert_eq!(select_credential(None, None, None), Ok(None));
+
+        // a broken header doesn't silently fall back to the cookie
+        assert!(
+            select_credential(Some(&header("Basic x")), None, Some("from.cookie.jwt")).is_err()
+        );
+    }
+
+    #[test]
+    fn test_unauthorized_has_challenge() {
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer"))
+            .to_http_request();
+
+        let err = AuthenticationGuard::from_request(&req, &mut Payload::None)
+            .into_inner()
+            .err()
+            .unwrap();
+        let response = err.error_response();
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Bearer realm=\"genesis\", error=\"invalid_request\""
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_authorization_parsing_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+            if let Ok(value) = HeaderValue::from_bytes(&bytes) {
+                let _ = parse_authorization(&value);
+                let _ = select_credential(None, Some(&value), None);
+            }
         }
 
-        // Todo : have a config in the state
-        let jwt_secret = match std::env::var("JWT_SECRET") {
-            Ok(secret) => secret,
-            Err(_) => {
-                return future::ready(Err(actix_error::ErrorInternalServerError(
-                    json!({"status": "fail", "message": "Internal Server Error"}),
-                )));
+        #[test]
+        fn prop_scheme_prefixed_input_never_panics(scheme in "(?i)(bearer|session|apikey|basic)?", rest in "\\PC{0,40}") {
+            if let Ok(value) = HeaderValue::from_str(&format!("{scheme}{rest}")) {
+                let _ = parse_authorization(&value);
             }
-        };
+        }
 
-        let decode = jwt::decode::<super::types::TokenClaims>(
-            token.unwrap().as_str(),
-            &DecodingKey::from_secret(jwt_secret.as_ref()),
-            &Validation::new(Algorithm::HS256),
-        );
+        #[test]
+        fn prop_extractor_never_panics(
+            authorization in proptest::collection::vec(any::<u8>(), 0..48),
+            api_key in proptest::collection::vec(any::<u8>(), 0..48),
+            cookie in "[ -~]{0,48}",
+        ) {
+            let mut req = TestRequest::default();
 
-        match decode {
-            Ok(token) => {
-                let users = super::USERS.lock().unwrap();
-                let user = users.iter().find(|user| user.id == token.claims.sub);
-
-                match user {
-                    Some(user) => future::ready(Ok(AuthenticationGuard { user: user.clone() })),
-                    None => {
-                        return future::ready(Err(actix_error::ErrorUnauthorized(
-                            json!({"status": "fail", "message": "User belonging to this token no logger exists"}),
-                        )));
-                    }
-                }
+            if let Ok(value) = HeaderValue::from_bytes(&authorization) {
+                req = req.insert_header((AUTHORIZATION, value));
+            }
+            if let Ok(value) = HeaderValue::from_bytes(&api_key) {
+                req = req.insert_header((API_KEY_HEADER, value));
             }
-            Err(_) => future::ready(Err(actix_error::ErrorUnauthorized(
-                json!({"status": "fail", "message": "Invalid token or user doesn't exists"}),
-            ))),
+
+            let req = req.cookie(Cookie::new("token", cookie)).to_http_request();
+            let result = AuthenticationGuard::from_request(&req, &mut Payload::None).into_inner();
+
+            // no random input authenticates anyone
+            prop_assert!(result.is_err());
         }
     }
 }
@@ -1,188 +1,436 @@
-use crate::{blockchain, utils};
-use std::hash::{Hash, Hasher};
+use crate::blockchain;
 
 use super::{
     Result,
-    types::{ActixContext, QueryParams, TokenClaims, User},
+    addresses::DEFAULT_ADDRESS_LABEL,
+    authentication::AuthenticationGuard,
+    key_management,
+    oidc::{Provider, ProviderIdentity},
+    roles,
+    sessions::{self, SessionClient},
+    types::{ActixContext, Identity, LoginParams, QueryParams, User, WalletAddress},
 };
 use actix_web::{
-    HttpResponse, Responder,
-    cookie::{Cookie, time::Duration as CookieDuration},
+    HttpRequest, HttpResponse, Responder,
+    cookie::{Cookie, SameSite, time::Duration as CookieDuration},
     http::header::LOCATION,
     web,
 };
-use jsonwebtoken::{EncodingKey, Header};
-use reqwest::{Client, Url};
-use serde::Deserialize;
-
-#[derive(Deserialize, Debug)]
-pub struct OAuthResponse {
-    pub access_token: String,
-    pub id_token: String,
-}
-
-#[derive(Deserialize, Debug, Default)]
-#[allow(unused)]
-pub struct GoogleUserResult {
-    pub id: String,
-    pub email: String,
-    pub verified_email: bool,
-    pub name: String,
-    pub given_name: String,
-    pub family_name: String,
-    pub picture: String,
-}
-
-pub async fn request_token(client: &Client, authorization_code: &str) -> Result<OAuthResponse> {
-    // Todo : have a config in the state
-    let redirect_url = std::env::var("GOOGLE_OAUTH_REDIRECT_URL")?;
-    let client_secret = std::env::var("GOOGLE_OAUTH_CLIENT_SECRET")?;
-    let client_id = std::env::var("GOOGLE_OAUTH_CLIENT_ID")?;
-    let token_url = "https://oauth2.googleapis.com/token";
-
-    let params = [
-        ("grant_type", "authorization_code"),
-        ("redirect_uri", redirect_url.as_str()),
-        ("client_id", client_id.as_str()),
-        ("code", authorization_code),
-        ("client_secret", client_secret.as_str()),
-    ];
-
-    let response = client
-        .post(token_url)
-        .form(&params)
-        .send()
-        .await?
-        .error_for_status()?;
-
-    let oauth_response = response.json::<OAuthResponse>().await?;
-    Ok(oauth_response)
-}
-
-pub async fn get_google_user(
-    client: &Client,
-    access_token: &str,
-    id_token: &str,
-) -> Result<GoogleUserResult> {
-    let mut url = Url::parse("https://www.googleapis.com/oauth2/v1/userinfo").unwrap();
-    url.query_pairs_mut()
-        .append_pair("alt", "json")
-        .append_pair("access_token", access_token);
-
-    let response = client
-        .get(url)
-        .bearer_auth(id_token)
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok(response.json::<GoogleUserResult>().await?)
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{self as jwt, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const OAUTH_STATE_COOKIE: &str = "oauth_state";
+const OAUTH_STATE_MAXAGE: i64 = 10; // minutes
+
+// pending login, kept client side in a signed cookie between start and callback
+#[derive(Serialize, Deserialize, Debug)]
+struct OAuthStateClaims {
+    provider: String,
+    state: String,
+    code_verifier: String,
+    nonce: String,
+    redirect_to: String,
+    link_user_id: Option<String>, // set when a logged in user links another identity
+    exp: usize,
 }
 
-#[actix_web::get("/auth/google")]
-async fn google_oauth_handler(
+fn random_token() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+// only origins in REDIRECT_ALLOWLIST (comma separated) or CLIENT_ORIGIN are accepted
+fn is_allowed_redirect(redirect_to: &str, allowlist: &[String]) -> bool {
+    let Ok(url) = Url::parse(redirect_to) else {
+        return false;
+    };
+
+    allowlist
+        .iter()
+        .filter_map(|allowed| Url::parse(allowed).ok())
+        .any(|allowed| allowed.origin() == url.origin())
+}
+
+fn redirect_allowlist() -> Vec<String> {
+    let mut allowlist: Vec<String> = std::env::var("REDIRECT_ALLOWLIST")
+        .unwrap_or_default()
+        .split(',')
+        .map(|origin| origin.trim().to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+
+    if let Ok(client_origin) = std::env::var("CLIENT_ORIGIN") {
+        allowlist.push(client_origin);
+    }
+
+    allowlist
+}
+
+fn encode_oauth_state(claims: &OAuthStateClaims) -> Result<String> {
+    let jwt_secret = std::env::var("JWT_SECRET")?;
+
+    Ok(jwt::encode(
+        &Header::default(),
+        claims,
+        &EncodingKey::from_secret(jwt_secret.as_ref()),
+    )?)
+}
+
+fn decode_oauth_state(token: &str) -> Result<OAuthStateClaims> {
+    let jwt_secret = std::env::var("JWT_SECRET")?;
+
+    Ok(jwt::decode::<OAuthStateClaims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret.as_ref()),
+        &Validation::new(Algorithm::HS256),
+    )?
+    .claims)
+}
+
+fn state_cookie_path(provider: &str) -> String {
+    format!("/auth/{provider}")
+}
+
+fn fail(message: &str) -> serde_json::Value {
+    serde_json::json!({"status": "fail", "message": message})
+}
+
+async fn create_user(context: &ActixContext, email: String, identity: Identity) -> Result<User> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let (seed, address) = blockchain::create_eth_account()?;
+    let wallet_address = address.to_string();
+
+    let user = User {
+        id: id.clone(),
+        roles: roles::initial_roles(Some(&email), &wallet_address),
+        email: Some(email),
+        identities: vec![identity],
+        key_shares: Some(
+            key_management::create_key_shares(context, &id, seed.expose_secret()).await?,
+        ),
+        addresses: vec![WalletAddress {
+            index: 0,
+            label: DEFAULT_ADDRESS_LABEL.to_string(),
+            address: wallet_address.clone(),
+        }],
+        wallet_address,
+    };
+
+    super::USERS.lock().unwrap().push(user.clone());
+    Ok(user)
+}
+
+enum LoginError {
+    Conflict,
+    UnknownUser,
+    Internal,
+}
+
+// an identity belongs to at most one user, new identities either link or create an account
+async fn find_or_create_user(
+    context: &ActixContext,
+    provider: &str,
+    identity: ProviderIdentity,
+    email: String,
+    link_user_id: Option<String>,
+) -> std::result::Result<User, LoginError> {
+    let identity = Identity {
+        provider: provider.to_string(),
+        subject: identity.subject,
+    };
+
+    let existing_user = super::USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|user| user.identities.contains(&identity))
+        .cloned();
+
+    match (existing_user, link_user_id) {
+        (Some(user), None) => Ok(user),
+        (Some(user), Some(link_user_id)) if user.id == link_user_id => Ok(user),
+        (Some(_), Some(_)) => Err(LoginError::Conflict),
+        (None, Some(link_user_id)) => {
+            let mut users = super::USERS.lock().unwrap();
+            let user = users
+                .iter_mut()
+                .find(|user| user.id == link_user_id)
+                .ok_or(LoginError::UnknownUser)?;
+
+            user.identities.push(identity);
+            Ok(user.clone())
+        }
+        (None, None) => {
+            // users migrated from the original server only have their Google email
+            let migrated_user = match provider {
+                "google" => super::USERS
+                    .lock()
+                    .unwrap()
+                    .iter_mut()
+                    .find(|user| {
+                        user.identities.is_empty() && user.email.as_deref() == Some(email.as_str())
+                    })
+                    .map(|user| {
+                        user.identities.push(identity.clone());
+                        user.clone()
+                    }),
+                _ => None,
+            };
+
+            match migrated_user {
+                Some(user) => Ok(user),
+                None => create_user(context, email, identity).await.map_err(|_e| {
+                    println!("creating user failed! {:?}", _e);
+                    LoginError::Internal
+                }),
+            }
+        }
+    }
+}
+
+#[actix_web::get("/auth/{provider}/start")]
+async fn oauth_start(
+    auth_guard: Option<AuthenticationGuard>,
     context: web::Data<ActixContext>,
-    query: web::Query<QueryParams>,
+    path: web::Path<String>,
+    query: web::Query<LoginParams>,
 ) -> impl Responder {
-    if query.auth_code.is_empty() {
-        return HttpResponse::Unauthorized().json(
-            serde_json::json!({"status": "fail", "message": "Authorization code not provided!"}),
-        );
-    }
+    let provider = match Provider::from_env(&path) {
+        Ok(provider) => provider,
+        Err(_) => return HttpResponse::NotFound().json(fail("Unknown login provider!")),
+    };
 
-    let token_response = request_token(&context.http_client, &query.auth_code).await;
+    let allowlist = redirect_allowlist();
 
-    if token_response.is_err() {
-        return HttpResponse::BadGateway()
-            .json(serde_json::json!({"status": "fail", "message": "Token request failed!"}));
+    let redirect_to = match &query.redirect_to {
+        Some(redirect_to) if is_allowed_redirect(redirect_to, &allowlist) => redirect_to.clone(),
+        Some(_) => return HttpResponse::BadRequest().json(fail("Redirect target not allowed!")),
+        None => std::env::var("CLIENT_ORIGIN").unwrap(),
+    };
+
+    let metadata = match provider.metadata(&context.http_client).await {
+        Ok(metadata) => metadata,
+        Err(_e) => {
+            println!("provider discovery failed! {:?}", _e);
+            return HttpResponse::BadGateway().json(fail("Login provider unavailable!"));
+        }
+    };
+
+    let claims = OAuthStateClaims {
+        provider: provider.name.clone(),
+        state: random_token(),
+        code_verifier: random_token(),
+        nonce: random_token(),
+        redirect_to,
+        link_user_id: auth_guard.map(|auth_guard| auth_guard.user.id),
+        exp: (chrono::Utc::now() + chrono::Duration::minutes(OAUTH_STATE_MAXAGE)).timestamp()
+            as usize,
+    };
+
+    let auth_url = provider.authorization_url(
+        &metadata,
+        &claims.state,
+        &pkce_challenge(&claims.code_verifier),
+        &claims.nonce,
+    );
+
+    match (encode_oauth_state(&claims), auth_url) {
+        (Ok(state_token), Ok(auth_url)) => {
+            // Lax so the cookie survives the top-level redirect back from the provider
+            let cookie = Cookie::build(OAUTH_STATE_COOKIE, state_token)
+                .path(state_cookie_path(&provider.name))
+                .max_age(CookieDuration::minutes(OAUTH_STATE_MAXAGE))
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .finish();
+
+            HttpResponse::SeeOther()
+                .append_header((LOCATION, auth_url.to_string()))
+                .cookie(cookie)
+                .finish()
+        }
+        _ => HttpResponse::InternalServerError().json(fail("Internal Server Error")),
     }
+}
 
-    let token_response = token_response.unwrap();
+#[actix_web::get("/auth/{provider}/callback")]
+async fn oauth_callback(
+    req: HttpRequest,
+    context: web::Data<ActixContext>,
+    path: web::Path<String>,
+    query: web::Query<QueryParams>,
+) -> impl Responder {
+    complete_login(&req, &context, &path, &query).await
+}
 
-    let google_user = get_google_user(
-        &context.http_client,
-        &token_response.access_token,
-        &token_response.id_token,
-    )
-    .await;
+// redirect url registered before providers were generic
+#[actix_web::get("/auth/google")]
+async fn google_oauth_handler(
+    req: HttpRequest,
+    context: web::Data<ActixContext>,
+    query: web::Query<QueryParams>,
+) -> impl Responder {
+    complete_login(&req, &context, "google", &query).await
+}
 
-    if google_user.is_err() {
-        return HttpResponse::BadGateway()
-            .json(serde_json::json!({"status": "fail", "message": "Google user request failed!"}));
+async fn complete_login(
+    req: &HttpRequest,
+    context: &ActixContext,
+    provider_name: &str,
+    query: &QueryParams,
+) -> HttpResponse {
+    if query.auth_code.is_empty() {
+        return HttpResponse::Unauthorized().json(fail("Authorization code not provided!"));
     }
 
-    let google_user = google_user.unwrap();
-    let mut users = super::USERS.lock().unwrap();
-    let google_email = google_user.email.to_lowercase();
-
-    let user = match users.iter().find(|user| user.email == google_email) {
-        Some(user) => user.clone(),
-        None => {
-            let id = uuid::Uuid::new_v4().to_string();
-            let new_pk = blockchain::create_eth_account().unwrap();
-            let shares = utils::split_secret(&new_pk.credential().to_bytes()).unwrap();
-
-            let user = User {
-                id: id.clone(),
-                email: google_email,
-                key_shares: [shares[0].to_string(), shares[1].to_string()],
-                wallet_address: new_pk.address().to_string(),
-            };
+    let provider = match Provider::from_env(provider_name) {
+        Ok(provider) => provider,
+        Err(_) => return HttpResponse::NotFound().json(fail("Unknown login provider!")),
+    };
 
-            users.push(user.clone());
+    let oauth_state = req
+        .cookie(OAUTH_STATE_COOKIE)
+        .ok_or("missing state cookie".into())
+        .and_then(|cookie| decode_oauth_state(cookie.value()));
 
-            let mut hasher = std::hash::DefaultHasher::new();
-            id.hash(&mut hasher);
-            let key = format!("S{}", &hasher.finish());
+    let oauth_state = match oauth_state {
+        Ok(oauth_state)
+            if oauth_state.state == query.state && oauth_state.provider == provider.name =>
+        {
+            oauth_state
+        }
+        _ => {
+            return HttpResponse::Unauthorized().json(fail("Invalid or expired login state!"));
+        }
+    };
 
-            // Todo : improve encryption
-            match context.secret_manager.create_secret(&key, &shares[2]).await {
-                Ok(_) => {}
-                Err(_e) => {
-                    // Todo: add logs
-                    println!("creating secret failed! {:?}", _e);
+    let metadata = match provider.metadata(&context.http_client).await {
+        Ok(metadata) => metadata,
+        Err(_e) => {
+            println!("provider discovery failed! {:?}", _e);
+            return HttpResponse::BadGateway().json(fail("Login provider unavailable!"));
+        }
+    };
 
-                    return HttpResponse::InternalServerError().json(
-                        serde_json::json!({"status": "fail", "message": "Internal Server Error"}),
-                    );
-                }
-            };
+    let token_response = provider
+        .request_token(
+            &context.http_client,
+            &metadata,
+            &query.auth_code,
+            &oauth_state.code_verifier,
+        )
+        .await;
 
-            user
+    let token_response = match token_response {
+        Ok(token_response) => token_response,
+        Err(_) => return HttpResponse::BadGateway().json(fail("Token request failed!")),
+    };
+
+    let identity = match provider
+        .identity(
+            &context.http_client,
+            &metadata,
+            &token_response,
+            &oauth_state.nonce,
+        )
+        .await
+    {
+        Ok(identity) => identity,
+        Err(_e) => {
+            println!("identity verification failed! {:?}", _e);
+            return HttpResponse::Unauthorized().json(fail("Invalid ID token!"));
         }
     };
 
-    let jwt_secret = std::env::var("JWT_SECRET").unwrap();
-    let jwt_max_age = std::env::var("TOKEN_MAXAGE")
-        .unwrap()
-        .parse::<i64>()
-        .unwrap();
-    let now = chrono::Utc::now();
-    let iat = now.timestamp() as usize;
-    let exp = (now + chrono::Duration::minutes(jwt_max_age)).timestamp() as usize;
-    let claims: TokenClaims = TokenClaims {
-        sub: user.id,
-        exp,
-        iat,
+    let email = match &identity.email {
+        Some(email) if identity.email_verified => email.to_lowercase(),
+        _ => return HttpResponse::Forbidden().json(fail("Email address is not verified!")),
     };
 
-    let jwt_token = jsonwebtoken::encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(jwt_secret.as_ref()),
+    let user = match find_or_create_user(
+        context,
+        &provider.name,
+        identity,
+        email,
+        oauth_state.link_user_id,
     )
-    .unwrap();
+    .await
+    {
+        Ok(user) => user,
+        Err(LoginError::Conflict) => {
+            return HttpResponse::Conflict()
+                .json(fail("Identity is already linked to another account!"));
+        }
+        Err(LoginError::UnknownUser) => {
+            return HttpResponse::Unauthorized().json(fail("User not found!"));
+        }
+        Err(LoginError::Internal) => {
+            return HttpResponse::InternalServerError().json(fail("Internal Server Error"));
+        }
+    };
 
-    let cookie = Cookie::build("token", jwt_token)
-        .path("/")
-        .max_age(CookieDuration::new(60 * jwt_max_age, 0))
-        .http_only(true)
-        .finish();
+    let tokens = match sessions::create_session(&user.id, SessionClient::from_request(req)) {
+        Ok(tokens) => tokens,
+        Err(_e) => {
+            println!("creating session failed! {:?}", _e);
+            return HttpResponse::InternalServerError().json(fail("Internal Server Error"));
+        }
+    };
 
-    let frontend_origin = std::env::var("CLIENT_ORIGIN").unwrap().to_owned();
+    let [access_cookie, refresh_cookie] = sessions::token_cookies(tokens);
+
+    let mut state_cookie = Cookie::build(OAUTH_STATE_COOKIE, "")
+        .path(state_cookie_path(&provider.name))
+        .finish();
+    state_cookie.make_removal();
 
     HttpResponse::SeeOther()
-        .append_header((LOCATION, frontend_origin))
-        .cookie(cookie)
+        .append_header((LOCATION, oauth_state.redirect_to))
+        .cookie(access_cookie)
+        .cookie(refresh_cookie)
+        .cookie(state_cookie)
         .finish()
 }
+
+#[actix_web::get("/me/identities")]
+async fn get_identities(auth_guard: AuthenticationGuard) -> impl Responder {
+    HttpResponse::Ok().json(auth_guard.user.identities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkce_challenge() {
+        // RFC 7636 appendix B
+        let challenge = pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+        assert_eq!(challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+
+    #[test]
+    fn test_redirect_allowlist() {
+        let allowlist = vec![String::from("https://app.example.com")];
+
+        assert!(is_allowed_redirect(
+            "https://app.example.com/wallet?tab=nfts",
+            &allowlist
+        ));
+        assert!(!is_allowed_redirect(
+            "https://app.example.com.evil.io/",
+            &allowlist
+        ));
+        assert!(!is_allowed_redirect("http://app.example.com/", &allowlist));
+        assert!(!is_allowed_redirect("//evil.io", &allowlist));
+    }
+}
@@ -0,0 +1,203 @@
+use super::{
+    Result, key_management,
+    two_factor::{self, StepUpGuard},
+    types::{ActixContext, KeystoreExportInfo, RecoveryPhrase},
+};
+use crate::{audit_log, key_shares};
+use actix_web::{HttpResponse, Responder, web};
+use alloy::signers::{k256::elliptic_curve::rand_core::OsRng, local::PrivateKeySigner};
+use serde_json::json;
+
+const MIN_KEYSTORE_PASSWORD_LEN: usize = 12;
+const KEYSTORE_FILE: &str = "keystore.json";
+
+// Web3 Secret Storage v3, eth-keystore only writes to disk so go through a scratch dir
+fn encrypt_keystore(pk: &[u8], password: &str) -> Result<String> {
+    let dir = std::env::temp_dir().join(format!("keystore-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir(&dir)?;
+
+    let keystore =
+        PrivateKeySigner::encrypt_keystore(&dir, &mut OsRng, pk, password, Some(KEYSTORE_FILE))
+            .map_err(|err| err.into())
+            .and_then(|_| Ok(std::fs::read_to_string(dir.join(KEYSTORE_FILE))?));
+
+    std::fs::remove_dir_all(&dir)?;
+    keystore
+}
+
+// exports need a second factor on the account, not just a recent check when one exists
+fn require_two_factor(auth_guard: &StepUpGuard) -> Option<HttpResponse> {
+    if two_factor::has_totp(&auth_guard.user.id) {
+        return None;
+    }
+
+    Some(HttpResponse::Forbidden().json(
+        json!({"status": "fail", "message": "Enable two-factor authentication to export keys"}),
+    ))
+}
+
+// the user backup share as words, it can be downloaded until the user confirms
+// it with /me/keys/recoveryShare/verify
+#[actix_web::post("/me/keys/recoveryShare")]
+async fn export_recovery_share(
+    auth_guard: StepUpGuard,
+    context: web::Data<ActixContext>,
+) -> impl Responder {
+    if let Some(response) = require_two_factor(&auth_guard) {
+        return response;
+    }
+
+    let user = auth_guard.user;
+    let exported = key_management::backup_share(&context, &user)
+        .and_then(|(key_shares, share)| Ok((key_shares, key_shares::share_to_words(&share)?)));
+
+    let recorded = audit_log::record(
+        Some(&user.id),
+        "key.export_recovery_share",
+        &user.wallet_address,
+        exported.is_ok(),
+        match &exported {
+            Ok((key_shares, _)) => json!({"generation": key_shares.generation}),
+            Err(err) => json!({"error": err.to_string()}),
+        },
+    );
+
+    // the phrase is only handed out once the export is on record
+    if let Err(err) = recorded {
+        println!("exporting recovery share of {} failed: {}", user.id, err);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match exported {
+        Ok((key_shares, phrase)) => HttpResponse::Ok().json(json!({
+            "phrase": phrase,
+            "generation": key_shares.generation,
+            "threshold": key_shares.threshold,
+            "shares": key_shares.holders,
+        })),
+        Err(err) => {
+            HttpResponse::NotFound().json(json!({"status": "fail", "message": err.to_string()}))
+        }
+    }
+}
+
+// confirms a downloaded phrase, the first time the backup share is dropped from
+// the user record, afterwards the phrase is checked by recovering the wallet with it
+#[actix_web::post("/me/keys/recoveryShare/verify")]
+async fn verify_recovery_share(
+    auth_guard: StepUpGuard,
+    context: web::Data<ActixContext>,
+    input: web::Json<RecoveryPhrase>,
+) -> impl Responder {
+    let user = auth_guard.user;
+    let share = match key_shares::share_from_words(&input.phrase) {
+        Ok(share) => share,
+        Err(err) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"status": "fail", "message": err.to_string()}));
+        }
+    };
+
+    let pending = user.key_shares.as_ref().is_some_and(|key_shares| {
+        key_shares.holders.iter().any(|holder| {
+            holder.location == key_shares::ShareLocation::UserBackup && !holder.exported
+        })
+    });
+
+    let verified = if pending {
+        key_management::confirm_backup_share(&context, &user.id, &share).and_then(|key_shares| {
+            audit_log::record(
+                Some(&user.id),
+                "key.confirm_recovery_share",
+                &user.wallet_address,
+                true,
+                json!({"generation": key_shares.generation}),
+            )
+        })
+    } else {
+        key_management::recover_secret_with(&context, &user, vec![share])
+            .await
+            .map(|_| ())
+    };
+
+    match verified {
+        Ok(()) => HttpResponse::Ok().json(json!({"status": "success"})),
+        Err(err) => {
+            println!("verifying recovery phrase of {} failed: {}", user.id, err);
+            HttpResponse::BadRequest().json(
+                json!({"status": "fail", "message": "Recovery phrase doesn't match the wallet"}),
+            )
+        }
+    }
+}
+
+#[actix_web::post("/me/keys/export")]
+async fn export_keystore(
+    auth_guard: StepUpGuard,
+    context: web::Data<ActixContext>,
+    input: web::Json<KeystoreExportInfo>,
+) -> impl Responder {
+    if let Some(response) = require_two_factor(&auth_guard) {
+        return response;
+    }
+
+    if input.password.chars().count() < MIN_KEYSTORE_PASSWORD_LEN {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "fail",
+            "message": format!("Password must be at least {MIN_KEYSTORE_PASSWORD_LEN} characters"),
+        }));
+    }
+
+    let Some(user) = auth_guard.user.with_address(input.address.as_deref()) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let keystore = match user.get_pk(&context).await {
+        Ok(key) => encrypt_keystore(key.expose_secret(), &input.password),
+        Err(err) => Err(err),
+    };
+
+    // the keystore is only handed out once the export is on record
+    match audit_log::record_result(
+        Some(&user.id),
+        "key.export_keystore",
+        &user.wallet_address,
+        keystore,
+    ) {
+        Ok(keystore) => HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.json\"", user.wallet_address),
+            ))
+            .body(keystore),
+        Err(err) => {
+            println!("exporting keystore of {} failed: {}", user.id, err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keystore_roundtrip() {
+        let signer = PrivateKeySigner::random();
+        let keystore = encrypt_keystore(&signer.to_bytes()[..], "correct horse battery").unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&keystore).unwrap();
+        assert_eq!(json["version"], 3);
+        assert!(!keystore.contains(&hex::encode(signer.to_bytes())));
+
+        let path = std::env::temp_dir().join(format!("keystore-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &keystore).unwrap();
+
+        let decrypted = PrivateKeySigner::decrypt_keystore(&path, "correct horse battery").unwrap();
+        assert_eq!(decrypted.address(), signer.address());
+        assert!(PrivateKeySigner::decrypt_keystore(&path, "wrong password").is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1096 @@
+use super::{
+    Result,
+    addresses::DEFAULT_ADDRESS_LABEL,
+    authentication::AuthenticationGuard,
+    roles::{self, Admin, RequireRole},
+    types::{ActixContext, BaselineUser, User, WalletAddress},
+};
+use crate::{
+    audit_log,
+    blockchain::{self, SecretKey},
+    key_shares::{self, CURRENT_NAMING, KeyShares, SecretKind, ShareHolder, ShareLocation},
+    recovery_service::RecoveryServiceClient,
+    utils,
+};
+use actix_web::{HttpResponse, Responder, web};
+use serde::Serialize;
+use serde_json::json;
+use zeroize::Zeroizing;
+
+fn share_key(
+    context: &ActixContext,
+    user_id: &str,
+    key_shares: &KeyShares,
+    holder: &ShareHolder,
+) -> Result<String> {
+    key_shares::share_name(
+        key_shares.naming,
+        &context.share_naming_key,
+        user_id,
+        key_shares.generation,
+        holder.index,
+    )
+}
+
+fn recovery_service(context: &ActixContext) -> Result<&RecoveryServiceClient> {
+    Ok(context
+        .recovery_service
+        .as_ref()
+        .ok_or("recovery service not configured")?)
+}
+
+async fn fetch_share(
+    context: &ActixContext,
+    user_id: &str,
+    location: ShareLocation,
+    key: &str,
+) -> Result<String> {
+    match location {
+        ShareLocation::SecretStore => context.secret_manager.get_secret(Some(user_id), key).await,
+        ShareLocation::RecoveryService => recovery_service(context)?.get_share(key).await,
+        ShareLocation::UserStore | ShareLocation::UserBackup => {
+            Err("share is kept in the user record".into())
+        }
+    }
+}
+
+async fn store_share(
+    context: &ActixContext,
+    user_id: &str,
+    location: ShareLocation,
+    key: &str,
+    share: &str,
+) -> Result<()> {
+    match location {
+        ShareLocation::SecretStore => {
+            context
+                .secret_manager
+                .create_secret(Some(user_id), key, share)
+                .await
+        }
+        ShareLocation::RecoveryService => recovery_service(context)?.store_share(key, share).await,
+        ShareLocation::UserStore | ShareLocation::UserBackup => Ok(()),
+    }
+}
+
+// removes the shares held outside the user record, returns the names of the
+// ones that couldn't be deleted
+async fn delete_remote_shares(
+    context: &ActixContext,
+    user_id: &str,
+    key_shares: &KeyShares,
+) -> Vec<String> {
+    let mut undeleted = Vec::new();
+
+    for holder in &key_shares.holders {
+        let deleted = match (
+            holder.location,
+            share_key(context, user_id, key_shares, holder),
+        ) {
+            (_, Err(err)) => Err(err),
+            (ShareLocation::SecretStore, Ok(key)) => {
+                context
+                    .secret_manager
+                    .delete_secret(Some(user_id), &key)
+                    .await
+            }
+            (ShareLocation::RecoveryService, Ok(key)) => match recovery_service(context) {
+                Ok(recovery_service) => recovery_service.delete_share(&key).await,
+                Err(err) => Err(err),
+            },
+            (ShareLocation::UserStore | ShareLocation::UserBackup, _) => Ok(()),
+        };
+
+        if let Err(err) = deleted {
+            println!(
+                "deleting share {} ({:?}) of {} failed: {}",
+                holder.index, holder.location, user_id, err
+            );
+            undeleted.push(
+                share_key(context, user_id, key_shares, holder)
+                    .unwrap_or(format!("share {} of {}", holder.index, user_id)),
+            );
+        }
+    }
+
+    undeleted
+}
+
+// splits the key with the configured scheme and hands every share to its holder,
+// if any holder fails the shares written so far are removed again
+async fn write_key_shares(
+    context: &ActixContext,
+    user_id: &str,
+    generation: u32,
+    secret_kind: SecretKind,
+    secret: &[u8],
+) -> Result<KeyShares> {
+    let scheme = &context.share_scheme;
+    let shares = Zeroizing::new(utils::split_secret(
+        secret,
+        scheme.threshold,
+        scheme.num_shares(),
+    )?);
+
+    let mut key_shares = KeyShares {
+        generation,
+        naming: CURRENT_NAMING,
+        secret_kind,
+        threshold: scheme.threshold,
+        holders: Vec::new(),
+        backup_replaced: false,
+        sealed: context.keyring.seal_shares(
+            user_id,
+            &shares
+                .iter()
+                .zip(&scheme.locations)
+                .filter(|(_, location)| location.in_user_record())
+                .map(|(share, _)| share.as_str())
+                .collect::<Vec<_>>(),
+        )?,
+    };
+
+    for (index, (share, location)) in shares.iter().zip(&scheme.locations).enumerate() {
+        let holder = ShareHolder {
+            index: index as u8,
+            location: *location,
+            exported: false,
+        };
+
+        let stored = match share_key(context, user_id, &key_shares, &holder) {
+            Ok(key) => store_share(context, user_id, *location, &key, share).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = stored {
+            delete_remote_shares(context, user_id, &key_shares).await;
+            return Err(err);
+        }
+
+        key_shares.holders.push(holder);
+    }
+
+    Ok(key_shares)
+}
+
+// new users get an HD wallet, the shares hold its seed
+pub async fn create_key_shares(
+    context: &ActixContext,
+    user_id: &str,
+    seed: &[u8],
+) -> Result<KeyShares> {
+    write_key_shares(context, user_id, 0, SecretKind::HdSeed, seed).await
+}
+
+// the key of address `index`
+fn derive_key(secret_kind: SecretKind, secret: &SecretKey, index: u32) -> Result<SecretKey> {
+    match secret_kind {
+        SecretKind::HdSeed => blockchain::derive_hd_key(secret, index),
+        SecretKind::PrivateKey if index == 0 => {
+            Ok(SecretKey::from_bytes(secret.expose_secret().to_vec()))
+        }
+        SecretKind::PrivateKey => Err("a private key wallet only has address 0".into()),
+    }
+}
+
+// the key of one of the user's addresses
+pub async fn recover_key(context: &ActixContext, user: &User, address: &str) -> Result<SecretKey> {
+    let index = user
+        .addresses
+        .iter()
+        .find(|wallet| wallet.address.eq_ignore_ascii_case(address))
+        .ok_or("address doesn't belong to the user")?
+        .index;
+
+    derive_address_key(context, user, index).await
+}
+
+pub async fn derive_address_key(
+    context: &ActixContext,
+    user: &User,
+    index: u32,
+) -> Result<SecretKey> {
+    let key_shares = user
+        .key_shares
+        .as_ref()
+        .ok_or("user signs with an external wallet")?;
+    let secret = recover_secret_with(context, user, Vec::new()).await?;
+
+    derive_key(key_shares.secret_kind, &secret, index)
+}
+
+// collects shares until the threshold is met, an unreachable holder is skipped.
+// `shares` are ones the user brought along, e.g. their backup share
+pub async fn recover_secret_with(
+    context: &ActixContext,
+    user: &User,
+    shares: Vec<String>,
+) -> Result<SecretKey> {
+    let secret = recover_from_holders(context, user, shares).await;
+
+    audit_log::record_result(
+        Some(&user.id),
+        "key.recover_secret",
+        &user.wallet_address,
+        secret,
+    )
+}
+
+async fn recover_from_holders(
+    context: &ActixContext,
+    user: &User,
+    shares: Vec<String>,
+) -> Result<SecretKey> {
+    let key_shares = user
+        .key_shares
+        .as_ref()
+        .ok_or("user signs with an external wallet")?;
+
+    let mut shares = Zeroizing::new(shares);
+    let record_shares = Zeroizing::new(context.keyring.open_shares(&user.id, &key_shares.sealed)?);
+    let mut record_shares = record_shares.iter().cloned();
+
+    for holder in &key_shares.holders {
+        if shares.len() == key_shares.threshold as usize {
+            break;
+        }
+
+        if holder.exported {
+            continue;
+        }
+
+        let share = if holder.location.in_user_record() {
+            record_shares
+                .next()
+                .ok_or("share missing from the user record".into())
+        } else {
+            match share_key(context, &user.id, key_shares, holder) {
+                Ok(key) => fetch_share(context, &user.id, holder.location, &key).await,
+                Err(err) => Err(err),
+            }
+        };
+
+        match share {
+            Ok(share) => shares.push(share),
+            Err(err) => println!(
+                "share {} ({:?}) of {} unavailable: {}",
+                holder.index, holder.location, user.id, err
+            ),
+        }
+    }
+
+    let secret = SecretKey::from_bytes(utils::recover_secret(&shares, key_shares.threshold)?);
+
+    // a corrupted share silently yields a different key
+    let primary_address = user.primary_address().ok_or("user has no wallet address")?;
+    if derive_key(key_shares.secret_kind, &secret, 0)?
+        .address()?
+        .to_string()
+        != primary_address.address
+    {
+        return Err("recovered key doesn't match the wallet address".into());
+    }
+
+    Ok(secret)
+}
+
+// position of the unexported backup share among the user record shares and holders
+fn backup_position(key_shares: &KeyShares) -> Result<(usize, usize)> {
+    key_shares
+        .holders
+        .iter()
+        .enumerate()
+        .filter(|(_, holder)| holder.in_user_record())
+        .enumerate()
+        .find(|(_, (_, holder))| holder.location == ShareLocation::UserBackup)
+        .map(|(record_position, (position, _))| (record_position, position))
+        .ok_or("no backup share available".into())
+}
+
+// the user backup share for download, it stays in the user record until the
+// user confirms holding it with `confirm_backup_share`
+pub fn backup_share(context: &ActixContext, user: &User) -> Result<(KeyShares, String)> {
+    let key_shares = user
+        .key_shares
+        .clone()
+        .ok_or("user signs with an external wallet")?;
+    let (record_position, _) = backup_position(&key_shares)?;
+
+    let mut record_shares =
+        Zeroizing::new(context.keyring.open_shares(&user.id, &key_shares.sealed)?);
+
+    Ok((key_shares, record_shares.swap_remove(record_position)))
+}
+
+// hands the backup share over once the user proved to hold it, it is dropped
+// from the user record so that only the user holds it afterwards
+pub fn confirm_backup_share(
+    context: &ActixContext,
+    user_id: &str,
+    share: &str,
+) -> Result<KeyShares> {
+    let mut users = super::USERS.lock().unwrap();
+    let key_shares = users
+        .iter_mut()
+        .find(|user| user.id == user_id)
+        .and_then(|user| user.key_shares.as_mut())
+        .ok_or("user signs with an external wallet")?;
+    let (record_position, holder_position) = backup_position(key_shares)?;
+
+    let mut record_shares =
+        Zeroizing::new(context.keyring.open_shares(user_id, &key_shares.sealed)?);
+    if record_shares[record_position] != share {
+        return Err("recovery phrase doesn't match the backup share".into());
+    }
+    record_shares.remove(record_position);
+
+    key_shares.sealed = context.keyring.seal_shares(
+        user_id,
+        &record_shares.iter().map(String::as_str).collect::<Vec<_>>(),
+    )?;
+    key_shares.holders[holder_position].exported = true;
+    key_shares.backup_replaced = false;
+
+    Ok(key_shares.clone())
+}
+
+// proactive resharing: the same key is split again into a fresh share set, so
+// shares of older generations no longer combine with the current ones. Returns
+// the names of old shares that couldn't be deleted
+pub async fn reshare_key(context: &ActixContext, user_id: &str) -> Result<Vec<String>> {
+    let user = super::USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|user| user.id == user_id)
+        .cloned()
+        .ok_or("user not found")?;
+
+    let old_shares = user
+        .key_shares
+        .clone()
+        .ok_or("user signs with an external wallet")?;
+
+    // the whole secret is reshared, for HD wallets the seed rather than one address key
+    let secret = recover_secret_with(context, &user, Vec::new()).await?;
+    let mut new_shares = write_key_shares(
+        context,
+        user_id,
+        old_shares.generation + 1,
+        old_shares.secret_kind,
+        secret.expose_secret(),
+    )
+    .await?;
+    drop(secret);
+
+    // the new backup share stays in the user record until the user exports it again
+    new_shares.backup_replaced = old_shares.backup_replaced
+        || old_shares
+            .holders
+            .iter()
+            .any(|holder| holder.location == ShareLocation::UserBackup && holder.exported);
+
+    // make sure the new set recovers the same wallet before the old one is dropped
+    let reshared = User {
+        key_shares: Some(new_shares.clone()),
+        ..user
+    };
+    if let Err(err) = recover_secret_with(context, &reshared, Vec::new()).await {
+        delete_remote_shares(context, user_id, &new_shares).await;
+        return Err(err);
+    }
+
+    let committed = match super::USERS
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|user| user.id == user_id)
+    {
+        // another reshare may have won the race
+        Some(user)
+            if user
+                .key_shares
+                .as_ref()
+                .is_some_and(|shares| shares.generation == old_shares.generation) =>
+        {
+            user.key_shares = Some(new_shares.clone());
+            true
+        }
+        _ => false,
+    };
+
+    if !committed {
+        delete_remote_shares(context, user_id, &new_shares).await;
+        return Err("key shares changed during resharing".into());
+    }
+
+    // the new shares are committed by now, a failed audit log only stops what comes after
+    if new_shares.backup_replaced {
+        if let Err(err) = audit_log::record(
+            Some(user_id),
+            "key.backup_replaced",
+            &reshared.wallet_address,
+            true,
+            json!({"generation": new_shares.generation}),
+        ) {
+            println!(
+                "recording the replaced backup of {} failed: {}",
+                user_id, err
+            );
+        }
+    }
+
+    Ok(delete_remote_shares(context, user_id, &old_shares).await)
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReshareReport {
+    pub reshared: usize,
+    pub failed: Vec<String>,
+    // old shares still held by the secret store or recovery service, to be removed by hand
+    pub undeleted_shares: Vec<String>,
+    // users whose exported backup share stopped working
+    pub replaced_backups: Vec<String>,
+}
+
+// reshares every custodial wallet
+pub async fn reshare_all(context: &ActixContext) -> ReshareReport {
+    let user_ids: Vec<String> = super::USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|user| user.key_shares.is_some())
+        .map(|user| user.id.clone())
+        .collect();
+
+    let mut report = ReshareReport::default();
+
+    for user_id in user_ids {
+        match reshare_key(context, &user_id).await {
+            Ok(undeleted) => {
+                report.reshared += 1;
+                report.undeleted_shares.extend(undeleted);
+                if has_replaced_backup(&user_id) {
+                    report.replaced_backups.push(user_id);
+                }
+            }
+            Err(err) => {
+                println!("resharing key of {} failed: {}", user_id, err);
+                report.failed.push(user_id);
+            }
+        }
+    }
+
+    report
+}
+
+fn has_replaced_backup(user_id: &str) -> bool {
+    super::USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|user| user.id == user_id)
+        .and_then(|user| user.key_shares.as_ref())
+        .is_some_and(|key_shares| key_shares.backup_replaced)
+}
+
+// moves a user of the original server over: recovers the raw key from its two
+// record shares and the secret store share, splits it with the current scheme
+// and removes the old secret once the user is stored. Returns the old secret
+// name if it couldn't be deleted
+pub async fn migrate_baseline_user(
+    context: &ActixContext,
+    baseline: &BaselineUser,
+) -> Result<Option<String>> {
+    let user_id = &baseline.id;
+    let secret_name = baseline
+        .secret_name
+        .clone()
+        .unwrap_or_else(|| key_shares::baseline_share_name(user_id));
+
+    let mut shares = Zeroizing::new(baseline.key_shares.to_vec());
+    shares.push(
+        context
+            .secret_manager
+            .get_secret(Some(user_id), &secret_name)
+            .await?,
+    );
+    let key = SecretKey::from_bytes(utils::recover_secret(&shares, 3)?);
+
+    if key.address()?.to_string() != baseline.wallet_address {
+        return Err("baseline shares don't recover the wallet address".into());
+    }
+
+    let key_shares = write_key_shares(
+        context,
+        user_id,
+        0,
+        SecretKind::PrivateKey,
+        key.expose_secret(),
+    )
+    .await?;
+
+    let email = baseline.email.to_lowercase();
+    let user = User {
+        id: user_id.clone(),
+        roles: roles::initial_roles(Some(&email), &baseline.wallet_address),
+        email: Some(email),
+        identities: Vec::new(), // linked on the next Google login, by email
+        key_shares: Some(key_shares.clone()),
+        wallet_address: baseline.wallet_address.clone(),
+        addresses: vec![WalletAddress {
+            index: 0,
+            label: DEFAULT_ADDRESS_LABEL.to_string(),
+            address: baseline.wallet_address.clone(),
+        }],
+    };
+
+    let committed = {
+        let mut users = super::USERS.lock().unwrap();
+        let exists = users.iter().any(|stored| stored.id == user.id);
+        if !exists {
+            users.push(user);
+        }
+        !exists
+    };
+
+    if !committed {
+        delete_remote_shares(context, user_id, &key_shares).await;
+        return Err("user already migrated".into());
+    }
+
+    match context
+        .secret_manager
+        .delete_secret(Some(user_id), &secret_name)
+        .await
+    {
+        Ok(()) => Ok(None),
+        Err(err) => {
+            println!("deleting baseline share of {} failed: {}", user_id, err);
+            Ok(Some(secret_name))
+        }
+    }
+}
+
+// RESHARE_INTERVAL_HOURS enables the scheduled resharing job
+pub fn reshare_interval() -> Option<std::time::Duration> {
+    std::env::var("RESHARE_INTERVAL_HOURS")
+        .ok()
+        .and_then(|hours| hours.parse::<u64>().ok())
+        .filter(|hours| *hours > 0)
+        .map(|hours| std::time::Duration::from_secs(hours * 60 * 60))
+}
+
+pub async fn run_reshare_job(context: ActixContext, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // the first tick completes immediately, don't reshare on every restart
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let report = reshare_all(&context).await;
+        println!(
+            "scheduled resharing done, reshared: {}, failed: {}, undeleted shares: {:?}",
+            report.reshared,
+            report.failed.len(),
+            report.undeleted_shares
+        );
+    }
+}
+
+#[actix_web::post("/admin/keys/reshare")]
+async fn reshare_shares(
+    _admin: RequireRole<Admin>,
+    context: web::Data<ActixContext>,
+) -> impl Responder {
+    HttpResponse::Ok().json(reshare_all(&context).await)
+}
+
+// takes the users of the original server, e.g. as dumped by it, users that
+// are already here are skipped
+#[actix_web::post("/admin/keys/migrateNames")]
+async fn migrate_names(
+    _admin: RequireRole<Admin>,
+    context: web::Data<ActixContext>,
+    baseline_users: web::Json<Vec<BaselineUser>>,
+) -> impl Responder {
+    let mut migrated = 0;
+    let mut skipped = 0;
+    let mut failed = Vec::new();
+    let mut undeleted_shares = Vec::new();
+
+    for baseline in baseline_users.iter() {
+        if super::USERS
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|user| user.id == baseline.id)
+        {
+            skipped += 1;
+            continue;
+        }
+
+        match migrate_baseline_user(&context, baseline).await {
+            Ok(undeleted) => {
+                migrated += 1;
+                undeleted_shares.extend(undeleted);
+            }
+            Err(err) => {
+                println!("migrating baseline user {} failed: {}", baseline.id, err);
+                failed.push(baseline.id.clone());
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(json!({
+        "naming": CURRENT_NAMING,
+        "migrated": migrated,
+        "skipped": skipped,
+        "failed": failed,
+        "undeleted_shares": undeleted_shares,
+    }))
+}
+
+#[actix_web::post("/admin/users/{user_id}/keys/reshare")]
+async fn reshare_user_shares(
+    _admin: RequireRole<Admin>,
+    context: web::Data<ActixContext>,
+    user_id: web::Path<String>,
+) -> impl Responder {
+    match reshare_key(&context, &user_id).await {
+        Ok(undeleted) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "undeleted_shares": undeleted,
+            "backup_replaced": has_replaced_backup(&user_id),
+        })),
+        Err(err) => {
+            println!("resharing key of {} failed: {}", user_id, err);
+            HttpResponse::InternalServerError()
+                .json(json!({"status": "fail", "message": "Resharing failed"}))
+        }
+    }
+}
+
+// where the shares of the caller's wallet key are held
+#[actix_web::get("/me/keyShares")]
+async fn get_key_shares(auth_guard: AuthenticationGuard) -> impl Responder {
+    match auth_guard.user.key_shares {
+        Some(key_shares) => HttpResponse::Ok().json(json!({
+            "generation": key_shares.generation,
+            "threshold": key_shares.threshold,
+            "shares": key_shares.holders,
+            "backup_replaced": key_shares.backup_replaced,
+        })),
+        None => HttpResponse::NotFound()
+            .json(json!({"status": "fail", "message": "Wallet is not custodial"})),
+    }
+}
+
+// re-wraps every user's data key with the current KEK so older KEK versions can be retired
+#[actix_web::post("/admin/keys/rewrap")]
+async fn rewrap_shares(
+    _admin: RequireRole<Admin>,
+    context: web::Data<ActixContext>,
+) -> impl Responder {
+    let mut users = super::USERS.lock().unwrap();
+    let mut rewrapped = 0;
+    let mut failed = Vec::new();
+
+    for user in users.iter_mut() {
+        let Some(key_shares) = user.key_shares.as_mut() else {
+            continue;
+        };
+
+        match context.keyring.rewrap(&user.id, &mut key_shares.sealed) {
+            Ok(true) => rewrapped += 1,
+            Ok(false) => {}
+            Err(err) => {
+                println!("rewrapping shares of {} failed: {}", user.id, err);
+                failed.push(user.id.clone());
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(json!({
+        "kek_version": context.keyring.current_version(),
+        "rewrapped": rewrapped,
+        "failed": failed,
+    }))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::{
+        blockchain::testing::unconnected_contracts,
+        envelope::Keyring,
+        key_shares::ShareScheme,
+        testing::{StandIn, share_stores},
+    };
+    use actix_web::http::Method;
+
+    // a context holding shares with `locations` in a fresh stand-in
+    pub(in crate::api) fn test_context(
+        threshold: u8,
+        locations: Vec<ShareLocation>,
+    ) -> (ActixContext, web::Data<StandIn>) {
+        let client = reqwest::Client::new();
+        let (secret_manager, recovery_service, store) = share_stores(&client);
+        let (contract, marketplace) = unconnected_contracts().unwrap();
+        let context = ActixContext {
+            contract,
+            marketplace,
+            http_client: client.clone(),
+            secret_manager,
+            keyring: Keyring::new(vec![(1, [9u8; 32])], None).unwrap(),
+            share_scheme: ShareScheme::new(threshold, locations).unwrap(),
+            share_naming_key: vec![7u8; 32],
+            recovery_service: Some(recovery_service),
+        };
+
+        (context, store)
+    }
+
+    // a custodial user, added to USERS
+    pub(in crate::api) async fn test_user(context: &ActixContext) -> User {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (seed, address) = blockchain::create_eth_account().unwrap();
+        let address = address.to_string();
+
+        let user = User {
+            id: id.clone(),
+            email: None,
+            identities: Vec::new(),
+            key_shares: Some(
+                create_key_shares(context, &id, seed.expose_secret())
+                    .await
+                    .unwrap(),
+            ),
+            wallet_address: address.clone(),
+            addresses: vec![WalletAddress {
+                index: 0,
+                label: DEFAULT_ADDRESS_LABEL.to_string(),
+                address,
+            }],
+            roles: Vec::new(),
+        };
+
+        super::super::USERS.lock().unwrap().push(user.clone());
+        user
+    }
+
+    #[tokio::test]
+    async fn test_recover_skips_unreachable_holder() {
+        use ShareLocation::*;
+        let (context, store) = test_context(2, vec![UserStore, SecretStore, RecoveryService]);
+        let user = test_user(&context).await;
+        assert_eq!(store.len(), 2);
+
+        let expected = recover_secret_with(&context, &user, Vec::new())
+            .await
+            .unwrap();
+
+        // the secret store is tried first, the recovery service makes up for it
+        store.fail("/hcp", Method::GET);
+        let recovered = recover_secret_with(&context, &user, Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(recovered.expose_secret(), expected.expose_secret());
+
+        store.fail("/recovery", Method::GET);
+        assert!(
+            recover_secret_with(&context, &user, Vec::new())
+                .await
+                .is_err()
+        );
+
+        store.recover();
+        assert!(
+            recover_secret_with(&context, &user, Vec::new())
+                .await
+                .is_ok()
+        );
+    }
+
+    fn key_shares_of(user_id: &str) -> KeyShares {
+        super::super::USERS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|user| user.id == user_id)
+            .and_then(|user| user.key_shares.clone())
+            .unwrap()
+    }
+
+    fn user_of(user_id: &str) -> User {
+        super::super::USERS
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|user| user.id == user_id)
+            .cloned()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reshare_key() {
+        use ShareLocation::*;
+        let (context, store) = test_context(2, vec![UserStore, SecretStore, RecoveryService]);
+        let user = test_user(&context).await;
+        let old_keys = store.keys();
+        let secret = recover_secret_with(&context, &user, Vec::new())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            reshare_key(&context, &user.id).await.unwrap(),
+            Vec::<String>::new()
+        );
+
+        let reshared = user_of(&user.id);
+        let key_shares = reshared.key_shares.as_ref().unwrap();
+        assert_eq!(key_shares.generation, 1);
+        assert!(!key_shares.backup_replaced);
+
+        // same wallet, only new shares are held
+        let recovered = recover_secret_with(&context, &reshared, Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(recovered.expose_secret(), secret.expose_secret());
+        assert_eq!(store.len(), 2);
+        assert!(store.keys().iter().all(|key| !old_keys.contains(key)));
+
+        // the old user record doesn't combine with the new remote shares
+        assert!(
+            recover_secret_with(&context, &user, Vec::new())
+                .await
+                .is_err()
+        );
+
+        // secret store accesses outside of a request are recorded for the user
+        let events = audit_log::query(&audit_log::AuditQuery {
+            user_id: Some(user.id.clone()),
+            action: Some("secret.delete".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(events.len(), 1);
+        assert!(events[0].request_id.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reshare_rolls_back_when_a_holder_fails() {
+        use ShareLocation::*;
+        let (context, store) = test_context(2, vec![UserStore, SecretStore, RecoveryService]);
+        let user = test_user(&context).await;
+        let old_keys = store.keys();
+
+        store.fail("/recovery", Method::PUT);
+        assert!(reshare_key(&context, &user.id).await.is_err());
+
+        // the new secret store share is removed again and the old set still works
+        assert_eq!(key_shares_of(&user.id).generation, 0);
+        assert_eq!(store.keys(), old_keys);
+        assert!(
+            recover_secret_with(&context, &user_of(&user.id), Vec::new())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reshare_loses_generation_race() {
+        use ShareLocation::*;
+        let (context, store) = test_context(2, vec![UserStore, SecretStore, RecoveryService]);
+        let user = test_user(&context).await;
+        let old_keys = store.keys();
+
+        // another reshare commits while this one writes its shares
+        let user_id = user.id.clone();
+        *store.on_write.lock().unwrap() = Some(Box::new(move || {
+            let mut users = super::super::USERS.lock().unwrap();
+            let user = users.iter_mut().find(|user| user.id == user_id).unwrap();
+            user.key_shares.as_mut().unwrap().generation = 7;
+        }));
+
+        assert!(reshare_key(&context, &user.id).await.is_err());
+        assert_eq!(key_shares_of(&user.id).generation, 7);
+        assert_eq!(store.keys(), old_keys);
+    }
+
+    #[tokio::test]
+    async fn test_reshare_reports_undeleted_shares() {
+        use ShareLocation::*;
+        let (context, store) = test_context(2, vec![UserStore, SecretStore, RecoveryService]);
+        let user = test_user(&context).await;
+        let old_secret_store_key = store
+            .keys()
+            .into_iter()
+            .find(|key| key.starts_with("/hcp/"))
+            .unwrap();
+
+        store.fail("/hcp", Method::DELETE);
+        let undeleted = reshare_key(&context, &user.id).await.unwrap();
+
+        assert_eq!(key_shares_of(&user.id).generation, 1);
+        assert_eq!(
+            undeleted,
+            vec![old_secret_store_key.trim_start_matches("/hcp/").to_string()]
+        );
+        assert_eq!(store.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reshare_flags_replaced_backup() {
+        use ShareLocation::*;
+        let (context, _store) =
+            test_context(3, vec![UserStore, UserBackup, SecretStore, RecoveryService]);
+        let user = test_user(&context).await;
+
+        let (_, share) = backup_share(&context, &user).unwrap();
+        confirm_backup_share(&context, &user.id, &share).unwrap();
+        reshare_key(&context, &user.id).await.unwrap();
+
+        let key_shares = key_shares_of(&user.id);
+        assert!(key_shares.backup_replaced);
+        assert!(key_shares.holders.iter().all(|holder| !holder.exported));
+
+        // exporting the new backup share clears it
+        let (_, share) = backup_share(&context, &user_of(&user.id)).unwrap();
+        let key_shares = confirm_backup_share(&context, &user.id, &share).unwrap();
+        assert!(!key_shares.backup_replaced);
+    }
+
+    #[tokio::test]
+    async fn test_backup_share_is_kept_until_confirmed() {
+        use ShareLocation::*;
+        let (context, store) =
+            test_context(3, vec![UserStore, UserBackup, SecretStore, RecoveryService]);
+        let user = test_user(&context).await;
+
+        // a download that never arrived can be repeated
+        let (_, share) = backup_share(&context, &user).unwrap();
+        let (_, again) = backup_share(&context, &user_of(&user.id)).unwrap();
+        assert_eq!(share, again);
+        assert!(
+            key_shares_of(&user.id)
+                .holders
+                .iter()
+                .all(|holder| !holder.exported)
+        );
+
+        let other = test_user(&context).await;
+        let (_, other_share) = backup_share(&context, &other).unwrap();
+        assert!(confirm_backup_share(&context, &user.id, &other_share).is_err());
+
+        let key_shares = confirm_backup_share(&context, &user.id, &share).unwrap();
+        assert!(key_shares.holders[1].exported);
+        assert_eq!(
+            context
+                .keyring
+                .open_shares(&user.id, &key_shares.sealed)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(backup_share(&context, &user_of(&user.id)).is_err());
+        assert!(confirm_backup_share(&context, &user.id, &share).is_err());
+
+        // the remaining holders still recover the key, as does the backup in place of one
+        let user = user_of(&user.id);
+        let secret = recover_secret_with(&context, &user, Vec::new())
+            .await
+            .unwrap();
+        store.fail("/hcp", Method::GET);
+        let recovered = recover_secret_with(&context, &user, vec![share])
+            .await
+            .unwrap();
+        assert_eq!(recovered.expose_secret(), secret.expose_secret());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_baseline_user() {
+        use ShareLocation::*;
+        let (context, store) =
+            test_context(3, vec![UserStore, UserBackup, SecretStore, RecoveryService]);
+
+        // as the original server wrote them, a raw key split 3-of-3 with the third
+        // share in the secret store under `S<DefaultHasher(id)>`
+        let id = uuid::Uuid::new_v4().to_string();
+        let signer = alloy::signers::local::PrivateKeySigner::random();
+        let key = signer.credential().to_bytes().to_vec();
+        let shares = utils::split_secret(&key, 3, 3).unwrap();
+        store.shares.lock().unwrap().insert(
+            format!("/hcp/{}", key_shares::baseline_share_name(&id)),
+            shares[2].clone(),
+        );
+        let baseline: BaselineUser = serde_json::from_value(json!({
+            "id": id,
+            "email": "Collector@Example.com",
+            "key_shares": [shares[0], shares[1]],
+            "wallet_address": signer.address().to_string(),
+        }))
+        .unwrap();
+
+        assert_eq!(
+            migrate_baseline_user(&context, &baseline).await.unwrap(),
+            None
+        );
+
+        let user = user_of(&id);
+        let key_shares = user.key_shares.as_ref().unwrap();
+        assert_eq!(key_shares.secret_kind, SecretKind::PrivateKey);
+        assert_eq!(key_shares.naming, CURRENT_NAMING);
+        assert_eq!(user.email.as_deref(), Some("collector@example.com"));
+        // the old secret is gone, the secret store and recovery service hold the new shares
+        assert!(
+            !store
+                .keys()
+                .contains(&format!("/hcp/{}", key_shares::baseline_share_name(&id)))
+        );
+        assert_eq!(store.len(), 2);
+
+        let recovered = recover_key(&context, &user, &baseline.wallet_address)
+            .await
+            .unwrap();
+        assert_eq!(recovered.expose_secret(), key.as_slice());
+        assert!(derive_address_key(&context, &user, 1).await.is_err());
+
+        assert!(migrate_baseline_user(&context, &baseline).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_baseline_user_by_recorded_name() {
+        use ShareLocation::*;
+        let (context, store) =
+            test_context(3, vec![UserStore, UserBackup, SecretStore, RecoveryService]);
+
+        let signer = alloy::signers::local::PrivateKeySigner::random();
+        let shares = utils::split_secret(&signer.credential().to_bytes(), 3, 3).unwrap();
+        store
+            .shares
+            .lock()
+            .unwrap()
+            .insert("/hcp/S42".to_string(), shares[2].clone());
+        let mut baseline = BaselineUser {
+            id: uuid::Uuid::new_v4().to_string(),
+            email: "collector@example.com".to_string(),
+            key_shares: [shares[1].clone(), shares[0].clone()],
+            wallet_address: alloy::signers::local::PrivateKeySigner::random()
+                .address()
+                .to_string(),
+            secret_name: Some("S42".to_string()),
+        };
+
+        // shares of another wallet are refused and nothing is written
+        assert!(migrate_baseline_user(&context, &baseline).await.is_err());
+        assert_eq!(store.keys(), vec!["/hcp/S42".to_string()]);
+
+        baseline.wallet_address = signer.address().to_string();
+        assert_eq!(
+            migrate_baseline_user(&context, &baseline).await.unwrap(),
+            None
+        );
+        assert!(!store.keys().contains(&"/hcp/S42".to_string()));
+    }
+}
@@ -1,12 +1,227 @@
 use super::{
+    Result,
     authentication::AuthenticationGuard,
-    types::{ActixContext, BidInfo, ListingInfo},
+    two_factor::StepUpGuard,
+    types::{
+        ActixContext, BidInfo, ListingInfo, Offer, OfferInfo, OrderSignatureInfo, SaleInfo,
+        SaleSplit, User,
+    },
 };
+use crate::blockchain::{self, ORDER_SIDE_BUY, ORDER_SIDE_SELL, RoyaltyInfo, SignedOrder};
 use actix_web::{HttpResponse, Responder, web};
+use alloy::primitives::U256;
 use std::sync::Mutex;
 
 // Todo - move to db
 static LISTINGS: Mutex<Vec<ListingInfo>> = Mutex::new(Vec::new());
+static OFFERS: Mutex<Vec<Offer>> = Mutex::new(Vec::new());
+static SALES: Mutex<Vec<SaleInfo>> = Mutex::new(Vec::new());
+static ORDER_NONCES: Mutex<Vec<(String, U256)>> = Mutex::new(Vec::new()); // (maker, nonce)
+
+const DEFAULT_ORDER_MAXAGE: i64 = 7 * 24 * 60; // minutes
+
+fn prune_expired_offers(offers: &mut Vec<Offer>, now: i64) {
+    offers.retain(|offer| offer.expires_at > now);
+}
+
+fn split_proceeds(
+    price: f64,
+    royalty: RoyaltyInfo,
+    platform_fee: Option<(String, u16)>,
+) -> SaleSplit {
+    let (platform, platform_amount) = match platform_fee {
+        Some((address, fee_bps)) => (Some(address), price * fee_bps as f64 / 10_000.0),
+        None => (None, 0.0),
+    };
+
+    // like GenesisMarketplace._settle, the royalty gets at most what the fee leaves
+    let creator_amount = royalty.amount.min(price - platform_amount).max(0.0);
+
+    SaleSplit {
+        seller_amount: price - platform_amount - creator_amount,
+        creator: royalty.receiver,
+        creator_amount,
+        platform,
+        platform_amount,
+    }
+}
+
+// ERC-20 payment tokens accepted by the marketplace, comma separated
+fn is_allowed_currency(currency: Option<&str>) -> bool {
+    is_payment_token(
+        &std::env::var("PAYMENT_TOKENS").unwrap_or_default(),
+        currency,
+    )
+}
+
+// native ETH (None) is always accepted
+fn is_payment_token(payment_tokens: &str, currency: Option<&str>) -> bool {
+    let Some(currency) = currency else {
+        return true;
+    };
+
+    payment_tokens
+        .split(',')
+        .any(|token| !token.trim().is_empty() && token.trim().eq_ignore_ascii_case(currency))
+}
+
+// offers are paid by the marketplace pulling the payment from the offerer once the
+// owner accepts, so they take an accepted ERC-20 token. Native ETH can't be pulled
+// and isn't offered any more, see CHANGELOG.md
+fn offer_token<'a>(payment_tokens: &str, currency: Option<&'a str>) -> Option<&'a str> {
+    currency.filter(|token| is_payment_token(payment_tokens, Some(token)))
+}
+
+// a nonce is used by one order only, the contract rejects it once filled or cancelled
+fn claim_nonce(nonces: &mut Vec<(String, U256)>, maker: &str, nonce: U256) -> bool {
+    if nonces
+        .iter()
+        .any(|(used_by, used)| *used == nonce && used_by.eq_ignore_ascii_case(maker))
+    {
+        return false;
+    }
+
+    nonces.push((maker.to_string(), nonce));
+    true
+}
+
+fn is_valid_expiry(expiry: u64, latest: u64, now: i64) -> bool {
+    expiry as i64 > now && expiry <= latest
+}
+
+fn default_order_expiry() -> u64 {
+    (chrono::Utc::now() + chrono::Duration::minutes(DEFAULT_ORDER_MAXAGE)).timestamp() as u64
+}
+
+// verifies a client signed EIP-712 order, or signs it with the maker's custodial key.
+// `expiry` is when custodial orders expire and the latest a client signed order may
+#[allow(clippy::too_many_arguments)]
+async fn sign_order_for(
+    context: &ActixContext,
+    maker: &User,
+    token_id: usize,
+    price: f64,
+    currency: Option<&str>,
+    side: u8,
+    expiry: u64,
+    signed: Option<&OrderSignatureInfo>,
+) -> Result<SignedOrder> {
+    let domain = context.marketplace.order_domain().await?;
+
+    let (expiry, nonce) = match signed {
+        // the expiry is part of the client signature and can't be clamped
+        Some(signed) => {
+            if !is_valid_expiry(signed.expiry, expiry, chrono::Utc::now().timestamp()) {
+                return Err("Order expiry out of range".into());
+            }
+
+            (signed.expiry, signed.nonce.parse()?)
+        }
+        None => (expiry, blockchain::new_order_nonce()),
+    };
+
+    if context
+        .marketplace
+        .is_nonce_used(&maker.wallet_address, nonce)
+        .await?
+    {
+        return Err("Order nonce already used".into());
+    }
+
+    let order = context
+        .contract
+        .build_order(
+            &maker.wallet_address,
+            token_id,
+            price,
+            currency,
+            expiry,
+            nonce,
+            side,
+        )
+        .await?;
+
+    let signature = match signed {
+        Some(signed) => {
+            if !blockchain::verify_order(&order, &domain, &signed.signature)? {
+                return Err("Invalid order signature".into());
+            }
+
+            signed.signature.clone()
+        }
+        None => {
+            blockchain::sign_with_user_key(maker.get_pk(context), async |maker| {
+                blockchain::sign_order(maker, &order, &domain).await
+            })
+            .await?
+        }
+    };
+
+    if !claim_nonce(
+        &mut ORDER_NONCES.lock().unwrap(),
+        &maker.wallet_address,
+        nonce,
+    ) {
+        return Err("Order nonce already used".into());
+    }
+
+    Ok(SignedOrder {
+        hash: blockchain::order_hash(&order, &domain).to_string(),
+        order,
+        signature,
+    })
+}
+
+async fn sale_split(context: &ActixContext, token_id: usize, price: f64) -> Result<SaleSplit> {
+    let royalty = context.contract.royalty_info(token_id, price).await?;
+
+    // the fee the contract will take, not a local setting that may have drifted from it
+    Ok(split_proceeds(
+        price,
+        royalty,
+        context.marketplace.platform_fee().await?,
+    ))
+}
+
+// records a completed sale and clears the token's listing and offers
+fn record_sale(
+    token_id: usize,
+    seller: &str,
+    buyer: &str,
+    price: f64,
+    currency: Option<&str>,
+    split: SaleSplit,
+) -> SaleInfo {
+    let sale = SaleInfo {
+        token_id,
+        seller: seller.to_string(),
+        buyer: buyer.to_string(),
+        price,
+        currency: currency.map(str::to_string),
+        split,
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+
+    LISTINGS.lock().unwrap().retain(|l| l.token_id != token_id);
+    OFFERS.lock().unwrap().retain(|o| o.token_id != token_id);
+    SALES.lock().unwrap().push(sale.clone());
+
+    sale
+}
+
+// the user acting through whichever of their addresses holds the token
+async fn token_seller(
+    context: &ActixContext,
+    user: &User,
+    token_id: usize,
+) -> std::result::Result<User, HttpResponse> {
+    match context.contract.owner_of_token(token_id).await {
+        Ok(token_owner) => user
+            .with_address(Some(&token_owner))
+            .ok_or(HttpResponse::Unauthorized().finish()),
+        Err(_) => Err(HttpResponse::NotFound().finish()),
+    }
+}
 
 #[actix_web::post("/list")]
 pub async fn list(
@@ -14,9 +229,16 @@ pub async fn list(
     context: web::Data<ActixContext>,
     listing_info: web::Json<ListingInfo>,
 ) -> impl Responder {
+    let Some(seller) = auth_guard
+        .user
+        .with_address(listing_info.address.as_deref())
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
     match context.contract.owner_of_token(listing_info.token_id).await {
         Ok(token_owner) => {
-            if token_owner != auth_guard.user.wallet_address {
+            if token_owner != seller.wallet_address {
                 return HttpResponse::Unauthorized().finish();
             }
         }
@@ -25,6 +247,58 @@ pub async fn list(
         }
     };
 
+    if !is_allowed_currency(listing_info.currency.as_deref()) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let order = sign_order_for(
+        &context,
+        &seller,
+        listing_info.token_id,
+        listing_info.price,
+        listing_info.currency.as_deref(),
+        ORDER_SIDE_SELL,
+        default_order_expiry(),
+        listing_info.signed.as_ref(),
+    )
+    .await;
+
+    let mut listing_info = listing_info.into_inner();
+    match order {
+        Ok(order) => listing_info.order = Some(order),
+        Err(err) => {
+            println!("Failed to sign listing order: {}", err);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+
+    if LISTINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|l| l.token_id == listing_info.token_id)
+    {
+        return HttpResponse::Conflict().finish();
+    }
+
+    let listed = blockchain::sign_with_user_key(seller.get_pk(&context), async |seller| {
+        context
+            .marketplace
+            .list(
+                seller,
+                listing_info.token_id,
+                listing_info.price,
+                listing_info.currency.as_deref(),
+            )
+            .await
+    })
+    .await;
+
+    if let Err(err) = listed {
+        println!("Failed to list token on the marketplace: {}", err);
+        return HttpResponse::BadGateway().finish();
+    }
+
     let mut listings = LISTINGS.lock().unwrap();
 
     if listings
@@ -35,7 +309,7 @@ pub async fn list(
         return HttpResponse::Conflict().finish();
     }
 
-    listings.push(listing_info.0);
+    listings.push(listing_info);
     HttpResponse::Ok().finish()
 }
 
@@ -44,26 +318,146 @@ pub async fn get_listings(
     _auth_guard: AuthenticationGuard,
     _context: web::Data<ActixContext>,
 ) -> impl Responder {
-    let listing: Vec<ListingInfo> = LISTINGS.lock().unwrap().iter().map(|v| v.clone()).collect();
+    let listing: Vec<ListingInfo> = LISTINGS.lock().unwrap().iter().cloned().collect();
 
     HttpResponse::Ok().json(listing)
 }
 
+// places the bid of a custodial user in the marketplace escrow
+async fn escrow_custodial_bid(
+    context: &ActixContext,
+    bidder: &User,
+    token_id: usize,
+    price: f64,
+    currency: Option<&str>,
+) -> std::result::Result<(), HttpResponse> {
+    // ERC-20 bids are pulled into escrow from the bidder's allowance, the bidder
+    // has to cover them before anything is escrowed or recorded
+    if let Some(token) = currency {
+        let approved = blockchain::sign_with_user_key(bidder.get_pk(context), async |signer| {
+            context
+                .marketplace
+                .approve_payment(signer, token, price)
+                .await
+        })
+        .await;
+
+        if let Err(err) = approved {
+            println!("Failed to approve bid payment: {}", err);
+            return Err(HttpResponse::PaymentRequired().finish());
+        }
+
+        match context
+            .marketplace
+            .has_funds(token, &bidder.wallet_address, price)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => return Err(HttpResponse::BadRequest().finish()),
+            Err(err) => {
+                println!("Failed to check bidder funds: {}", err);
+                return Err(HttpResponse::BadGateway().finish());
+            }
+        };
+    }
+
+    let escrowed = blockchain::sign_with_user_key(bidder.get_pk(context), async |signer| {
+        context
+            .marketplace
+            .bid(signer, token_id, price, currency)
+            .await
+    })
+    .await;
+
+    escrowed.map_err(|err| {
+        println!("Failed to place bid on the marketplace: {}", err);
+        HttpResponse::PaymentRequired().finish()
+    })
+}
+
 #[actix_web::post("/bid/{token_id}")]
 pub async fn bid(
-    auth_guard: AuthenticationGuard,
-    _context: web::Data<ActixContext>,
+    auth_guard: StepUpGuard,
+    context: web::Data<ActixContext>,
     mut input: web::Json<BidInfo>,
     token_id: web::Path<usize>,
 ) -> impl Responder {
     let token_id = token_id.into_inner();
 
+    let currency = LISTINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|l| l.token_id == token_id)
+        .map(|l| l.currency.clone());
+
+    let Some(currency) = currency else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let order = sign_order_for(
+        &context,
+        &auth_guard.user,
+        token_id,
+        input.price,
+        currency.as_deref(),
+        ORDER_SIDE_BUY,
+        default_order_expiry(),
+        input.signed.as_ref(),
+    )
+    .await;
+
+    match order {
+        Ok(order) => input.order = Some(order),
+        Err(err) => {
+            println!("Failed to sign bid order: {}", err);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+
+    // external wallets escrow the bid themselves before posting its signed order
+    let escrowed = match input.signed {
+        Some(_) => match context
+            .marketplace
+            .has_escrowed(
+                token_id,
+                &auth_guard.user.wallet_address,
+                input.price,
+                currency.as_deref(),
+            )
+            .await
+        {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(HttpResponse::PaymentRequired().finish()),
+            Err(err) => {
+                println!("Failed to look up bid on the marketplace: {}", err);
+                Err(HttpResponse::BadGateway().finish())
+            }
+        },
+        None => {
+            escrow_custodial_bid(
+                &context,
+                &auth_guard.user,
+                token_id,
+                input.price,
+                currency.as_deref(),
+            )
+            .await
+        }
+    };
+
+    if let Err(response) = escrowed {
+        return response;
+    }
+
     let mut listings = LISTINGS.lock().unwrap();
     let listing = listings.iter_mut().find(|l| l.token_id == token_id);
 
     match listing {
         Some(listing_info) => {
+            // a new bid refunds the bidder's previous one on-chain
             input.bidder = auth_guard.user.wallet_address;
+            listing_info.bids.retain(|b| b.bidder != input.bidder);
             listing_info.bids.push(input.into_inner());
             HttpResponse::Ok().finish()
         }
@@ -77,17 +471,61 @@ pub async fn update_listing(
     context: web::Data<ActixContext>,
     listing_info: web::Json<ListingInfo>,
 ) -> impl Responder {
-    match context.contract.owner_of_token(listing_info.token_id).await {
-        Ok(token_owner) => {
-            if token_owner != auth_guard.user.wallet_address {
-                return HttpResponse::Unauthorized().finish();
-            }
-        }
-        Err(_) => {
-            return HttpResponse::NotFound().finish();
+    let seller = match token_seller(&context, &auth_guard.user, listing_info.token_id).await {
+        Ok(seller) => seller,
+        Err(response) => return response,
+    };
+
+    let listed = LISTINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|l| l.token_id == listing_info.token_id)
+        .map(|l| (l.currency.clone(), l.order.as_ref().map(|o| o.order.nonce)));
+
+    let Some((currency, old_nonce)) = listed else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    // the previous order signature no longer matches the new price
+    let order = match sign_order_for(
+        &context,
+        &seller,
+        listing_info.token_id,
+        listing_info.price,
+        currency.as_deref(),
+        ORDER_SIDE_SELL,
+        default_order_expiry(),
+        listing_info.signed.as_ref(),
+    )
+    .await
+    {
+        Ok(order) => order,
+        Err(err) => {
+            println!("Failed to sign listing order: {}", err);
+            return HttpResponse::BadRequest().finish();
         }
     };
 
+    let updated = blockchain::sign_with_user_key(seller.get_pk(&context), async |seller| {
+        context
+            .marketplace
+            .update_price(seller, listing_info.token_id, listing_info.price)
+            .await?;
+
+        // the old order could otherwise still be filled at the old price
+        match old_nonce {
+            Some(nonce) => context.marketplace.cancel_order(seller, nonce).await,
+            None => Ok(()),
+        }
+    })
+    .await;
+
+    if let Err(err) = updated {
+        println!("Failed to update listing on the marketplace: {}", err);
+        return HttpResponse::BadGateway().finish();
+    }
+
     let mut listings = LISTINGS.lock().unwrap();
     let listing = listings
         .iter_mut()
@@ -96,6 +534,7 @@ pub async fn update_listing(
     match listing {
         Some(listing) => {
             listing.price = listing_info.price;
+            listing.order = Some(order);
             HttpResponse::Ok().finish()
         }
         None => HttpResponse::NotFound().finish(),
@@ -110,17 +549,39 @@ pub async fn cancel_listing(
 ) -> impl Responder {
     let token_id = token_id.into_inner();
 
-    match context.contract.owner_of_token(token_id).await {
-        Ok(token_owner) => {
-            if token_owner != auth_guard.user.wallet_address {
-                return HttpResponse::Unauthorized().finish();
-            }
-        }
-        Err(_) => {
-            return HttpResponse::NotFound().finish();
-        }
+    let seller = match token_seller(&context, &auth_guard.user, token_id).await {
+        Ok(seller) => seller,
+        Err(response) => return response,
+    };
+
+    let old_nonce = LISTINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|l| l.token_id == token_id)
+        .map(|l| l.order.as_ref().map(|o| o.order.nonce));
+
+    let Some(old_nonce) = old_nonce else {
+        return HttpResponse::NotFound().finish();
     };
 
+    // escrowed bids stay withdrawable by their bidders after cancelling
+    let cancelled = blockchain::sign_with_user_key(seller.get_pk(&context), async |seller| {
+        context.marketplace.cancel(seller, token_id).await?;
+
+        // the signed listing order could otherwise still be filled
+        match old_nonce {
+            Some(nonce) => context.marketplace.cancel_order(seller, nonce).await,
+            None => Ok(()),
+        }
+    })
+    .await;
+
+    if let Err(err) = cancelled {
+        println!("Failed to cancel listing on the marketplace: {}", err);
+        return HttpResponse::BadGateway().finish();
+    }
+
     let mut listings = LISTINGS.lock().unwrap();
     let removed = listings.iter().position(|l| l.token_id == token_id);
 
@@ -132,3 +593,607 @@ pub async fn cancel_listing(
         None => HttpResponse::NotFound().finish(),
     }
 }
+
+#[actix_web::post("/acceptBid/{token_id}/{bid_index}")]
+pub async fn accept_bid(
+    auth_guard: StepUpGuard,
+    context: web::Data<ActixContext>,
+    path: web::Path<(usize, usize)>,
+) -> impl Responder {
+    let (token_id, bid_index) = path.into_inner();
+
+    let seller = match token_seller(&context, &auth_guard.user, token_id).await {
+        Ok(seller) => seller,
+        Err(response) => return response,
+    };
+
+    let accepted_bid = LISTINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|l| l.token_id == token_id)
+        .and_then(|l| Some((l.bids.get(bid_index).cloned()?, l.currency.clone())));
+
+    let Some((accepted_bid, currency)) = accepted_bid else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let split = match sale_split(&context, token_id, accepted_bid.price).await {
+        Ok(split) => split,
+        Err(err) => {
+            println!("Failed to get royalty info: {}", err);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+
+    let accepted = blockchain::sign_with_user_key(seller.get_pk(&context), async |seller| {
+        context
+            .marketplace
+            .accept_bid(seller, token_id, &accepted_bid.bidder)
+            .await
+    })
+    .await;
+
+    match accepted {
+        Ok(_) => HttpResponse::Ok().json(record_sale(
+            token_id,
+            &seller.wallet_address,
+            &accepted_bid.bidder,
+            accepted_bid.price,
+            currency.as_deref(),
+            split,
+        )),
+        Err(err) => {
+            println!("Failed to accept bid on the marketplace: {}", err);
+            HttpResponse::BadGateway().finish()
+        }
+    }
+}
+
+// the listing is filled on-chain, the token left its seller for the buyer's address
+async fn record_wallet_purchase(
+    context: &ActixContext,
+    buyer: &User,
+    token_id: usize,
+    token_owner: &str,
+    seller: Option<String>,
+    price: f64,
+    currency: Option<String>,
+) -> HttpResponse {
+    let Some(seller) = seller else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if buyer.with_address(Some(&seller)).is_some() {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    if buyer.with_address(Some(token_owner)).is_none() {
+        return HttpResponse::PaymentRequired().finish();
+    }
+
+    // a plain transfer leaves the listing in place, a sale removes it
+    match context.marketplace.is_listed(token_id).await {
+        Ok(false) => {}
+        Ok(true) => return HttpResponse::PaymentRequired().finish(),
+        Err(err) => {
+            println!("Failed to look up listing on the marketplace: {}", err);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+
+    let split = match sale_split(context, token_id, price).await {
+        Ok(split) => split,
+        Err(err) => {
+            println!("Failed to get royalty info: {}", err);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+
+    HttpResponse::Ok().json(record_sale(
+        token_id,
+        &seller,
+        token_owner,
+        price,
+        currency.as_deref(),
+        split,
+    ))
+}
+
+#[actix_web::post("/buy/{token_id}")]
+pub async fn buy(
+    auth_guard: StepUpGuard,
+    context: web::Data<ActixContext>,
+    token_id: web::Path<usize>,
+) -> impl Responder {
+    let token_id = token_id.into_inner();
+
+    let listing = LISTINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|l| l.token_id == token_id)
+        .map(|l| {
+            (
+                l.price,
+                l.currency.clone(),
+                l.order.as_ref().map(|o| o.order.maker.to_string()),
+            )
+        });
+
+    let Some((price, currency, listed_by)) = listing else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let token_owner = match context.contract.owner_of_token(token_id).await {
+        Ok(token_owner) => token_owner,
+        Err(_) => {
+            return HttpResponse::NotFound().finish();
+        }
+    };
+
+    // external wallets buy from their own wallet and report the purchase here
+    if auth_guard.user.key_shares.is_none() {
+        return record_wallet_purchase(
+            &context,
+            &auth_guard.user,
+            token_id,
+            &token_owner,
+            listed_by,
+            price,
+            currency,
+        )
+        .await;
+    }
+
+    if auth_guard.user.with_address(Some(&token_owner)).is_some() {
+        return HttpResponse::BadRequest().finish();
+    }
+    let seller = token_owner;
+
+    let split = match sale_split(&context, token_id, price).await {
+        Ok(split) => split,
+        Err(err) => {
+            println!("Failed to get royalty info: {}", err);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+
+    let bought = blockchain::sign_with_user_key(auth_guard.user.get_pk(&context), async |buyer| {
+        context.marketplace.buy(buyer, token_id).await
+    })
+    .await;
+
+    match bought {
+        Ok(_) => HttpResponse::Ok().json(record_sale(
+            token_id,
+            &seller,
+            &auth_guard.user.wallet_address,
+            price,
+            currency.as_deref(),
+            split,
+        )),
+        Err(err) => {
+            println!("Failed to buy token on the marketplace: {}", err);
+            HttpResponse::PaymentRequired().finish()
+        }
+    }
+}
+
+#[actix_web::delete("/bid/{token_id}")]
+pub async fn withdraw_bid(
+    auth_guard: AuthenticationGuard,
+    context: web::Data<ActixContext>,
+    token_id: web::Path<usize>,
+) -> impl Responder {
+    let token_id = token_id.into_inner();
+
+    match context
+        .marketplace
+        .has_bid(token_id, &auth_guard.user.wallet_address)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().finish(),
+        Err(err) => {
+            println!("Failed to look up bid on the marketplace: {}", err);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+
+    let withdrawn =
+        blockchain::sign_with_user_key(auth_guard.user.get_pk(&context), async |bidder| {
+            context.marketplace.withdraw_bid(bidder, token_id).await
+        })
+        .await;
+
+    if let Err(err) = withdrawn {
+        println!("Failed to withdraw bid on the marketplace: {}", err);
+        return HttpResponse::BadGateway().finish();
+    }
+
+    if let Some(listing) = LISTINGS
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|l| l.token_id == token_id)
+    {
+        listing
+            .bids
+            .retain(|b| b.bidder != auth_guard.user.wallet_address);
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[actix_web::post("/offer/{token_id}")]
+pub async fn make_offer(
+    auth_guard: StepUpGuard,
+    context: web::Data<ActixContext>,
+    input: web::Json<OfferInfo>,
+    token_id: web::Path<usize>,
+) -> impl Responder {
+    let token_id = token_id.into_inner();
+
+    match context.contract.owner_of_token(token_id).await {
+        Ok(token_owner) => {
+            if auth_guard.user.with_address(Some(&token_owner)).is_some() {
+                return HttpResponse::BadRequest().finish();
+            }
+        }
+        Err(_) => {
+            return HttpResponse::NotFound().finish();
+        }
+    };
+
+    let Some(token) = offer_token(
+        &std::env::var("PAYMENT_TOKENS").unwrap_or_default(),
+        input.currency.as_deref(),
+    ) else {
+        return HttpResponse::BadRequest().finish();
+    };
+
+    // custodial offerers approve the marketplace here, external wallets beforehand
+    if input.signed.is_none() {
+        let approved =
+            blockchain::sign_with_user_key(auth_guard.user.get_pk(&context), async |offerer| {
+                context
+                    .marketplace
+                    .approve_payment(offerer, token, input.price)
+                    .await
+            })
+            .await;
+
+        if let Err(err) = approved {
+            println!("Failed to approve offer payment: {}", err);
+            return HttpResponse::PaymentRequired().finish();
+        }
+    }
+
+    let offerer = &auth_guard.user.wallet_address;
+    match context
+        .marketplace
+        .has_funds(token, offerer, input.price)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::PaymentRequired().finish();
+        }
+        Err(err) => {
+            println!("Failed to check offerer funds: {}", err);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+
+    let max_age = std::env::var("OFFER_MAXAGE")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_ORDER_MAXAGE);
+
+    let now = chrono::Utc::now();
+    let expires_in = match &input.signed {
+        Some(_) => max_age,
+        None => input.expires_in.unwrap_or(max_age).clamp(1, max_age),
+    };
+
+    let order = match sign_order_for(
+        &context,
+        &auth_guard.user,
+        token_id,
+        input.price,
+        input.currency.as_deref(),
+        ORDER_SIDE_BUY,
+        (now + chrono::Duration::minutes(expires_in)).timestamp() as u64,
+        input.signed.as_ref(),
+    )
+    .await
+    {
+        Ok(order) => order,
+        Err(err) => {
+            println!("Failed to sign offer order: {}", err);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    let expires_at = order.order.expiry.saturating_to::<i64>();
+
+    let offer = Offer {
+        id: uuid::Uuid::new_v4().to_string(),
+        token_id,
+        offerer: auth_guard.user.wallet_address.clone(),
+        price: input.price,
+        currency: input.currency.clone(),
+        expires_at,
+        order,
+    };
+
+    OFFERS.lock().unwrap().push(offer.clone());
+    HttpResponse::Ok().json(offer)
+}
+
+#[actix_web::get("/offers/{token_id}")]
+pub async fn get_offers(
+    auth_guard: AuthenticationGuard,
+    context: web::Data<ActixContext>,
+    token_id: web::Path<usize>,
+) -> impl Responder {
+    let token_id = token_id.into_inner();
+
+    if let Err(response) = token_seller(&context, &auth_guard.user, token_id).await {
+        return response;
+    }
+
+    let mut offers = OFFERS.lock().unwrap();
+    prune_expired_offers(&mut offers, chrono::Utc::now().timestamp());
+
+    let offers: Vec<Offer> = offers
+        .iter()
+        .filter(|o| o.token_id == token_id)
+        .cloned()
+        .collect();
+
+    HttpResponse::Ok().json(offers)
+}
+
+#[actix_web::post("/acceptOffer/{offer_id}")]
+pub async fn accept_offer(
+    auth_guard: StepUpGuard,
+    context: web::Data<ActixContext>,
+    offer_id: web::Path<String>,
+) -> impl Responder {
+    let offer = {
+        let mut offers = OFFERS.lock().unwrap();
+        prune_expired_offers(&mut offers, chrono::Utc::now().timestamp());
+        offers.iter().find(|o| o.id == *offer_id).cloned()
+    };
+
+    let Some(offer) = offer else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let seller = match token_seller(&context, &auth_guard.user, offer.token_id).await {
+        Ok(seller) => seller,
+        Err(response) => return response,
+    };
+
+    let split = match sale_split(&context, offer.token_id, offer.price).await {
+        Ok(split) => split,
+        Err(err) => {
+            println!("Failed to get royalty info: {}", err);
+            return HttpResponse::BadGateway().finish();
+        }
+    };
+
+    // the marketplace pulls the payment and transfers the token in one transaction
+    let filled = blockchain::sign_with_user_key(seller.get_pk(&context), async |seller| {
+        context.marketplace.fill_order(seller, &offer.order).await
+    })
+    .await;
+
+    match filled {
+        Ok(()) => HttpResponse::Ok().json(record_sale(
+            offer.token_id,
+            &seller.wallet_address,
+            &offer.offerer,
+            offer.price,
+            offer.currency.as_deref(),
+            split,
+        )),
+        Err(err) => {
+            println!("Failed to fill offer on the marketplace: {}", err);
+            HttpResponse::PaymentRequired().finish()
+        }
+    }
+}
+
+#[actix_web::delete("/rejectOffer/{offer_id}")]
+pub async fn reject_offer(
+    auth_guard: AuthenticationGuard,
+    context: web::Data<ActixContext>,
+    offer_id: web::Path<String>,
+) -> impl Responder {
+    let token_id = OFFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|o| o.id == *offer_id)
+        .map(|o| o.token_id);
+
+    let Some(token_id) = token_id else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if let Err(response) = token_seller(&context, &auth_guard.user, token_id).await {
+        return response;
+    }
+
+    OFFERS.lock().unwrap().retain(|o| o.id != *offer_id);
+    HttpResponse::Ok().finish()
+}
+
+#[actix_web::get("/orders/domain")]
+pub async fn order_domain(context: web::Data<ActixContext>) -> impl Responder {
+    match context.marketplace.order_domain().await {
+        Ok(domain) => HttpResponse::Ok().json(serde_json::json!({
+            "name": domain.name,
+            "version": domain.version,
+            "chainId": domain.chain_id,
+            "verifyingContract": domain.verifying_contract,
+        })),
+        Err(err) => {
+            println!("Failed to build order domain: {}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[actix_web::get("/sales")]
+pub async fn get_sales(
+    _auth_guard: AuthenticationGuard,
+    _context: web::Data<ActixContext>,
+) -> impl Responder {
+    let sales: Vec<SaleInfo> = SALES.lock().unwrap().iter().cloned().collect();
+
+    HttpResponse::Ok().json(sales)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(id: &str, expires_at: i64) -> Offer {
+        Offer {
+            id: id.to_string(),
+            token_id: 1,
+            offerer: String::from("0x0"),
+            price: 1.0,
+            currency: None,
+            expires_at,
+            order: SignedOrder {
+                order: blockchain::Order::default(),
+                hash: String::new(),
+                signature: String::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_prune_expired_offers() {
+        let mut offers = vec![
+            offer("expired", 100),
+            offer("edge", 200),
+            offer("live", 300),
+        ];
+        prune_expired_offers(&mut offers, 200);
+
+        let ids: Vec<&str> = offers.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(ids, vec!["live"]);
+    }
+
+    #[test]
+    fn test_order_nonces_are_claimed_once() {
+        let mut nonces = Vec::new();
+
+        assert!(claim_nonce(&mut nonces, "0xAbC", U256::from(1)));
+        assert!(!claim_nonce(&mut nonces, "0xabc", U256::from(1)));
+        // nonces are per maker
+        assert!(claim_nonce(&mut nonces, "0xdef", U256::from(1)));
+        assert!(claim_nonce(&mut nonces, "0xabc", U256::from(2)));
+    }
+
+    #[test]
+    fn test_signed_order_expiry() {
+        let now = 1_700_000_000;
+        let latest = (now + DEFAULT_ORDER_MAXAGE * 60) as u64;
+
+        assert!(is_valid_expiry(now as u64 + 60, latest, now));
+        assert!(is_valid_expiry(latest, latest, now));
+        assert!(!is_valid_expiry(now as u64, latest, now));
+        assert!(!is_valid_expiry(latest + 1, latest, now));
+        // e.g. a listing signed to never expire
+        assert!(!is_valid_expiry(u64::MAX, latest, now));
+    }
+
+    #[test]
+    fn test_payment_tokens() {
+        let tokens = "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238, 0xdAC17F958D2ee523a2206206994597C13D831ec7";
+
+        assert!(is_payment_token(tokens, None));
+        assert!(is_payment_token(
+            tokens,
+            Some("0x1c7d4b196cb0c7b01d743fbc6116a902379c7238")
+        ));
+        assert!(is_payment_token(
+            tokens,
+            Some("0xdAC17F958D2ee523a2206206994597C13D831ec7")
+        ));
+        assert!(!is_payment_token(
+            tokens,
+            Some("0x0000000000000000000000000000000000000001")
+        ));
+        // no tokens configured, only native ETH
+        assert!(!is_payment_token("", Some("")));
+        assert!(!is_payment_token(
+            "",
+            Some("0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238")
+        ));
+    }
+
+    #[test]
+    fn test_offers_take_no_native_eth() {
+        let tokens = "0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238";
+
+        assert_eq!(offer_token(tokens, None), None);
+        assert_eq!(offer_token("", None), None);
+        assert_eq!(
+            offer_token(tokens, Some("0x1c7d4b196cb0c7b01d743fbc6116a902379c7238")),
+            Some("0x1c7d4b196cb0c7b01d743fbc6116a902379c7238")
+        );
+        assert_eq!(
+            offer_token(tokens, Some("0xdAC17F958D2ee523a2206206994597C13D831ec7")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_split_proceeds() {
+        let royalty = RoyaltyInfo {
+            receiver: String::from("0xcreator"),
+            amount: 5.0,
+        };
+
+        let split = split_proceeds(100.0, royalty, Some((String::from("0xplatform"), 250)));
+
+        assert_eq!(split.creator_amount, 5.0);
+        assert_eq!(split.platform_amount, 2.5);
+        assert_eq!(split.seller_amount, 92.5);
+        assert_eq!(split.platform.as_deref(), Some("0xplatform"));
+    }
+
+    #[test]
+    fn test_split_proceeds_clamps_royalty() {
+        // a royalty above the price, _settle pays the fee, the rest as royalty and nothing to the seller
+        let royalty = RoyaltyInfo {
+            receiver: String::from("0xcreator"),
+            amount: 120.0,
+        };
+
+        let split = split_proceeds(
+            100.0,
+            royalty.clone(),
+            Some((String::from("0xplatform"), 250)),
+        );
+
+        assert_eq!(split.platform_amount, 2.5);
+        assert_eq!(split.creator_amount, 97.5);
+        assert_eq!(split.seller_amount, 0.0);
+        assert_eq!(
+            split.seller_amount + split.creator_amount + split.platform_amount,
+            100.0
+        );
+
+        let split = split_proceeds(100.0, royalty, None);
+        assert_eq!(split.creator_amount, 100.0);
+        assert_eq!(split.seller_amount, 0.0);
+    }
+}
@@ -1,14 +1,38 @@
 use super::Result;
-use crate::{blockchain::GTKContract, secret_storage::HcpClient};
+use crate::{
+    audit_log,
+    blockchain::{self, GTKContract, MarketplaceContract},
+    envelope::Keyring,
+    key_shares::{self, ShareLocation, ShareScheme},
+    owner_key,
+    recovery_service::RecoveryServiceClient,
+    secret_storage::HcpClient,
+};
 use actix_web::{
-    App, HttpResponse, HttpServer, Responder, http::StatusCode, middleware::Logger, web,
+    App, HttpResponse, HttpServer, Responder,
+    http::StatusCode,
+    middleware::{self, Logger},
+    web,
 };
+use alloy::primitives::Address;
 use authentication::AuthenticationGuard;
+use roles::{Minter, RequireRole};
 use std::sync::Mutex;
+use two_factor::StepUpGuard;
 
+mod addresses;
+mod api_keys;
+mod audit;
 mod authentication;
 mod authorization;
+mod key_export;
+mod key_management;
 mod marketplace;
+mod oidc;
+mod roles;
+mod sessions;
+mod siwe;
+mod two_factor;
 mod types;
 
 use types::*;
@@ -29,24 +53,52 @@ async fn index(auth_guard: AuthenticationGuard, context: web::Data<ActixContext>
 // Todo : get gass fee
 #[actix_web::post("/mint")]
 async fn mint(
-    auth_guard: AuthenticationGuard,
+    auth_guard: RequireRole<Minter>,
     context: web::Data<ActixContext>,
     input: web::Json<MintInfo>,
 ) -> impl Responder {
-    let user = auth_guard.user;
+    let Some(user) = auth_guard.user.with_address(input.address.as_deref()) else {
+        return HttpResponse::NotFound().finish();
+    };
 
     println!(
         "minting token id: {} to: {}",
         input.token_id, user.wallet_address
     );
 
-    context
+    if input.royalty_bps.is_some_and(|bps| bps > 10_000) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    // ERC-2981 rejects the zero address, and anything else would mint with royalties nobody gets
+    let royalty_receiver = match input.royalty_receiver.as_deref() {
+        Some(receiver) => match receiver.parse::<Address>() {
+            Ok(receiver) if receiver != Address::ZERO => receiver.to_string(),
+            _ => return HttpResponse::BadRequest().finish(),
+        },
+        None => user.wallet_address.clone(),
+    };
+
+    let minted = context
         .contract
-        .mint_nft(&user.wallet_address, input.token_id, &input.token_uri)
-        .await
-        .unwrap();
+        .mint_nft(
+            &user.wallet_address,
+            input.token_id,
+            &input.token_uri,
+            input
+                .royalty_bps
+                .map(|bps| (royalty_receiver.as_str(), bps)),
+        )
+        .await;
 
-    HttpResponse::new(StatusCode::OK)
+    // the input is checked above, what's left is the chain refusing or failing the mint
+    match minted {
+        Ok(()) => HttpResponse::new(StatusCode::OK),
+        Err(err) => {
+            println!("Failed to mint token {}: {}", input.token_id, err);
+            HttpResponse::BadGateway().finish()
+        }
+    }
 }
 
 #[actix_web::get("/owner/{token_id}")]
@@ -61,13 +113,17 @@ async fn owner(
 
 #[actix_web::put("/transfer")]
 async fn transfer_nft(
-    auth_guard: AuthenticationGuard,
+    auth_guard: StepUpGuard,
     context: web::Data<ActixContext>,
     input: web::Json<TransferInfo>,
 ) -> impl Responder {
+    let Some(user) = auth_guard.user.with_address(input.address.as_deref()) else {
+        return HttpResponse::NotFound().finish();
+    };
+
     match context.contract.owner_of_token(input.token_id).await {
         Ok(token_owner) => {
-            if token_owner != auth_guard.user.wallet_address {
+            if token_owner != user.wallet_address {
                 return HttpResponse::Unauthorized().finish();
             }
         }
@@ -76,19 +132,18 @@ async fn transfer_nft(
         }
     };
 
-    match auth_guard.user.get_pk(&context.secret_manager).await {
-        Ok(owner_pk) => {
-            // Todo : handle errors
-            context
-                .contract
-                .transfer_nft(&owner_pk, &input.to, input.token_id)
-                .await
-                .unwrap();
+    let transferred = blockchain::sign_with_user_key(user.get_pk(&context), async |signer| {
+        context
+            .contract
+            .transfer_nft(signer, &input.to, input.token_id)
+            .await
+    })
+    .await;
 
-            HttpResponse::new(StatusCode::OK)
-        }
+    match transferred {
+        Ok(()) => HttpResponse::new(StatusCode::OK),
         Err(err) => {
-            println!("Failed to recover secret: {}", err);
+            println!("Failed to transfer token: {}", err);
             HttpResponse::InternalServerError().finish()
         }
     }
@@ -110,26 +165,54 @@ async fn metadata(
 }
 
 pub async fn start_server() -> Result<()> {
-    let contract = GTKContract::new().await.unwrap();
+    // opened first, so that loading the keys below is audited too
+    audit_log::open_from_env()?;
 
     let client = reqwest::Client::new();
-    let client_id = std::env::var("HCP_CLIENT_ID").unwrap();
-    let client_secret = std::env::var("HCP_CLIENT_SECRET").unwrap();
-    let org_id = std::env::var("HCP_ORG_ID").unwrap();
-    let proj_id = std::env::var("HCP_PROJ_ID").unwrap();
-    let app_name = std::env::var("HCP_APP_NAME").unwrap();
+    let secret_manager = HcpClient::from_env(&client).await?;
 
-    let secret_manager =
-        HcpClient::new(&client, client_id, client_secret, org_id, proj_id, app_name).await?;
+    // refuse to start without a key encryption key for the user shares
+    let keyring = Keyring::load(&secret_manager).await?;
+
+    let share_scheme = ShareScheme::from_env()?;
+    let share_naming_key = key_shares::naming_key_from_env()?;
+    let recovery_service = RecoveryServiceClient::from_env(&client);
+    if share_scheme.uses(ShareLocation::RecoveryService) && recovery_service.is_none() {
+        return Err(
+            "key share scheme needs RECOVERY_SERVICE_URL and RECOVERY_SERVICE_TOKEN".into(),
+        );
+    }
+
+    // fails closed, the owner key only comes from a protected source
+    let owner_signer = owner_key::load_owner_signer(
+        &client,
+        &secret_manager,
+        recovery_service.as_ref(),
+        &share_naming_key,
+    )
+    .await?;
+    println!("contract owner signs as {}", owner_signer.address());
+    let contract = GTKContract::new(owner_signer).await?;
+    let marketplace = MarketplaceContract::new(&contract)?;
 
     let context = ActixContext {
         contract,
+        marketplace,
         http_client: client,
         secret_manager,
+        keyring,
+        share_scheme,
+        share_naming_key,
+        recovery_service,
     };
 
+    if let Some(interval) = key_management::reshare_interval() {
+        actix_web::rt::spawn(key_management::run_reshare_job(context.clone(), interval));
+    }
+
     Ok(HttpServer::new(move || {
         App::new()
+            .wrap(middleware::from_fn(audit::request_scope))
             .wrap(Logger::default())
             .app_data(web::Data::new(context.clone()))
             .service(index)
@@ -142,7 +225,48 @@ pub async fn start_server() -> Result<()> {
             .service(marketplace::bid)
             .service(marketplace::update_listing)
             .service(marketplace::cancel_listing)
+            .service(marketplace::accept_bid)
+            .service(marketplace::buy)
+            .service(marketplace::withdraw_bid)
+            .service(marketplace::make_offer)
+            .service(marketplace::get_offers)
+            .service(marketplace::accept_offer)
+            .service(marketplace::reject_offer)
+            .service(marketplace::get_sales)
+            .service(marketplace::order_domain)
+            .service(authorization::oauth_start)
+            .service(authorization::oauth_callback)
             .service(authorization::google_oauth_handler)
+            .service(authorization::get_identities)
+            .service(siwe::siwe_nonce)
+            .service(siwe::siwe_verify)
+            .service(roles::get_roles)
+            .service(roles::grant_role)
+            .service(roles::revoke_role)
+            .service(api_keys::create_key)
+            .service(api_keys::get_keys)
+            .service(api_keys::delete_key)
+            .service(key_management::rewrap_shares)
+            .service(key_management::reshare_shares)
+            .service(key_management::reshare_user_shares)
+            .service(key_management::migrate_names)
+            .service(key_management::get_key_shares)
+            .service(addresses::get_addresses)
+            .service(addresses::create_address)
+            .service(addresses::label_address)
+            .service(key_export::export_recovery_share)
+            .service(key_export::verify_recovery_share)
+            .service(key_export::export_keystore)
+            .service(audit::get_audit_events)
+            .service(two_factor::enrol_totp)
+            .service(two_factor::confirm_totp)
+            .service(two_factor::verify_totp)
+            .service(two_factor::disable_totp)
+            .service(sessions::refresh)
+            .service(sessions::logout)
+            .service(sessions::get_sessions)
+            .service(sessions::delete_session)
+            .service(sessions::delete_other_sessions)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
@@ -0,0 +1,822 @@
+use super::Result;
+use jsonwebtoken::{self as jwt, Algorithm, DecodingKey, Validation, jwk::JwkSet};
+use reqwest::{Client, Url, header::ACCEPT};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{str::FromStr, sync::Mutex};
+
+const JWKS_MAXAGE: i64 = 60; // minutes
+const DISCOVERY_MAXAGE: i64 = 60; // minutes
+const GOOGLE_ISSUER: &str = "https://accounts.google.com";
+
+// endpoints of a provider, discovered from the issuer or configured for plain OAuth2
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProviderMetadata {
+    pub issuer: Option<String>,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+}
+
+// a login provider configured from OIDC_<NAME>_* variables
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub name: String,
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+    scopes: String,
+    issuer: Option<String>, // None for plain OAuth2 providers (e.g. GitHub)
+    jwks_url: Option<String>,
+    authorization_endpoint: Option<String>,
+    token_endpoint: Option<String>,
+    userinfo_endpoint: Option<String>,
+    emails_endpoint: Option<String>, // e.g. GitHub's /user/emails, for users without a public email
+    subject_claim: String,
+    trust_email: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub id_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProviderIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+// Todo - move to a shared cache
+static DISCOVERY_CACHE: Mutex<Vec<(String, i64, ProviderMetadata)>> = Mutex::new(Vec::new()); // (issuer, fetched_at, metadata)
+
+// the original GOOGLE_* variables keep working for the google provider
+fn provider_var(name: &str, key: &str) -> Option<String> {
+    let legacy = match key {
+        "CLIENT_ID" => "GOOGLE_OAUTH_CLIENT_ID",
+        "CLIENT_SECRET" => "GOOGLE_OAUTH_CLIENT_SECRET",
+        "REDIRECT_URL" => "GOOGLE_OAUTH_REDIRECT_URL",
+        "JWKS_URL" => "GOOGLE_JWKS_URL",
+        _ => "",
+    };
+
+    std::env::var(format!("OIDC_{}_{}", name.to_uppercase(), key))
+        .ok()
+        .or_else(|| {
+            (name == "google" && !legacy.is_empty())
+                .then(|| std::env::var(legacy).ok())
+                .flatten()
+        })
+}
+
+// comma separated OIDC_PROVIDERS, google only by default
+pub fn enabled_providers() -> Vec<String> {
+    std::env::var("OIDC_PROVIDERS")
+        .unwrap_or("google".to_string())
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+impl Provider {
+    pub fn from_env(name: &str) -> Result<Self> {
+        if !enabled_providers().iter().any(|enabled| enabled == name) {
+            return Err(format!("unknown provider {name}").into());
+        }
+
+        let var = |key: &str| provider_var(name, key);
+        let required =
+            |key: &str| var(key).ok_or(format!("OIDC_{}_{key} not set", name.to_uppercase()));
+
+        let issuer = var("ISSUER").or((name == "google").then(|| GOOGLE_ISSUER.to_string()));
+        let scopes = var("SCOPES").unwrap_or(match issuer {
+            Some(_) => "openid email profile".to_string(),
+            None => "email".to_string(),
+        });
+
+        Ok(Self {
+            name: name.to_string(),
+            client_id: required("CLIENT_ID")?,
+            client_secret: required("CLIENT_SECRET")?,
+            redirect_url: required("REDIRECT_URL")?,
+            scopes,
+            issuer,
+            jwks_url: var("JWKS_URL"),
+            authorization_endpoint: var("AUTHORIZATION_ENDPOINT"),
+            token_endpoint: var("TOKEN_ENDPOINT"),
+            userinfo_endpoint: var("USERINFO_ENDPOINT"),
+            emails_endpoint: var("EMAILS_ENDPOINT"),
+            subject_claim: var("SUBJECT_CLAIM").unwrap_or("sub".to_string()),
+            trust_email: var("TRUST_EMAIL").is_some_and(|v| v == "true"),
+        })
+    }
+
+    // discovery results are cached, explicitly configured endpoints take precedence
+    pub async fn metadata(&self, client: &Client) -> Result<ProviderMetadata> {
+        let mut metadata = match &self.issuer {
+            Some(issuer) => discover(client, issuer).await?,
+            None => ProviderMetadata {
+                issuer: None,
+                authorization_endpoint: self
+                    .authorization_endpoint
+                    .clone()
+                    .ok_or("authorization endpoint not configured")?,
+                token_endpoint: self
+                    .token_endpoint
+                    .clone()
+                    .ok_or("token endpoint not configured")?,
+                jwks_uri: None,
+                userinfo_endpoint: None,
+            },
+        };
+
+        if let Some(endpoint) = &self.authorization_endpoint {
+            metadata.authorization_endpoint = endpoint.clone();
+        }
+        if let Some(endpoint) = &self.token_endpoint {
+            metadata.token_endpoint = endpoint.clone();
+        }
+        if let Some(jwks_url) = &self.jwks_url {
+            metadata.jwks_uri = Some(jwks_url.clone());
+        }
+        if let Some(endpoint) = &self.userinfo_endpoint {
+            metadata.userinfo_endpoint = Some(endpoint.clone());
+        }
+
+        Ok(metadata)
+    }
+
+    pub fn authorization_url(
+        &self,
+        metadata: &ProviderMetadata,
+        state: &str,
+        code_challenge: &str,
+        nonce: &str,
+    ) -> Result<Url> {
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_url)
+            .append_pair("response_type", "code")
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url)
+    }
+
+    pub async fn request_token(
+        &self,
+        client: &Client,
+        metadata: &ProviderMetadata,
+        authorization_code: &str,
+        code_verifier: &str,
+    ) -> Result<TokenResponse> {
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("redirect_uri", self.redirect_url.as_str()),
+            ("client_id", self.client_id.as_str()),
+            ("code", authorization_code),
+            ("client_secret", self.client_secret.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+
+        // GitHub answers form encoded unless JSON is asked for
+        let response = client
+            .post(&metadata.token_endpoint)
+            .header(ACCEPT, "application/json")
+            .form(&params)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json::<TokenResponse>().await?)
+    }
+
+    // OIDC providers are trusted through the verified ID token, plain OAuth2 ones through userinfo
+    pub async fn identity(
+        &self,
+        client: &Client,
+        metadata: &ProviderMetadata,
+        tokens: &TokenResponse,
+        nonce: &str,
+    ) -> Result<ProviderIdentity> {
+        if let Some(issuer) = &self.issuer {
+            let id_token = tokens.id_token.as_deref().ok_or("id token missing")?;
+            let jwks_url = metadata.jwks_uri.as_deref().ok_or("jwks uri missing")?;
+            // Google may also issue tokens without the scheme, other issuers must match exactly
+            let issuers = match issuer.as_str() {
+                GOOGLE_ISSUER => vec![GOOGLE_ISSUER, "accounts.google.com"],
+                issuer => vec![issuer],
+            };
+
+            let claims =
+                verify_id_token(client, jwks_url, id_token, &issuers, &self.client_id, nonce)
+                    .await?;
+
+            return Ok(ProviderIdentity {
+                subject: claims.sub,
+                email: claims.email,
+                email_verified: claims.email_verified,
+            });
+        }
+
+        let userinfo_endpoint = metadata
+            .userinfo_endpoint
+            .as_deref()
+            .ok_or("userinfo endpoint not configured")?;
+
+        let userinfo = get_json(client, userinfo_endpoint, &tokens.access_token).await?;
+
+        let subject = match &userinfo[&self.subject_claim] {
+            Value::String(subject) => subject.clone(),
+            Value::Number(subject) => subject.to_string(),
+            _ => return Err("subject claim missing".into()),
+        };
+
+        let (email, email_verified) = match (userinfo["email"].as_str(), &self.emails_endpoint) {
+            (Some(email), _) => (
+                Some(email.to_string()),
+                self.trust_email || userinfo["email_verified"].as_bool() == Some(true),
+            ),
+            (None, Some(emails_endpoint)) => {
+                let emails = get_json(client, emails_endpoint, &tokens.access_token).await?;
+                (verified_email(&emails), true)
+            }
+            (None, None) => (None, false),
+        };
+
+        Ok(ProviderIdentity {
+            subject,
+            email_verified: email_verified && email.is_some(),
+            email,
+        })
+    }
+}
+
+// GitHub wants a user agent
+async fn get_json(client: &Client, url: &str, access_token: &str) -> Result<Value> {
+    Ok(client
+        .get(url)
+        .bearer_auth(access_token)
+        .header(ACCEPT, "application/json")
+        .header(reqwest::header::USER_AGENT, "nft-minter")
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?)
+}
+
+// the primary address of a [{email, primary, verified}] list, or else any verified one
+fn verified_email(emails: &Value) -> Option<String> {
+    let verified: Vec<&Value> = emails
+        .as_array()?
+        .iter()
+        .filter(|email| email["verified"].as_bool() == Some(true))
+        .collect();
+
+    verified
+        .iter()
+        .find(|email| email["primary"].as_bool() == Some(true))
+        .or(verified.first())
+        .and_then(|email| email["email"].as_str())
+        .map(str::to_string)
+}
+
+async fn discover(client: &Client, issuer: &str) -> Result<ProviderMetadata> {
+    let stale_after = chrono::Utc::now().timestamp() - DISCOVERY_MAXAGE * 60;
+
+    let cached = DISCOVERY_CACHE
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(cached_issuer, fetched_at, _)| cached_issuer == issuer && *fetched_at > stale_after)
+        .map(|(_, _, metadata)| metadata.clone());
+
+    if let Some(metadata) = cached {
+        return Ok(metadata);
+    }
+
+    let discovery_url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    );
+
+    let metadata = client
+        .get(discovery_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<ProviderMetadata>()
+        .await?;
+
+    // OIDC discovery requires the document to name the issuer it was fetched from
+    if metadata.issuer.as_deref() != Some(issuer) {
+        return Err("discovered issuer does not match".into());
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut cache = DISCOVERY_CACHE.lock().unwrap();
+    cache.retain(|(cached_issuer, _, _)| cached_issuer != issuer);
+    cache.push((issuer.to_string(), now, metadata.clone()));
+
+    Ok(metadata)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub nonce: Option<String>,
+}
+
+// Todo - move to a shared cache
+static JWKS_CACHE: Mutex<Vec<(String, i64, JwkSet)>> = Mutex::new(Vec::new()); // (url, fetched_at, keys)
+
+async fn fetch_jwks(client: &Client, jwks_url: &str) -> Result<JwkSet> {
+    let jwks = client
+        .get(jwks_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<JwkSet>()
+        .await?;
+
+    let now = chrono::Utc::now().timestamp();
+    let mut cache = JWKS_CACHE.lock().unwrap();
+    cache.retain(|(url, _, _)| url != jwks_url);
+    cache.push((jwks_url.to_string(), now, jwks.clone()));
+
+    Ok(jwks)
+}
+
+// keys are served from the cache until they expire or an unknown kid shows up (key rotation).
+// Returns the key with the algorithm it is for, RS256 unless the JWK names one
+async fn decoding_key(
+    client: &Client,
+    jwks_url: &str,
+    kid: &str,
+) -> Result<(DecodingKey, Algorithm)> {
+    let stale_after = chrono::Utc::now().timestamp() - JWKS_MAXAGE * 60;
+
+    let cached = JWKS_CACHE
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(url, fetched_at, _)| url == jwks_url && *fetched_at > stale_after)
+        .and_then(|(_, _, jwks)| jwks.find(kid).cloned());
+
+    let jwk = match cached {
+        Some(jwk) => jwk,
+        None => fetch_jwks(client, jwks_url)
+            .await?
+            .find(kid)
+            .cloned()
+            .ok_or("unknown signing key")?,
+    };
+
+    let algorithm = match jwk.common.key_algorithm {
+        Some(key_algorithm) => Algorithm::from_str(&key_algorithm.to_string())?,
+        None => Algorithm::RS256,
+    };
+
+    Ok((DecodingKey::from_jwk(&jwk)?, algorithm))
+}
+
+pub async fn verify_id_token(
+    client: &Client,
+    jwks_url: &str,
+    id_token: &str,
+    issuers: &[&str],
+    audience: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims> {
+    let header = jwt::decode_header(id_token)?;
+
+    // ID tokens are signed with the provider's public keys, never a shared secret
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err("unsupported id token algorithm".into());
+    }
+
+    let kid = header.kid.ok_or("id token without kid")?;
+    let (key, algorithm) = decoding_key(client, jwks_url, &kid).await?;
+
+    // the token doesn't get to pick the algorithm, it must be the one of its key
+    if header.alg != algorithm {
+        return Err("id token algorithm doesn't match its key".into());
+    }
+
+    let mut validation = Validation::new(algorithm);
+    validation.set_issuer(issuers);
+    validation.set_audience(&[audience]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let claims = jwt::decode::<IdTokenClaims>(id_token, &key, &validation)?.claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err("id token nonce mismatch".into());
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::{Value, json};
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    pub const TEST_KID: &str = "test-key";
+    const TEST_KEY_N: &str = "rpTBTay81FxEcreN84ba5k4KSMbExO9f6UuVIq9IDkBLWmCNdugq5HMaDo1WQW5PzWhvBxvXBNX77MU2fcu9fADyaDUbfk_CBEIkqGP_h8L9R5ufBP6sJT9HT9pUV81wJIRL3lgB5RjcX9rjSkd1QWynQGpG0G53jqV03YWAJM0PehG6PbPGQQxQlHHuCHrqEBl0L7T8Pj8l6dSIXLhJnrvoHOdC-tlnJIce4fAs3SKPB02rOXPoJNLRdE8U3Qns4w3TnhekdNQG4dVQ4rPN4u14UcsIB0H__05wqbg-138s0pVHeEVyzg39ufjEjoGgP2QW3kntVlnobIN5GI2Yww";
+
+    pub fn test_jwks() -> Value {
+        json!({"keys": [{
+            "kty": "RSA",
+            "kid": TEST_KID,
+            "use": "sig",
+            "alg": "RS256",
+            "n": TEST_KEY_N,
+            "e": "AQAB",
+        }]})
+    }
+
+    pub fn sign_test_token(claims: &Value) -> String {
+        sign_test_token_with(Algorithm::RS256, claims)
+    }
+
+    fn sign_test_token_with(algorithm: Algorithm, claims: &Value) -> String {
+        let mut header = Header::new(algorithm);
+        header.kid = Some(TEST_KID.to_string());
+
+        let key = EncodingKey::from_rsa_pem(include_bytes!("testdata/oidc_test_key.pem")).unwrap();
+        jwt::encode(&header, claims, &key).unwrap()
+    }
+
+    // serves `route(base_url, path)` as JSON over plain HTTP, returns the base url and a request counter
+    pub fn serve_json(
+        route: impl Fn(&str, &str) -> Value + Send + 'static,
+    ) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let served_url = base_url.clone();
+
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 8192];
+                let len = stream.read(&mut buf).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..len]);
+                let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+                counter.fetch_add(1, Ordering::SeqCst);
+                let body = route(&served_url, &path).to_string();
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        (base_url, hits)
+    }
+
+    fn id_token_claims(nonce: &str, exp: i64) -> Value {
+        issued_claims("https://accounts.google.com", nonce, exp)
+    }
+
+    pub fn issued_claims(issuer: &str, nonce: &str, exp: i64) -> Value {
+        json!({
+            "iss": issuer,
+            "aud": "client-id",
+            "sub": "1234567890",
+            "email": "alice@example.com",
+            "email_verified": true,
+            "nonce": nonce,
+            "exp": exp,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_verify_id_token() {
+        let (base_url, hits) = serve_json(|_, _| test_jwks());
+        let jwks_url = format!("{base_url}/certs");
+        let client = Client::new();
+        let issuers = ["https://accounts.google.com"];
+        let exp = chrono::Utc::now().timestamp() + 600;
+
+        let token = sign_test_token(&id_token_claims("n-1", exp));
+        let claims = verify_id_token(&client, &jwks_url, &token, &issuers, "client-id", "n-1")
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "1234567890");
+        assert!(claims.email_verified);
+
+        // served from the cache
+        verify_id_token(&client, &jwks_url, &token, &issuers, "client-id", "n-1")
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        assert!(
+            verify_id_token(&client, &jwks_url, &token, &issuers, "client-id", "n-2")
+                .await
+                .is_err()
+        );
+        assert!(
+            verify_id_token(&client, &jwks_url, &token, &issuers, "other-client", "n-1")
+                .await
+                .is_err()
+        );
+        assert!(
+            verify_id_token(
+                &client,
+                &jwks_url,
+                &token,
+                &["https://evil.io"],
+                "client-id",
+                "n-1"
+            )
+            .await
+            .is_err()
+        );
+
+        let expired = sign_test_token(&id_token_claims("n-1", exp - 3600));
+        assert!(
+            verify_id_token(&client, &jwks_url, &expired, &issuers, "client-id", "n-1")
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_id_token_algorithm_is_pinned() {
+        let client = Client::new();
+        let issuers = ["https://accounts.google.com"];
+        let claims = id_token_claims("n-1", chrono::Utc::now().timestamp() + 600);
+
+        // the JWK is for RS256, the same RSA key used with another algorithm is refused
+        let (base_url, _) = serve_json(|_, _| test_jwks());
+        let jwks_url = format!("{base_url}/certs");
+        let token = sign_test_token_with(Algorithm::RS512, &claims);
+        assert!(
+            verify_id_token(&client, &jwks_url, &token, &issuers, "client-id", "n-1")
+                .await
+                .is_err()
+        );
+
+        // without an alg in the JWK only RS256 is accepted
+        let (base_url, _) = serve_json(|_, _| {
+            let mut jwks = test_jwks();
+            jwks["keys"][0].as_object_mut().unwrap().remove("alg");
+            jwks
+        });
+        let jwks_url = format!("{base_url}/certs");
+        assert!(
+            verify_id_token(&client, &jwks_url, &token, &issuers, "client-id", "n-1")
+                .await
+                .is_err()
+        );
+        let token = sign_test_token(&claims);
+        assert!(
+            verify_id_token(&client, &jwks_url, &token, &issuers, "client-id", "n-1")
+                .await
+                .is_ok()
+        );
+    }
+
+    fn test_provider(issuer: Option<String>) -> Provider {
+        Provider {
+            name: "corp".to_string(),
+            client_id: "client-id".to_string(),
+            client_secret: "client-secret".to_string(),
+            redirect_url: "http://localhost:8080/auth/corp/callback".to_string(),
+            scopes: "openid email".to_string(),
+            issuer,
+            jwks_url: None,
+            authorization_endpoint: None,
+            token_endpoint: None,
+            userinfo_endpoint: None,
+            emails_endpoint: None,
+            subject_claim: "sub".to_string(),
+            trust_email: false,
+        }
+    }
+
+    // stand-in identity provider with discovery, token and jwks endpoints
+    pub fn mock_oidc_server(nonce: &'static str) -> String {
+        let (base_url, _) = serve_json(move |base_url, path| match path {
+            "/.well-known/openid-configuration" => json!({
+                "issuer": base_url,
+                "authorization_endpoint": format!("{base_url}/authorize"),
+                "token_endpoint": format!("{base_url}/token"),
+                "jwks_uri": format!("{base_url}/certs"),
+            }),
+            "/token" => {
+                let exp = chrono::Utc::now().timestamp() + 600;
+                json!({
+                    "access_token": "access-token",
+                    "id_token": sign_test_token(&issued_claims(base_url, nonce, exp)),
+                })
+            }
+            _ => test_jwks(),
+        });
+
+        base_url
+    }
+
+    #[tokio::test]
+    async fn test_discovered_provider() {
+        let issuer = mock_oidc_server("n-1");
+        let provider = test_provider(Some(issuer.clone()));
+        let client = Client::new();
+
+        let metadata = provider.metadata(&client).await.unwrap();
+        assert_eq!(metadata.token_endpoint, format!("{issuer}/token"));
+
+        let auth_url = provider
+            .authorization_url(&metadata, "state", "challenge", "n-1")
+            .unwrap();
+        assert!(
+            auth_url
+                .as_str()
+                .starts_with(&format!("{issuer}/authorize?"))
+        );
+        assert!(
+            auth_url
+                .query_pairs()
+                .any(|(k, v)| k == "nonce" && v == "n-1")
+        );
+
+        let tokens = provider
+            .request_token(&client, &metadata, "code", "verifier")
+            .await
+            .unwrap();
+        let identity = provider
+            .identity(&client, &metadata, &tokens, "n-1")
+            .await
+            .unwrap();
+        assert_eq!(identity.subject, "1234567890");
+        assert!(identity.email_verified);
+
+        assert!(
+            provider
+                .identity(&client, &metadata, &tokens, "n-2")
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_provider() {
+        let (base_url, _) = serve_json(|_, path| match path {
+            "/token" => json!({"access_token": "access-token"}),
+            _ => json!({"id": 42, "email": "octocat@example.com"}),
+        });
+
+        let mut provider = test_provider(None);
+        provider.authorization_endpoint = Some(format!("{base_url}/authorize"));
+        provider.token_endpoint = Some(format!("{base_url}/token"));
+        provider.userinfo_endpoint = Some(format!("{base_url}/user"));
+        provider.subject_claim = "id".to_string();
+
+        let client = Client::new();
+        let metadata = provider.metadata(&client).await.unwrap();
+        let tokens = provider
+            .request_token(&client, &metadata, "code", "verifier")
+            .await
+            .unwrap();
+
+        let identity = provider
+            .identity(&client, &metadata, &tokens, "")
+            .await
+            .unwrap();
+        assert_eq!(identity.subject, "42");
+        assert!(!identity.email_verified);
+
+        provider.trust_email = true;
+        let identity = provider
+            .identity(&client, &metadata, &tokens, "")
+            .await
+            .unwrap();
+        assert!(identity.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_provider_without_public_email() {
+        let (base_url, _) = serve_json(|_, path| match path {
+            "/user/emails" => json!([
+                {"email": "old@example.com", "primary": false, "verified": true},
+                {"email": "unverified@example.com", "primary": false, "verified": false},
+                {"email": "octocat@example.com", "primary": true, "verified": true},
+            ]),
+            _ => json!({"id": 42, "email": null}),
+        });
+
+        let mut provider = test_provider(None);
+        provider.userinfo_endpoint = Some(format!("{base_url}/user"));
+        provider.subject_claim = "id".to_string();
+        let metadata = ProviderMetadata {
+            issuer: None,
+            authorization_endpoint: format!("{base_url}/authorize"),
+            token_endpoint: format!("{base_url}/token"),
+            jwks_uri: None,
+            userinfo_endpoint: provider.userinfo_endpoint.clone(),
+        };
+        let tokens = TokenResponse {
+            access_token: "access-token".to_string(),
+            id_token: None,
+        };
+        let client = Client::new();
+
+        let identity = provider
+            .identity(&client, &metadata, &tokens, "")
+            .await
+            .unwrap();
+        assert_eq!(identity.email, None);
+        assert!(!identity.email_verified);
+
+        provider.emails_endpoint = Some(format!("{base_url}/user/emails"));
+        let identity = provider
+            .identity(&client, &metadata, &tokens, "")
+            .await
+            .unwrap();
+        assert_eq!(identity.email.as_deref(), Some("octocat@example.com"));
+        assert!(identity.email_verified);
+
+        assert_eq!(
+            verified_email(
+                &json!([{"email": "a@example.com", "primary": true, "verified": false}])
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_only_google_issuer_is_relaxed() {
+        let client = Client::new();
+        let exp = chrono::Utc::now().timestamp() + 600;
+
+        let google = mock_oidc_server("n-1");
+        let mut provider = test_provider(Some(GOOGLE_ISSUER.to_string()));
+        let mut metadata = test_provider(Some(google.clone()))
+            .metadata(&client)
+            .await
+            .unwrap();
+        metadata.issuer = Some(GOOGLE_ISSUER.to_string());
+        let tokens = |issuer: &str| TokenResponse {
+            access_token: "access-token".to_string(),
+            id_token: Some(sign_test_token(&issued_claims(issuer, "n-1", exp))),
+        };
+
+        assert!(
+            provider
+                .identity(&client, &metadata, &tokens("accounts.google.com"), "n-1")
+                .await
+                .is_ok()
+        );
+
+        // any other issuer must match with its scheme
+        let issuer = google.clone();
+        provider.issuer = Some(issuer.clone());
+        assert!(
+            provider
+                .identity(&client, &metadata, &tokens(&issuer), "n-1")
+                .await
+                .is_ok()
+        );
+        assert!(
+            provider
+                .identity(
+                    &client,
+                    &metadata,
+                    &tokens(issuer.trim_start_matches("http://")),
+                    "n-1"
+                )
+                .await
+                .is_err()
+        );
+    }
+}
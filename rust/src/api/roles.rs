@@ -0,0 +1,171 @@
+use super::{
+    authentication::AuthenticationGuard,
+    types::{Role, RoleInfo, User},
+};
+use actix_web::{
+    FromRequest, HttpRequest, HttpResponse, Responder, dev::Payload, error as actix_error, web,
+};
+use serde_json::json;
+use std::{future, marker::PhantomData};
+
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub struct Admin;
+pub struct Minter;
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+impl RequiredRole for Minter {
+    const ROLE: Role = Role::Minter;
+}
+
+// admins implicitly hold every role
+pub fn has_role(roles: &[Role], role: Role) -> bool {
+    roles.contains(&role) || roles.contains(&Role::Admin)
+}
+
+pub fn roles_of(user_id: &str) -> Vec<Role> {
+    super::USERS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|user| user.id == user_id)
+        .map(|user| user.roles.clone())
+        .unwrap_or_default()
+}
+
+// ADMIN_EMAILS and ADMIN_WALLETS (comma separated) bootstrap the first admins
+pub fn initial_roles(email: Option<&str>, wallet_address: &str) -> Vec<Role> {
+    let listed = |var: &str, value: &str| {
+        std::env::var(var).is_ok_and(|list| {
+            list.split(',')
+                .any(|entry| entry.trim().eq_ignore_ascii_case(value))
+        })
+    };
+
+    let is_admin = email.is_some_and(|email| listed("ADMIN_EMAILS", email))
+        || listed("ADMIN_WALLETS", wallet_address);
+
+    if is_admin {
+        vec![Role::User, Role::Admin]
+    } else {
+        vec![Role::User]
+    }
+}
+
+// the roles claim in the token is informational, access is checked against the
+// stored user so that revocations apply immediately
+pub struct RequireRole<R: RequiredRole> {
+    pub user: User,
+    _role: PhantomData<R>,
+}
+
+impl<R: RequiredRole> FromRequest for RequireRole<R> {
+    type Error = actix_error::Error;
+    type Future = future::Ready<std::result::Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let auth_guard = match AuthenticationGuard::from_request(req, payload).into_inner() {
+            Ok(auth_guard) => auth_guard,
+            Err(err) => return future::ready(Err(err)),
+        };
+
+        if !has_role(&auth_guard.user.roles, R::ROLE) {
+            return future::ready(Err(actix_error::ErrorForbidden(
+                json!({"status": "fail", "message": "You don't have permission to do this"}),
+            )));
+        }
+
+        future::ready(Ok(RequireRole {
+            user: auth_guard.user,
+            _role: PhantomData,
+        }))
+    }
+}
+
+#[actix_web::get("/admin/users/{user_id}/roles")]
+async fn get_roles(_admin: RequireRole<Admin>, user_id: web::Path<String>) -> impl Responder {
+    let users = super::USERS.lock().unwrap();
+
+    match users.iter().find(|user| user.id == *user_id) {
+        Some(user) => HttpResponse::Ok().json(&user.roles),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[actix_web::post("/admin/users/{user_id}/roles")]
+async fn grant_role(
+    _admin: RequireRole<Admin>,
+    user_id: web::Path<String>,
+    input: web::Json<RoleInfo>,
+) -> impl Responder {
+    let mut users = super::USERS.lock().unwrap();
+
+    let Some(user) = users.iter_mut().find(|user| user.id == *user_id) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if !user.roles.contains(&input.role) {
+        user.roles.push(input.role);
+    }
+
+    HttpResponse::Ok().json(&user.roles)
+}
+
+#[actix_web::delete("/admin/users/{user_id}/roles/{role}")]
+async fn revoke_role(
+    _admin: RequireRole<Admin>,
+    path: web::Path<(String, Role)>,
+) -> impl Responder {
+    let (user_id, role) = path.into_inner();
+    let mut users = super::USERS.lock().unwrap();
+
+    let admins = users
+        .iter()
+        .filter(|user| user.roles.contains(&Role::Admin))
+        .count();
+
+    let Some(user) = users.iter_mut().find(|user| user.id == user_id) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if role == Role::User {
+        return HttpResponse::BadRequest()
+            .json(json!({"status": "fail", "message": "The user role can't be revoked"}));
+    }
+
+    if role == Role::Admin && admins == 1 && user.roles.contains(&Role::Admin) {
+        return HttpResponse::Conflict()
+            .json(json!({"status": "fail", "message": "Can't revoke the last admin"}));
+    }
+
+    user.roles.retain(|granted| *granted != role);
+
+    HttpResponse::Ok().json(&user.roles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_role() {
+        assert!(has_role(&[Role::User, Role::Minter], Role::Minter));
+        assert!(!has_role(&[Role::User], Role::Minter));
+        assert!(!has_role(&[Role::User, Role::Minter], Role::Admin));
+        assert!(has_role(&[Role::User, Role::Admin], Role::Minter));
+    }
+
+    #[test]
+    fn test_role_serialization() {
+        assert_eq!(serde_json::to_string(&Role::Minter).unwrap(), "\"minter\"");
+        assert_eq!(
+            serde_json::from_str::<Role>("\"admin\"").unwrap(),
+            Role::Admin
+        );
+    }
+}
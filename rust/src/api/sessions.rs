@@ -0,0 +1,477 @@
+use super::{
+    Result,
+    authentication::AuthenticationGuard,
+    roles,
+    types::{SessionInfo, TokenClaims},
+};
+use actix_web::{
+    HttpRequest, HttpResponse, Responder,
+    cookie::{Cookie, time::Duration as CookieDuration},
+    http::header::{USER_AGENT, X_FORWARDED_FOR},
+    web,
+};
+use jsonwebtoken::{EncodingKey, Header};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{net::IpAddr, sync::Mutex};
+
+const DEFAULT_REFRESH_TOKEN_MAXAGE: i64 = 30 * 24 * 60; // minutes
+
+// a session is one refresh token family, rotated on every refresh
+#[derive(Clone)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub created_at: i64,
+    pub last_used: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub expires_at: i64,
+    pub revoked: bool,
+    pub second_factor_at: Option<i64>, // last TOTP or backup code check
+    refresh_hash: String,
+    used_refresh_hashes: Vec<String>,
+}
+
+pub struct IssuedTokens {
+    pub access_token: String,
+    pub access_max_age: i64, // minutes
+    pub refresh_token: String,
+    pub refresh_max_age: i64, // minutes
+}
+
+// Todo - move to db
+static SESSIONS: Mutex<Vec<Session>> = Mutex::new(Vec::new());
+static REVOKED_TOKENS: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new()); // (jti, exp)
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn new_secret() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+fn refresh_max_age() -> i64 {
+    std::env::var("REFRESH_TOKEN_MAXAGE")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_REFRESH_TOKEN_MAXAGE)
+}
+
+fn encode_access_token(user_id: &str, session_id: &str) -> Result<(String, i64)> {
+    let jwt_secret = std::env::var("JWT_SECRET")?;
+    let jwt_max_age = std::env::var("TOKEN_MAXAGE")?.parse::<i64>()?;
+
+    let now = chrono::Utc::now();
+    let claims = TokenClaims {
+        sub: user_id.to_string(),
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: session_id.to_string(),
+        roles: roles::roles_of(user_id),
+        iat: now.timestamp() as usize,
+        exp: (now + chrono::Duration::minutes(jwt_max_age)).timestamp() as usize,
+    };
+
+    let token = jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(jwt_secret.as_ref()),
+    )?;
+
+    Ok((token, jwt_max_age))
+}
+
+fn issue_tokens(user_id: &str, session_id: &str, refresh_secret: &str) -> Result<IssuedTokens> {
+    let (access_token, access_max_age) = encode_access_token(user_id, session_id)?;
+
+    Ok(IssuedTokens {
+        access_token,
+        access_max_age,
+        refresh_token: format!("{}.{}", session_id, refresh_secret),
+        refresh_max_age: refresh_max_age(),
+    })
+}
+
+// user agent and ip of the client a session was started from
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl SessionClient {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        Self {
+            user_agent: req
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|h| h.to_str().ok())
+                .map(str::to_string),
+            ip: client_ip(req),
+        }
+    }
+}
+
+// the peer address, unless it is one of TRUSTED_PROXIES (comma separated ips).
+// Anyone can send X-Forwarded-For, so it is only followed through trusted proxies
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    let trusted_proxies: Vec<IpAddr> = std::env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+
+    let forwarded_for = req
+        .headers()
+        .get(X_FORWARDED_FOR)
+        .and_then(|h| h.to_str().ok());
+
+    Some(forwarded_client(peer, forwarded_for, &trusted_proxies).to_string())
+}
+
+// walks X-Forwarded-For from the right, proxies append the address they received
+// the request from, so the first untrusted hop is the client
+fn forwarded_client(peer: IpAddr, forwarded_for: Option<&str>, trusted: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+
+    for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+        if !trusted.contains(&client) {
+            break;
+        }
+
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+    }
+
+    client
+}
+
+fn start_session(user_id: &str, client: SessionClient) -> (String, String) {
+    let now = chrono::Utc::now();
+    let secret = new_secret();
+
+    let session = Session {
+        id: uuid::Uuid::new_v4().to_string(),
+        user_id: user_id.to_string(),
+        created_at: now.timestamp(),
+        last_used: now.timestamp(),
+        user_agent: client.user_agent,
+        ip: client.ip,
+        expires_at: (now + chrono::Duration::minutes(refresh_max_age())).timestamp(),
+        revoked: false,
+        second_factor_at: None,
+        refresh_hash: hash_secret(&secret),
+        used_refresh_hashes: Vec::new(),
+    };
+
+    let session_id = session.id.clone();
+    SESSIONS.lock().unwrap().push(session);
+
+    (session_id, secret)
+}
+
+// swaps the presented refresh token for a new one, presenting an already
+// rotated token is treated as theft and revokes the whole family
+fn rotate_refresh_token(refresh_token: &str) -> Result<(String, String, String)> {
+    let (session_id, secret) = refresh_token
+        .split_once('.')
+        .ok_or("Malformed refresh token")?;
+
+    let mut sessions = SESSIONS.lock().unwrap();
+    let session = sessions
+        .iter_mut()
+        .find(|s| s.id == session_id)
+        .ok_or("Unknown session")?;
+
+    if session.revoked || session.expires_at <= chrono::Utc::now().timestamp() {
+        return Err("Session is no longer active".into());
+    }
+
+    let presented_hash = hash_secret(secret);
+
+    if session.used_refresh_hashes.contains(&presented_hash) {
+        session.revoked = true;
+        return Err("Refresh token reuse detected".into());
+    }
+
+    if session.refresh_hash != presented_hash {
+        return Err("Invalid refresh token".into());
+    }
+
+    let new_secret = new_secret();
+    let old_hash = std::mem::replace(&mut session.refresh_hash, hash_secret(&new_secret));
+    session.used_refresh_hashes.push(old_hash);
+    session.last_used = chrono::Utc::now().timestamp();
+
+    Ok((session.user_id.clone(), session.id.clone(), new_secret))
+}
+
+pub fn create_session(user_id: &str, client: SessionClient) -> Result<IssuedTokens> {
+    let (session_id, secret) = start_session(user_id, client);
+
+    issue_tokens(user_id, &session_id, &secret)
+}
+
+pub fn refresh_session(refresh_token: &str) -> Result<IssuedTokens> {
+    let (user_id, session_id, secret) = rotate_refresh_token(refresh_token)?;
+
+    issue_tokens(&user_id, &session_id, &secret)
+}
+
+pub fn revoke_session(session_id: &str) {
+    if let Some(session) = SESSIONS
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|s| s.id == session_id)
+    {
+        session.revoked = true;
+    }
+}
+
+pub fn touch_session(session_id: &str) {
+    if let Some(session) = SESSIONS
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|s| s.id == session_id)
+    {
+        session.last_used = chrono::Utc::now().timestamp();
+    }
+}
+
+pub fn mark_second_factor(session_id: &str) {
+    if let Some(session) = SESSIONS
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .find(|s| s.id == session_id)
+    {
+        session.second_factor_at = Some(chrono::Utc::now().timestamp());
+    }
+}
+
+pub fn second_factor_at(session_id: &str) -> Option<i64> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|s| s.id == session_id)
+        .and_then(|s| s.second_factor_at)
+}
+
+fn active_sessions(user_id: &str, now: i64) -> Vec<Session> {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|s| s.user_id == user_id && !s.revoked && s.expires_at > now)
+        .cloned()
+        .collect()
+}
+
+pub fn revoke_token(claims: &TokenClaims) {
+    let now = chrono::Utc::now().timestamp() as usize;
+    let mut revoked = REVOKED_TOKENS.lock().unwrap();
+
+    // revoked tokens only need to be remembered until they expire
+    revoked.retain(|(_, exp)| *exp > now);
+    revoked.push((claims.jti.clone(), claims.exp));
+}
+
+pub fn is_revoked(claims: &TokenClaims) -> bool {
+    let token_revoked = REVOKED_TOKENS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(jti, _)| *jti == claims.jti);
+
+    let session_active = SESSIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|s| s.id == claims.sid && !s.revoked);
+
+    token_revoked || !session_active
+}
+
+pub fn token_cookies(tokens: IssuedTokens) -> [Cookie<'static>; 2] {
+    let access_cookie = Cookie::build("token", tokens.access_token)
+        .path("/")
+        .max_age(CookieDuration::new(60 * tokens.access_max_age, 0))
+        .http_only(true)
+        .finish();
+
+    let refresh_cookie = Cookie::build("refresh_token", tokens.refresh_token)
+        .path("/auth")
+        .max_age(CookieDuration::new(60 * tokens.refresh_max_age, 0))
+        .http_only(true)
+        .finish();
+
+    [access_cookie, refresh_cookie]
+}
+
+#[actix_web::post("/auth/refresh")]
+async fn refresh(req: HttpRequest) -> impl Responder {
+    let Some(refresh_token) = req.cookie("refresh_token") else {
+        return HttpResponse::Unauthorized()
+            .json(json!({"status": "fail", "message": "Refresh token not provided"}));
+    };
+
+    match refresh_session(refresh_token.value()) {
+        Ok(tokens) => {
+            let [access_cookie, refresh_cookie] = token_cookies(tokens);
+
+            HttpResponse::Ok()
+                .cookie(access_cookie)
+                .cookie(refresh_cookie)
+                .json(json!({"status": "success"}))
+        }
+        Err(err) => {
+            println!("Refreshing session failed: {}", err);
+
+            HttpResponse::Unauthorized()
+                .json(json!({"status": "fail", "message": "Invalid or expired refresh token"}))
+        }
+    }
+}
+
+#[actix_web::post("/auth/logout")]
+async fn logout(auth_guard: AuthenticationGuard) -> impl Responder {
+    revoke_session(&auth_guard.claims.sid);
+    revoke_token(&auth_guard.claims);
+
+    let mut access_cookie = Cookie::build("token", "").path("/").finish();
+    access_cookie.make_removal();
+
+    let mut refresh_cookie = Cookie::build("refresh_token", "").path("/auth").finish();
+    refresh_cookie.make_removal();
+
+    HttpResponse::Ok()
+        .cookie(access_cookie)
+        .cookie(refresh_cookie)
+        .json(json!({"status": "success"}))
+}
+
+#[actix_web::get("/me/sessions")]
+async fn get_sessions(auth_guard: AuthenticationGuard) -> impl Responder {
+    let now = chrono::Utc::now().timestamp();
+
+    let sessions: Vec<SessionInfo> = active_sessions(&auth_guard.user.id, now)
+        .into_iter()
+        .map(|s| SessionInfo {
+            current: s.id == auth_guard.claims.sid,
+            id: s.id,
+            created_at: s.created_at,
+            last_used: s.last_used,
+            user_agent: s.user_agent,
+            ip: s.ip,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(sessions)
+}
+
+#[actix_web::delete("/me/sessions/{session_id}")]
+async fn delete_session(
+    auth_guard: AuthenticationGuard,
+    session_id: web::Path<String>,
+) -> impl Responder {
+    let now = chrono::Utc::now().timestamp();
+
+    if !active_sessions(&auth_guard.user.id, now)
+        .iter()
+        .any(|s| s.id == *session_id)
+    {
+        return HttpResponse::NotFound()
+            .json(json!({"status": "fail", "message": "Session not found"}));
+    }
+
+    revoke_session(&session_id);
+    HttpResponse::Ok().json(json!({"status": "success"}))
+}
+
+// signs out every session except the one making the request
+#[actix_web::delete("/me/sessions")]
+async fn delete_other_sessions(auth_guard: AuthenticationGuard) -> impl Responder {
+    let now = chrono::Utc::now().timestamp();
+
+    let revoked = active_sessions(&auth_guard.user.id, now)
+        .iter()
+        .filter(|s| s.id != auth_guard.claims.sid)
+        .inspect(|s| revoke_session(&s.id))
+        .count();
+
+    HttpResponse::Ok().json(json!({"status": "success", "revoked": revoked}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_client() {
+        let peer: IpAddr = "10.0.0.2".parse().unwrap();
+        let proxies: Vec<IpAddr> = vec!["10.0.0.2".parse().unwrap(), "10.0.0.3".parse().unwrap()];
+
+        // without a trusted proxy the header is ignored
+        assert_eq!(forwarded_client(peer, Some("1.2.3.4"), &[]), peer);
+        assert_eq!(forwarded_client(peer, None, &proxies), peer);
+
+        assert_eq!(
+            forwarded_client(peer, Some("1.2.3.4"), &proxies).to_string(),
+            "1.2.3.4"
+        );
+        // a spoofed left-most entry doesn't win over the hop the proxies saw
+        assert_eq!(
+            forwarded_client(peer, Some("6.6.6.6, 1.2.3.4, 10.0.0.3"), &proxies).to_string(),
+            "1.2.3.4"
+        );
+        assert_eq!(forwarded_client(peer, Some("garbage"), &proxies), peer);
+    }
+
+    #[test]
+    fn test_refresh_token_rotation() {
+        let (session_id, secret) = start_session(
+            "rotation-user",
+            SessionClient {
+                user_agent: None,
+                ip: None,
+            },
+        );
+        let first = format!("{}.{}", session_id, secret);
+
+        let (user_id, rotated_session, new_secret) = rotate_refresh_token(&first).unwrap();
+        assert_eq!(user_id, "rotation-user");
+        assert_eq!(rotated_session, session_id);
+
+        let second = format!("{}.{}", session_id, new_secret);
+        assert!(rotate_refresh_token(&second).is_ok());
+    }
+
+    #[test]
+    fn test_refresh_token_reuse_revokes_family() {
+        let (session_id, secret) = start_session(
+            "reuse-user",
+            SessionClient {
+                user_agent: None,
+                ip: None,
+            },
+        );
+        let stolen = format!("{}.{}", session_id, secret);
+
+        let (_, _, new_secret) = rotate_refresh_token(&stolen).unwrap();
+        assert!(rotate_refresh_token(&stolen).is_err());
+
+        // the legitimate holder's newer token is now dead as well
+        let current = format!("{}.{}", session_id, new_secret);
+        assert!(rotate_refresh_token(&current).is_err());
+    }
+}
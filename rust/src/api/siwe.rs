@@ -0,0 +1,360 @@
+use super::{
+    Result,
+    addresses::DEFAULT_ADDRESS_LABEL,
+    roles,
+    sessions::{self, SessionClient},
+    types::{ActixContext, Identity, SiweLogin, User, WalletAddress},
+};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use alloy::{hex, primitives::Address};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use serde_json::json;
+use std::sync::Mutex;
+
+const SIWE_PROVIDER: &str = "ethereum";
+const SIWE_NONCE_MAXAGE: i64 = 10; // minutes
+const SIWE_CLOCK_SKEW: i64 = 5; // minutes
+const SIWE_PREAMBLE: &str = " wants you to sign in with your Ethereum account:";
+
+// Todo - move to db
+static SIWE_NONCES: Mutex<Vec<(String, i64)>> = Mutex::new(Vec::new()); // (nonce, expires_at)
+
+// EIP-4361 message
+#[derive(Debug, PartialEq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)?.with_timezone(&Utc))
+}
+
+impl SiweMessage {
+    pub fn parse(message: &str) -> Result<Self> {
+        let mut lines = message.lines().peekable();
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(SIWE_PREAMBLE))
+            .ok_or("invalid preamble")?
+            .to_string();
+
+        // the address must be EIP-55 checksummed
+        let address = Address::parse_checksummed(lines.next().ok_or("missing address")?, None)?;
+
+        while lines.peek() == Some(&"") {
+            lines.next();
+        }
+
+        let statement = match lines.peek() {
+            Some(line) if !line.starts_with("URI: ") => lines.next().map(str::to_string),
+            _ => None,
+        };
+
+        while lines.peek() == Some(&"") {
+            lines.next();
+        }
+
+        let mut fields: Vec<(&str, &str)> = Vec::new();
+        let mut resources = Vec::new();
+
+        while let Some(line) = lines.next() {
+            if line == "Resources:" {
+                resources = lines
+                    .by_ref()
+                    .map(|resource| resource.strip_prefix("- ").map(str::to_string))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or("invalid resource")?;
+                break;
+            }
+
+            fields.push(line.split_once(": ").ok_or("invalid field")?);
+        }
+
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        let required = |name: &str| field(name).ok_or(format!("missing {name}"));
+
+        Ok(Self {
+            domain,
+            address,
+            statement,
+            uri: required("URI")?,
+            version: required("Version")?,
+            chain_id: required("Chain ID")?.parse()?,
+            nonce: required("Nonce")?,
+            issued_at: parse_time(&required("Issued At")?)?,
+            expiration_time: field("Expiration Time")
+                .map(|value| parse_time(&value))
+                .transpose()?,
+            not_before: field("Not Before")
+                .map(|value| parse_time(&value))
+                .transpose()?,
+            request_id: field("Request ID"),
+            resources,
+        })
+    }
+
+    pub fn validate(&self, domain: &str, chain_id: u64, now: DateTime<Utc>) -> Result<()> {
+        let skew = chrono::Duration::minutes(SIWE_CLOCK_SKEW);
+
+        if self.domain != domain {
+            return Err("domain mismatch".into());
+        }
+        if self.version != "1" {
+            return Err("unsupported version".into());
+        }
+        if self.chain_id != chain_id {
+            return Err("chain id mismatch".into());
+        }
+        if self.issued_at > now + skew {
+            return Err("message issued in the future".into());
+        }
+        if self.expiration_time.is_some_and(|expiry| expiry <= now) {
+            return Err("message expired".into());
+        }
+        if self
+            .not_before
+            .is_some_and(|not_before| not_before > now + skew)
+        {
+            return Err("message not yet valid".into());
+        }
+
+        Ok(())
+    }
+}
+
+// SIWE_DOMAIN, or the host of the frontend
+fn siwe_domain() -> Result<String> {
+    if let Ok(domain) = std::env::var("SIWE_DOMAIN") {
+        return Ok(domain);
+    }
+
+    let client_origin = Url::parse(&std::env::var("CLIENT_ORIGIN")?)?;
+    let host = client_origin
+        .host_str()
+        .ok_or("CLIENT_ORIGIN without host")?;
+
+    Ok(match client_origin.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    })
+}
+
+pub fn new_nonce() -> String {
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let expires_at = (Utc::now() + chrono::Duration::minutes(SIWE_NONCE_MAXAGE)).timestamp();
+
+    let mut nonces = SIWE_NONCES.lock().unwrap();
+    nonces.retain(|(_, expires_at)| *expires_at > Utc::now().timestamp());
+    nonces.push((nonce.clone(), expires_at));
+
+    nonce
+}
+
+// nonces are single use
+pub fn take_nonce(nonce: &str) -> bool {
+    let now = Utc::now().timestamp();
+    let mut nonces = SIWE_NONCES.lock().unwrap();
+
+    let found = nonces
+        .iter()
+        .any(|(issued, expires_at)| issued == nonce && *expires_at > now);
+    nonces.retain(|(issued, _)| issued != nonce);
+
+    found
+}
+
+// external wallets keep their keys, so the user has no key shares
+fn find_or_create_wallet_user(address: Address) -> User {
+    let identity = Identity {
+        provider: SIWE_PROVIDER.to_string(),
+        subject: address.to_checksum(None),
+    };
+
+    let mut users = super::USERS.lock().unwrap();
+
+    if let Some(user) = users
+        .iter()
+        .find(|user| user.identities.contains(&identity))
+    {
+        return user.clone();
+    }
+
+    let user = User {
+        id: uuid::Uuid::new_v4().to_string(),
+        email: None,
+        identities: vec![identity],
+        key_shares: None,
+        wallet_address: address.to_checksum(None),
+        addresses: vec![WalletAddress {
+            index: 0,
+            label: DEFAULT_ADDRESS_LABEL.to_string(),
+            address: address.to_checksum(None),
+        }],
+        roles: roles::initial_roles(None, &address.to_checksum(None)),
+    };
+
+    users.push(user.clone());
+    user
+}
+
+#[actix_web::get("/auth/siwe/nonce")]
+async fn siwe_nonce() -> impl Responder {
+    HttpResponse::Ok().json(json!({"nonce": new_nonce()}))
+}
+
+#[actix_web::post("/auth/siwe/verify")]
+async fn siwe_verify(
+    req: HttpRequest,
+    context: web::Data<ActixContext>,
+    input: web::Json<SiweLogin>,
+) -> impl Responder {
+    let message = match SiweMessage::parse(&input.message) {
+        Ok(message) => message,
+        Err(err) => {
+            return HttpResponse::BadRequest().json(
+                json!({"status": "fail", "message": format!("Invalid SIWE message: {err}")}),
+            );
+        }
+    };
+
+    let (domain, chain_id) = match (siwe_domain(), context.contract.chain_id().await) {
+        (Ok(domain), Ok(chain_id)) => (domain, chain_id),
+        _ => {
+            return HttpResponse::InternalServerError()
+                .json(json!({"status": "fail", "message": "Internal Server Error"}));
+        }
+    };
+
+    if let Err(err) = message.validate(&domain, chain_id, Utc::now()) {
+        return HttpResponse::Unauthorized()
+            .json(json!({"status": "fail", "message": format!("Invalid SIWE message: {err}")}));
+    }
+
+    if !take_nonce(&message.nonce) {
+        return HttpResponse::Unauthorized()
+            .json(json!({"status": "fail", "message": "Invalid or expired nonce"}));
+    }
+
+    let valid_signature = match hex::decode(&input.signature) {
+        Ok(signature) => context
+            .contract
+            .is_valid_message_signature(message.address, &input.message, &signature)
+            .await
+            .unwrap_or(false),
+        Err(_) => false,
+    };
+
+    if !valid_signature {
+        return HttpResponse::Unauthorized()
+            .json(json!({"status": "fail", "message": "Invalid signature"}));
+    }
+
+    let user = find_or_create_wallet_user(message.address);
+
+    match sessions::create_session(&user.id, SessionClient::from_request(&req)) {
+        Ok(tokens) => {
+            let [access_cookie, refresh_cookie] = sessions::token_cookies(tokens);
+
+            HttpResponse::Ok()
+                .cookie(access_cookie)
+                .cookie(refresh_cookie)
+                .json(json!({"status": "success", "wallet_address": user.wallet_address}))
+        }
+        Err(err) => {
+            println!("creating session failed! {:?}", err);
+
+            HttpResponse::InternalServerError()
+                .json(json!({"status": "fail", "message": "Internal Server Error"}))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::signers::{SignerSync, local::PrivateKeySigner};
+
+    fn siwe_message(address: Address, nonce: &str) -> String {
+        format!(
+            "app.example.com wants you to sign in with your Ethereum account:\n\
+             {}\n\n\
+             Sign in to Genesis.\n\n\
+             URI: https://app.example.com/login\n\
+             Version: 1\n\
+             Chain ID: 31337\n\
+             Nonce: {nonce}\n\
+             Issued At: 2026-01-01T00:00:00Z\n\
+             Expiration Time: 2026-01-01T00:10:00Z\n\
+             Resources:\n\
+             - https://app.example.com/terms",
+            address.to_checksum(None)
+        )
+    }
+
+    #[test]
+    fn test_parse_and_validate_siwe_message() {
+        let signer = PrivateKeySigner::random();
+        let message = SiweMessage::parse(&siwe_message(signer.address(), "n0nce1234")).unwrap();
+
+        assert_eq!(message.domain, "app.example.com");
+        assert_eq!(message.address, signer.address());
+        assert_eq!(message.statement.as_deref(), Some("Sign in to Genesis."));
+        assert_eq!(message.nonce, "n0nce1234");
+        assert_eq!(message.resources, vec!["https://app.example.com/terms"]);
+
+        let now = parse_time("2026-01-01T00:05:00Z").unwrap();
+        assert!(message.validate("app.example.com", 31337, now).is_ok());
+        assert!(message.validate("evil.example.com", 31337, now).is_err());
+        assert!(message.validate("app.example.com", 1, now).is_err());
+
+        let later = parse_time("2026-01-01T00:10:00Z").unwrap();
+        assert!(message.validate("app.example.com", 31337, later).is_err());
+
+        // lowercase addresses are not accepted
+        let lowercase = siwe_message(signer.address(), "n0nce1234").replace(
+            &signer.address().to_checksum(None),
+            &signer.address().to_string().to_lowercase(),
+        );
+        assert!(SiweMessage::parse(&lowercase).is_err());
+    }
+
+    #[test]
+    fn test_siwe_signature_and_nonce() {
+        let signer = PrivateKeySigner::random();
+        let nonce = new_nonce();
+        let message = siwe_message(signer.address(), &nonce);
+
+        let signature = signer.sign_message_sync(message.as_bytes()).unwrap();
+        assert_eq!(
+            signature.recover_address_from_msg(&message).unwrap(),
+            signer.address()
+        );
+        assert_ne!(
+            signature
+                .recover_address_from_msg(message.replace("31337", "1"))
+                .unwrap(),
+            signer.address()
+        );
+
+        assert!(take_nonce(&nonce));
+        assert!(!take_nonce(&nonce));
+    }
+}
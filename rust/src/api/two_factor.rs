@@ -0,0 +1,383 @@
+use super::{
+    Result,
+    authentication::AuthenticationGuard,
+    sessions,
+    types::{TotpCode, User},
+};
+use actix_web::{
+    FromRequest, HttpRequest, HttpResponse, Responder, dev::Payload, error as actix_error, web,
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{future, sync::Mutex};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const TOTP_STEP: u64 = 30; // seconds
+const BACKUP_CODE_COUNT: usize = 10;
+const DEFAULT_STEP_UP_MAXAGE: i64 = 5; // minutes
+const FREE_ATTEMPTS: u32 = 3;
+const MAX_BACKOFF: u64 = 60 * 60; // seconds
+
+struct TotpEnrolment {
+    user_id: String,
+    secret: Vec<u8>, // Todo : encrypt at rest
+    confirmed: bool,
+    last_step: u64, // last accepted time step, codes can't be replayed
+    backup_code_hashes: Vec<String>,
+    failed_attempts: u32,
+    locked_until: u64,
+}
+
+// Todo - move to db
+static TOTP_ENROLMENTS: Mutex<Vec<TotpEnrolment>> = Mutex::new(Vec::new());
+
+fn hash_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+fn step_up_max_age() -> i64 {
+    std::env::var("STEP_UP_MAXAGE")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_STEP_UP_MAXAGE)
+}
+
+fn totp(secret: Vec<u8>, account_name: &str) -> Result<TOTP> {
+    let issuer = std::env::var("TOTP_ISSUER").unwrap_or("Genesis".to_string());
+
+    Ok(TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        TOTP_STEP,
+        secret,
+        Some(issuer),
+        account_name.replace(':', ""),
+    )?)
+}
+
+fn account_name(user: &User) -> String {
+    user.email.clone().unwrap_or(user.wallet_address.clone())
+}
+
+fn new_backup_codes() -> Vec<String> {
+    (0..BACKUP_CODE_COUNT)
+        .map(|_| {
+            let code = uuid::Uuid::new_v4().simple().to_string();
+            format!("{}-{}", &code[..5], &code[5..10])
+        })
+        .collect()
+}
+
+// accepts a code from the current, previous or next time step that is newer than the last used one
+fn check_totp(enrolment: &mut TotpEnrolment, code: &str, now: u64) -> bool {
+    let Ok(totp) = totp(enrolment.secret.clone(), "") else {
+        return false;
+    };
+
+    let current_step = now / TOTP_STEP;
+    let matched_step = [current_step - 1, current_step, current_step + 1]
+        .into_iter()
+        .filter(|step| *step > enrolment.last_step)
+        .find(|step| totp.generate(step * TOTP_STEP) == code);
+
+    match matched_step {
+        Some(step) => {
+            enrolment.last_step = step;
+            true
+        }
+        None => false,
+    }
+}
+
+// backup codes are single use
+fn use_backup_code(enrolment: &mut TotpEnrolment, code: &str) -> bool {
+    let code_hash = hash_code(code.trim());
+    let unused = enrolment.backup_code_hashes.len();
+
+    enrolment
+        .backup_code_hashes
+        .retain(|hash| *hash != code_hash);
+
+    enrolment.backup_code_hashes.len() < unused
+}
+
+// past FREE_ATTEMPTS wrong codes every further one doubles the wait, so codes
+// can't be guessed. Err holds the seconds until the next attempt is allowed
+fn limit_attempts(
+    enrolment: &mut TotpEnrolment,
+    now: u64,
+    check: impl FnOnce(&mut TotpEnrolment) -> bool,
+) -> std::result::Result<bool, u64> {
+    if now < enrolment.locked_until {
+        return Err(enrolment.locked_until - now);
+    }
+
+    if check(enrolment) {
+        enrolment.failed_attempts = 0;
+        return Ok(true);
+    }
+
+    enrolment.failed_attempts += 1;
+    if let Some(excess) = enrolment.failed_attempts.checked_sub(FREE_ATTEMPTS) {
+        let backoff = TOTP_STEP
+            .saturating_mul(1 << excess.min(16))
+            .min(MAX_BACKOFF);
+        enrolment.locked_until = now + backoff;
+    }
+
+    Ok(false)
+}
+
+fn too_many_attempts(retry_after: u64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(json!({"status": "fail", "message": "Too many attempts", "retry_after": retry_after}))
+}
+
+pub fn has_totp(user_id: &str) -> bool {
+    TOTP_ENROLMENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|enrolment| enrolment.user_id == user_id && enrolment.confirmed)
+}
+
+fn verify_second_factor(user_id: &str, code: &str) -> std::result::Result<bool, u64> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let mut enrolments = TOTP_ENROLMENTS.lock().unwrap();
+
+    match enrolments
+        .iter_mut()
+        .find(|enrolment| enrolment.user_id == user_id && enrolment.confirmed)
+    {
+        Some(enrolment) => limit_attempts(enrolment, now, |enrolment| {
+            check_totp(enrolment, code, now) || use_backup_code(enrolment, code)
+        }),
+        None => Ok(false),
+    }
+}
+
+pub fn is_recent(second_factor_at: Option<i64>, now: i64, max_age: i64) -> bool {
+    second_factor_at.is_some_and(|verified_at| verified_at > now - max_age * 60)
+}
+
+// like AuthenticationGuard, but users with TOTP enabled must have passed the
+// second factor in this session within STEP_UP_MAXAGE minutes
+pub struct StepUpGuard {
+    pub user: User,
+}
+
+impl FromRequest for StepUpGuard {
+    type Error = actix_error::Error;
+    type Future = future::Ready<std::result::Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let auth_guard = match AuthenticationGuard::from_request(req, payload).into_inner() {
+            Ok(auth_guard) => auth_guard,
+            Err(err) => return future::ready(Err(err)),
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        let second_factor_at = sessions::second_factor_at(&auth_guard.claims.sid);
+
+        if has_totp(&auth_guard.user.id) && !is_recent(second_factor_at, now, step_up_max_age()) {
+            return future::ready(Err(actix_error::ErrorForbidden(
+                json!({"status": "fail", "message": "Two-factor verification required", "step_up": true}),
+            )));
+        }
+
+        future::ready(Ok(StepUpGuard {
+            user: auth_guard.user,
+        }))
+    }
+}
+
+#[actix_web::post("/me/2fa/totp")]
+async fn enrol_totp(auth_guard: AuthenticationGuard) -> impl Responder {
+    let user = auth_guard.user;
+
+    if has_totp(&user.id) {
+        return HttpResponse::Conflict()
+            .json(json!({"status": "fail", "message": "TOTP is already enabled"}));
+    }
+
+    let secret = match Secret::generate_secret().to_bytes() {
+        Ok(secret) => secret,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let totp = match totp(secret.clone(), &account_name(&user)) {
+        Ok(totp) => totp,
+        Err(_e) => {
+            println!("creating totp failed! {:?}", _e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut enrolments = TOTP_ENROLMENTS.lock().unwrap();
+    enrolments.retain(|enrolment| enrolment.user_id != user.id);
+    enrolments.push(TotpEnrolment {
+        user_id: user.id,
+        secret,
+        confirmed: false,
+        last_step: 0,
+        backup_code_hashes: Vec::new(),
+        failed_attempts: 0,
+        locked_until: 0,
+    });
+
+    // the otpauth uri is what authenticator apps scan from the QR code
+    HttpResponse::Ok().json(json!({
+        "secret": totp.get_secret_base32(),
+        "otpauth_uri": totp.get_url(),
+    }))
+}
+
+#[actix_web::post("/me/2fa/totp/confirm")]
+async fn confirm_totp(
+    auth_guard: AuthenticationGuard,
+    input: web::Json<TotpCode>,
+) -> impl Responder {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let mut enrolments = TOTP_ENROLMENTS.lock().unwrap();
+
+    let Some(enrolment) = enrolments
+        .iter_mut()
+        .find(|enrolment| enrolment.user_id == auth_guard.user.id && !enrolment.confirmed)
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    match limit_attempts(enrolment, now, |enrolment| {
+        check_totp(enrolment, &input.code, now)
+    }) {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::Unauthorized()
+                .json(json!({"status": "fail", "message": "Invalid code"}));
+        }
+        Err(retry_after) => return too_many_attempts(retry_after),
+    }
+
+    let backup_codes = new_backup_codes();
+    enrolment.backup_code_hashes = backup_codes.iter().map(|code| hash_code(code)).collect();
+    enrolment.confirmed = true;
+
+    sessions::mark_second_factor(&auth_guard.claims.sid);
+
+    // backup codes are only shown once
+    HttpResponse::Ok().json(json!({"status": "success", "backup_codes": backup_codes}))
+}
+
+#[actix_web::post("/me/2fa/verify")]
+async fn verify_totp(
+    auth_guard: AuthenticationGuard,
+    input: web::Json<TotpCode>,
+) -> impl Responder {
+    match verify_second_factor(&auth_guard.user.id, &input.code) {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::Unauthorized()
+                .json(json!({"status": "fail", "message": "Invalid code"}));
+        }
+        Err(retry_after) => return too_many_attempts(retry_after),
+    }
+
+    sessions::mark_second_factor(&auth_guard.claims.sid);
+
+    HttpResponse::Ok().json(json!({"status": "success"}))
+}
+
+#[actix_web::delete("/me/2fa/totp")]
+async fn disable_totp(auth_guard: StepUpGuard) -> impl Responder {
+    TOTP_ENROLMENTS
+        .lock()
+        .unwrap()
+        .retain(|enrolment| enrolment.user_id != auth_guard.user.id);
+
+    HttpResponse::Ok().json(json!({"status": "success"}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enrolment() -> TotpEnrolment {
+        TotpEnrolment {
+            user_id: "totp-user".to_string(),
+            secret: Secret::generate_secret().to_bytes().unwrap(),
+            confirmed: true,
+            last_step: 0,
+            backup_code_hashes: Vec::new(),
+            failed_attempts: 0,
+            locked_until: 0,
+        }
+    }
+
+    #[test]
+    fn test_totp_code_cannot_be_replayed() {
+        let mut enrolment = enrolment();
+        let now = chrono::Utc::now().timestamp() as u64;
+        let code = totp(enrolment.secret.clone(), "").unwrap().generate(now);
+
+        assert!(!check_totp(&mut enrolment, "000000x", now));
+        assert!(check_totp(&mut enrolment, &code, now));
+        assert!(!check_totp(&mut enrolment, &code, now));
+    }
+
+    #[test]
+    fn test_backup_codes_are_single_use() {
+        let mut enrolment = enrolment();
+        let backup_codes = new_backup_codes();
+        enrolment.backup_code_hashes = backup_codes.iter().map(|code| hash_code(code)).collect();
+
+        assert_eq!(backup_codes.len(), BACKUP_CODE_COUNT);
+        assert!(use_backup_code(&mut enrolment, &backup_codes[0]));
+        assert!(!use_backup_code(&mut enrolment, &backup_codes[0]));
+        assert!(use_backup_code(&mut enrolment, &backup_codes[1]));
+    }
+
+    #[test]
+    fn test_wrong_codes_back_off() {
+        let mut enrolment = enrolment();
+        let now = chrono::Utc::now().timestamp() as u64;
+        let code = totp(enrolment.secret.clone(), "").unwrap().generate(now);
+        let attempt = |enrolment: &mut TotpEnrolment, code: &str, now: u64| {
+            limit_attempts(enrolment, now, |enrolment| check_totp(enrolment, code, now))
+        };
+
+        for _ in 0..FREE_ATTEMPTS {
+            assert_eq!(attempt(&mut enrolment, "000000x", now), Ok(false));
+        }
+
+        // even the right code waits out the backoff
+        assert_eq!(attempt(&mut enrolment, &code, now), Err(TOTP_STEP));
+        assert_eq!(
+            attempt(&mut enrolment, "000000x", now + TOTP_STEP),
+            Ok(false)
+        );
+        assert_eq!(
+            attempt(&mut enrolment, &code, now + TOTP_STEP),
+            Err(2 * TOTP_STEP)
+        );
+
+        let later = now + 3 * TOTP_STEP;
+        let code = totp(enrolment.secret.clone(), "").unwrap().generate(later);
+        assert_eq!(attempt(&mut enrolment, &code, later), Ok(true));
+        assert_eq!(enrolment.failed_attempts, 0);
+
+        enrolment.failed_attempts = 40;
+        assert_eq!(attempt(&mut enrolment, "000000x", later), Ok(false));
+        assert_eq!(enrolment.locked_until, later + MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_step_up_freshness() {
+        let now = chrono::Utc::now().timestamp();
+
+        assert!(is_recent(Some(now - 60), now, 5));
+        assert!(!is_recent(Some(now - 6 * 60), now, 5));
+        assert!(!is_recent(None, now, 5));
+    }
+}
@@ -1,28 +1,50 @@
 use super::Result;
-use crate::{blockchain::GTKContract, secret_storage::HcpClient};
+use crate::{
+    blockchain::{GTKContract, MarketplaceContract, SecretKey, SignedOrder},
+    envelope::Keyring,
+    key_shares::{KeyShares, ShareScheme},
+    recovery_service::RecoveryServiceClient,
+    secret_storage::HcpClient,
+};
 use serde::{Deserialize, Serialize};
-use std::hash::{Hash, Hasher};
 
 // Todo : remove unused derives
 
 #[derive(Clone)]
 pub struct ActixContext {
     pub contract: GTKContract,
+    pub marketplace: MarketplaceContract,
     pub http_client: reqwest::Client,
     pub secret_manager: HcpClient,
+    pub keyring: Keyring,
+    pub share_scheme: ShareScheme,
+    pub share_naming_key: Vec<u8>,
+    pub recovery_service: Option<RecoveryServiceClient>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MintInfo {
     // Todo : make token_id auto-increment
     pub token_id: usize,
+    pub address: Option<String>, // which of the minter's addresses receives the token
     pub token_uri: String,
+    pub royalty_receiver: Option<String>, // defaults to the minter
+    pub royalty_bps: Option<u16>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct TransferInfo {
     pub to: String,
     pub token_id: usize,
+    pub address: Option<String>, // the sender's address holding the token
+}
+
+// EIP-712 order fields chosen by a client that signs with its own wallet
+#[derive(Debug, Clone, Deserialize)]
+pub struct OrderSignatureInfo {
+    pub expiry: u64,
+    pub nonce: String,
+    pub signature: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,48 +52,226 @@ pub struct BidInfo {
     #[serde(skip_deserializing)]
     pub bidder: String,
     pub price: f64,
+    #[serde(skip_serializing)]
+    pub signed: Option<OrderSignatureInfo>,
+    #[serde(skip_deserializing)]
+    pub order: Option<SignedOrder>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ListingInfo {
     pub token_id: usize,
-    pub price: f64, // Todo : add more fields like expiration
+    #[serde(default, skip_serializing)]
+    pub address: Option<String>, // the seller's address holding the token
+    pub price: f64,               // Todo : add more fields like expiration
+    pub currency: Option<String>, // ERC-20 address, native ETH if none
+    #[serde(skip_deserializing)]
+    pub bids: Vec<BidInfo>,
+    #[serde(skip_serializing)]
+    pub signed: Option<OrderSignatureInfo>,
     #[serde(skip_deserializing)]
-    pub bids: Vec<BidInfo>
+    pub order: Option<SignedOrder>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OfferInfo {
+    pub price: f64,
+    pub currency: Option<String>, // ERC-20 address, required as ETH can't be pulled from the offerer
+    pub expires_in: Option<i64>,  // minutes
+    pub signed: Option<OrderSignatureInfo>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Offer {
+    pub id: String,
+    pub token_id: usize,
+    pub offerer: String,
+    pub price: f64,
+    pub currency: Option<String>,
+    pub expires_at: i64,
+    pub order: SignedOrder,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SaleInfo {
+    pub token_id: usize,
+    pub seller: String,
+    pub buyer: String,
+    pub price: f64,
+    pub currency: Option<String>,
+    pub split: SaleSplit,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SaleSplit {
+    pub seller_amount: f64,
+    pub creator: String,
+    pub creator_amount: f64,
+    pub platform: Option<String>,
+    pub platform_amount: f64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct QueryParams {
     #[serde(rename = "code")]
     pub auth_code: String,
+    pub state: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SiweLogin {
+    pub message: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct KeystoreExportInfo {
+    pub password: String,
+    pub address: Option<String>, // the primary address by default
+}
+
+#[derive(Deserialize)]
+pub struct RecoveryPhrase {
+    pub phrase: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginParams {
+    pub redirect_to: Option<String>,
+}
+
+// a login at an external provider, `subject` is the provider's stable user id
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Identity {
+    pub provider: String,
+    pub subject: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Minter,
+    User,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Mint,
+    Read,
+    Marketplace,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub user_id: Option<String>,
+    pub expires_in: Option<i64>, // days
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeySummary {
+    pub id: String,
+    pub name: String,
+    pub user_id: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used: Option<i64>,
+    pub revoked: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleInfo {
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WalletAddress {
+    pub index: u32, // BIP-44 address index
+    pub label: String,
+    pub address: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddressLabel {
+    pub label: String,
 }
 
 #[derive(Clone)]
 pub struct User {
+    pub id: String,
+    pub email: Option<String>,
+    pub identities: Vec<Identity>,
+    pub key_shares: Option<KeyShares>, // None for external wallets (SIWE)
+    pub wallet_address: String,        // the address the user acts through, index 0 by default
+    pub addresses: Vec<WalletAddress>, // all addresses derived from the user's seed
+    pub roles: Vec<Role>,
+}
+
+// a user as the original server kept them: the raw wallet key split 3-of-3,
+// two plaintext shares in the record and the third in the secret store
+#[derive(Debug, Deserialize)]
+pub struct BaselineUser {
     pub id: String,
     pub email: String,
     pub key_shares: [String; 2],
     pub wallet_address: String,
+    // the name the old binary stored the third share under, `baseline_share_name` if missing
+    pub secret_name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: String,
+    pub jti: String,
+    pub sid: String, // session the token was issued for
+    #[serde(default)]
+    pub roles: Vec<Role>,
     pub iat: usize,
     pub exp: usize,
 }
 
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: i64,
+    pub last_used: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub current: bool,
+}
+
 impl User {
-    pub async fn get_pk(&self, secret_manager: &HcpClient) -> Result<Vec<u8>> {
-        let mut hasher = std::hash::DefaultHasher::new();
-        self.id.hash(&mut hasher);
-        let key = format!("S{}", &hasher.finish());
+    // the key of the address the user currently acts through
+    pub async fn get_pk(&self, context: &ActixContext) -> Result<SecretKey> {
+        super::key_management::recover_key(context, self, &self.wallet_address).await
+    }
 
-        let secret_share = secret_manager.get_secret(&key).await.unwrap();
+    // the user acting through another of their addresses, None if it isn't theirs
+    pub fn with_address(&self, address: Option<&str>) -> Option<User> {
+        let Some(address) = address else {
+            return Some(self.clone());
+        };
 
-        let mut shares = self.key_shares.to_vec();
-        shares.push(secret_share);
+        self.addresses
+            .iter()
+            .find(|wallet| wallet.address.eq_ignore_ascii_case(address))
+            .map(|wallet| User {
+                wallet_address: wallet.address.clone(),
+                ..self.clone()
+            })
+    }
 
-        crate::utils::recover_secret(&shares)
+    pub fn primary_address(&self) -> Option<&WalletAddress> {
+        self.addresses.iter().find(|wallet| wallet.index == 0)
     }
 }
@@ -0,0 +1,428 @@
+use super::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    sync::Mutex,
+};
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const DEFAULT_AUDIT_LOG_PATH: &str = "audit.jsonl";
+
+// append-only and hash-chained: every event commits to the one before it, so
+// editing, removing or reordering events breaks the chain from there on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub seq: u64,
+    pub id: String,
+    pub at: i64,
+    pub user_id: Option<String>, // whose key or secret, the authenticated user by default
+    pub actor: Option<String>,   // the authenticated user of the request
+    pub action: String,
+    pub subject: String,
+    pub success: bool,
+    pub request_id: Option<String>, // None outside of requests, e.g. at startup or in jobs
+    pub endpoint: Option<String>,
+    pub ip: Option<String>,
+    pub details: serde_json::Value,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEvent {
+    // sha256 over the event with an empty `hash`, `prev_hash` links it to the chain
+    fn compute_hash(&self) -> Result<String> {
+        let unhashed = AuditEvent {
+            hash: String::new(),
+            ..self.clone()
+        };
+
+        Ok(hex::encode(Sha256::digest(serde_json::to_vec(&unhashed)?)))
+    }
+}
+
+struct AuditLog {
+    events: Vec<AuditEvent>,
+    file: Option<File>, // events are only kept in memory until `open` is called
+    // set by the first failed write, no further events are accepted after it so
+    // that audited operations fail rather than go unrecorded
+    failed: Option<String>,
+}
+
+// Todo - move to db
+static AUDIT_LOG: Mutex<AuditLog> = Mutex::new(AuditLog {
+    events: Vec::new(),
+    file: None,
+    failed: None,
+});
+
+// the HTTP request events are recorded in, see api::audit::request_scope
+pub struct RequestScope {
+    pub request_id: String,
+    pub endpoint: String,
+    pub ip: Option<String>,
+    actor: RefCell<Option<String>>,
+}
+
+impl RequestScope {
+    pub fn new(endpoint: String, ip: Option<String>) -> Self {
+        Self {
+            request_id: uuid::Uuid::new_v4().to_string(),
+            endpoint,
+            ip,
+            actor: RefCell::new(None),
+        }
+    }
+}
+
+tokio::task_local! {
+    static REQUEST: RequestScope;
+}
+
+pub async fn in_request<F: Future>(scope: RequestScope, future: F) -> F::Output {
+    REQUEST.scope(scope, future).await
+}
+
+// called once the request is authenticated
+pub fn set_actor(user_id: &str) {
+    let _ = REQUEST.try_with(|request| *request.actor.borrow_mut() = Some(user_id.to_string()));
+}
+
+// AUDIT_LOG_PATH, audit.jsonl by default. Refuses to continue a broken chain
+pub fn open_from_env() -> Result<()> {
+    let path = std::env::var("AUDIT_LOG_PATH").unwrap_or(DEFAULT_AUDIT_LOG_PATH.to_string());
+
+    let events = match File::open(&path) {
+        Ok(_) => verify_file(&path)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+    println!("audit log {} opened with {} events", path, events.len());
+
+    let mut audit_log = AUDIT_LOG.lock().unwrap();
+    // events recorded before the file was opened go after the stored ones
+    let pending = std::mem::replace(&mut audit_log.events, events);
+    audit_log.file = Some(file);
+    for event in pending {
+        append(&mut audit_log, event)?;
+    }
+
+    Ok(())
+}
+
+// chains the event onto the log. Once a write failed the log stops accepting
+// events, the chain in memory stays the one in the file
+fn append(audit_log: &mut AuditLog, mut event: AuditEvent) -> Result<()> {
+    if let Some(err) = &audit_log.failed {
+        return Err(format!("audit log unavailable: {err}").into());
+    }
+
+    event.seq = audit_log.events.len() as u64;
+    event.prev_hash = audit_log
+        .events
+        .last()
+        .map_or(GENESIS_HASH.to_string(), |last| last.hash.clone());
+    event.hash = event.compute_hash()?;
+
+    if let Some(file) = audit_log.file.as_mut() {
+        if let Err(err) = write_event(file, &event) {
+            println!("writing audit event {} failed: {}", event.id, err);
+            audit_log.failed = Some(err.to_string());
+            return Err(format!("audit log unavailable: {err}").into());
+        }
+    }
+
+    audit_log.events.push(event);
+    Ok(())
+}
+
+// one JSON line per event, on disk before the operation goes on
+fn write_event(file: &mut File, event: &AuditEvent) -> Result<()> {
+    writeln!(file, "{}", serde_json::to_string(event)?)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+// fails when the event can't be stored, the caller must not go on unaudited
+pub fn record(
+    user_id: Option<&str>,
+    action: &str,
+    subject: &str,
+    success: bool,
+    details: serde_json::Value,
+) -> Result<()> {
+    let (request_id, endpoint, ip, actor) = REQUEST
+        .try_with(|request| {
+            (
+                Some(request.request_id.clone()),
+                Some(request.endpoint.clone()),
+                request.ip.clone(),
+                request.actor.borrow().clone(),
+            )
+        })
+        .unwrap_or_default();
+
+    let event = AuditEvent {
+        seq: 0,
+        id: uuid::Uuid::new_v4().to_string(),
+        at: chrono::Utc::now().timestamp(),
+        user_id: user_id.map(str::to_string).or(actor.clone()),
+        actor,
+        action: action.to_string(),
+        subject: subject.to_string(),
+        success,
+        request_id,
+        endpoint,
+        ip,
+        details,
+        prev_hash: String::new(),
+        hash: String::new(),
+    };
+
+    println!(
+        "audit: {} {} {} success: {} request: {}",
+        event.user_id.as_deref().unwrap_or("-"),
+        event.action,
+        event.subject,
+        event.success,
+        event.request_id.as_deref().unwrap_or("-"),
+    );
+
+    append(&mut AUDIT_LOG.lock().unwrap(), event)
+}
+
+// records the outcome of an operation, with the error on failure. A result that
+// couldn't be recorded is turned into an error
+pub fn record_result<T>(
+    user_id: Option<&str>,
+    action: &str,
+    subject: &str,
+    result: Result<T>,
+) -> Result<T> {
+    let details = match &result {
+        Ok(_) => serde_json::json!({}),
+        Err(err) => serde_json::json!({"error": err.to_string()}),
+    };
+
+    record(user_id, action, subject, result.is_ok(), details)?;
+    result
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub user_id: Option<String>,
+    pub action: Option<String>,
+    pub request_id: Option<String>,
+    pub since: Option<i64>, // unix timestamp
+    pub limit: Option<usize>,
+}
+
+// matching events, the most recent `limit` in chain order
+pub fn query(filter: &AuditQuery) -> Vec<AuditEvent> {
+    let audit_log = AUDIT_LOG.lock().unwrap();
+
+    let events: Vec<&AuditEvent> = audit_log
+        .events
+        .iter()
+        .filter(|event| filter.user_id.is_none() || event.user_id == filter.user_id)
+        .filter(|event| {
+            filter
+                .action
+                .as_ref()
+                .is_none_or(|action| &event.action == action)
+        })
+        .filter(|event| filter.request_id.is_none() || event.request_id == filter.request_id)
+        .filter(|event| filter.since.is_none_or(|since| event.at >= since))
+        .collect();
+
+    let skip = events
+        .len()
+        .saturating_sub(filter.limit.unwrap_or(events.len()));
+    events.into_iter().skip(skip).cloned().collect()
+}
+
+// the hash of the last event, anchor it elsewhere to also detect a truncated log
+pub fn head() -> String {
+    AUDIT_LOG
+        .lock()
+        .unwrap()
+        .events
+        .last()
+        .map_or(GENESIS_HASH.to_string(), |last| last.hash.clone())
+}
+
+pub fn verify_events(events: &[AuditEvent]) -> Result<()> {
+    let mut prev_hash = GENESIS_HASH;
+
+    for (seq, event) in events.iter().enumerate() {
+        if event.seq != seq as u64 {
+            return Err(format!("audit event {seq}: found seq {}", event.seq).into());
+        }
+        if event.prev_hash != prev_hash {
+            return Err(format!("audit event {seq}: doesn't follow the previous event").into());
+        }
+        if event.compute_hash()? != event.hash {
+            return Err(format!("audit event {seq}: hash mismatch, the event was modified").into());
+        }
+
+        prev_hash = &event.hash;
+    }
+
+    Ok(())
+}
+
+pub fn verify_file(path: &str) -> Result<Vec<AuditEvent>> {
+    let events = BufReader::new(File::open(path)?)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(number, line)| {
+            serde_json::from_str(&line?)
+                .map_err(|err| format!("{path} line {}: {err}", number + 1).into())
+        })
+        .collect::<Result<Vec<AuditEvent>>>()?;
+
+    verify_events(&events)?;
+
+    Ok(events)
+}
+
+// `verify-audit-log [path]`, checks the chain offline
+pub fn verify_cli(path: Option<String>) -> Result<()> {
+    let path = path
+        .or(std::env::var("AUDIT_LOG_PATH").ok())
+        .unwrap_or(DEFAULT_AUDIT_LOG_PATH.to_string());
+    let events = verify_file(&path)?;
+
+    println!(
+        "{}: {} events, chain intact, head {}",
+        path,
+        events.len(),
+        events.last().map_or(GENESIS_HASH, |last| &last.hash)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(count: usize) -> Vec<AuditEvent> {
+        let mut audit_log = AuditLog {
+            events: Vec::new(),
+            file: None,
+            failed: None,
+        };
+
+        for index in 0..count {
+            let event = AuditEvent {
+                seq: 0,
+                id: format!("event-{index}"),
+                at: 1_700_000_000 + index as i64,
+                user_id: Some("user-1".to_string()),
+                actor: None,
+                action: "secret.get".to_string(),
+                subject: format!("K1_share_0_{index}"),
+                success: true,
+                request_id: Some("request-1".to_string()),
+                endpoint: Some("POST /transfer".to_string()),
+                ip: None,
+                details: serde_json::json!({"index": index}),
+                prev_hash: String::new(),
+                hash: String::new(),
+            };
+            append(&mut audit_log, event).unwrap();
+        }
+
+        audit_log.events
+    }
+
+    #[test]
+    fn test_audit_chain_verifies() {
+        let events = chain(4);
+        assert!(verify_events(&events).is_ok());
+        assert_eq!(events[0].prev_hash, GENESIS_HASH);
+        assert_eq!(events[3].prev_hash, events[2].hash);
+
+        // survives the trip through the log file the CLI verifies
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let mut file = File::create(&path).unwrap();
+        for event in &events {
+            write_event(&mut file, event).unwrap();
+        }
+        let verified = verify_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(verified.unwrap().last().unwrap().hash, events[3].hash);
+    }
+
+    #[test]
+    fn test_audit_chain_detects_tampering() {
+        let mut modified = chain(4);
+        modified[1].success = false;
+        assert!(verify_events(&modified).is_err());
+
+        let mut removed = chain(4);
+        removed.remove(2);
+        assert!(verify_events(&removed).is_err());
+
+        let mut reordered = chain(4);
+        reordered.swap(1, 2);
+        assert!(verify_events(&reordered).is_err());
+
+        // rehashing a modified event doesn't help, the next one still points at the old hash
+        let mut rehashed = chain(4);
+        rehashed[1].subject = "something else".to_string();
+        rehashed[1].hash = rehashed[1].compute_hash().unwrap();
+        assert!(verify_events(&rehashed).is_err());
+    }
+
+    #[test]
+    fn test_failed_write_stops_the_log() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        File::create(&path).unwrap();
+
+        let mut audit_log = AuditLog {
+            events: Vec::new(),
+            // read only, so every write fails
+            file: Some(File::open(&path).unwrap()),
+            failed: None,
+        };
+        let mut events = chain(2).into_iter();
+
+        assert!(append(&mut audit_log, events.next().unwrap()).is_err());
+        assert!(audit_log.failed.is_some());
+
+        // nothing goes on record after it, not even once the file is writable again
+        audit_log.file = Some(OpenOptions::new().append(true).open(&path).unwrap());
+        assert!(append(&mut audit_log, events.next().unwrap()).is_err());
+        assert!(audit_log.events.is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_events_carry_the_request() {
+        let scope = RequestScope::new("POST /transfer".to_string(), None);
+        let request_id = scope.request_id.clone();
+
+        in_request(scope, async {
+            set_actor("user-audit-test");
+            record(None, "key.sign", "0xabc", true, serde_json::json!({})).unwrap();
+        })
+        .await;
+
+        let events = query(&AuditQuery {
+            request_id: Some(request_id),
+            ..Default::default()
+        });
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].user_id.as_deref(), Some("user-audit-test"));
+        assert_eq!(events[0].endpoint.as_deref(), Some("POST /transfer"));
+    }
+}
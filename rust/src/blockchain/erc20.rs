@@ -0,0 +1,96 @@
+use super::{GTKProvider, Result};
+use alloy::{
+    primitives::{Address, U256, utils::parse_units},
+    sol,
+};
+use std::str::FromStr;
+
+sol!(
+    #[sol(rpc)]
+    interface IERC20 {
+        function decimals() external view returns (uint8);
+        function balanceOf(address account) external view returns (uint256);
+        function allowance(address owner, address spender) external view returns (uint256);
+        function approve(address spender, uint256 value) external returns (bool);
+    }
+);
+
+pub(super) type ERC20Instance = IERC20::IERC20Instance<(), GTKProvider>;
+
+pub(super) const ETHER_DECIMALS: u8 = 18;
+
+// parses the shortest decimal representation of the amount, rounded half up to
+// `decimals` places, so a float artifact like 0.30000000000000004 doesn't fail
+// the conversion and 0.1 ether is exactly 10^17 wei
+pub(super) fn decimal_units(amount: f64, decimals: u8) -> Result<U256> {
+    if !amount.is_finite() || amount < 0.0 {
+        return Err(format!("invalid amount {amount}").into());
+    }
+
+    let amount = amount.to_string();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((&amount, ""));
+    let places = fraction.len().min(decimals as usize);
+
+    let decimal = match places {
+        0 => whole.to_string(),
+        _ => format!("{whole}.{}", &fraction[..places]),
+    };
+
+    let mut units: U256 = parse_units(&decimal, decimals)?.into();
+    if fraction[places..].starts_with(['5', '6', '7', '8', '9']) {
+        units += U256::from(1);
+    }
+
+    Ok(units)
+}
+
+// amounts are converted using the token's own decimals
+async fn erc20_amount(token: &ERC20Instance, amount: f64) -> Result<U256> {
+    let decimals = token.decimals().call().await?._0;
+
+    decimal_units(amount, decimals)
+}
+
+pub(super) fn erc20(provider: &GTKProvider, token: &str) -> Result<ERC20Instance> {
+    Ok(IERC20::new(Address::from_str(token)?, provider.clone()))
+}
+
+// converts a price to (currency address, amount in base units), native ETH is the zero address
+pub(super) async fn price_in_base_units(
+    provider: &GTKProvider,
+    price: f64,
+    currency: Option<&str>,
+) -> Result<(Address, U256)> {
+    match currency {
+        Some(token) => {
+            let erc20 = erc20(provider, token)?;
+            Ok((
+                erc20.address().to_owned(),
+                erc20_amount(&erc20, price).await?,
+            ))
+        }
+        None => Ok((Address::ZERO, decimal_units(price, ETHER_DECIMALS)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimal_units() {
+        assert_eq!(
+            decimal_units(0.1, 18).unwrap(),
+            U256::from(100_000_000_000_000_000u64)
+        );
+        assert_eq!(decimal_units(0.1 + 0.2, 6).unwrap(), U256::from(300_000));
+        assert_eq!(decimal_units(12.0, 6).unwrap(), U256::from(12_000_000));
+        assert_eq!(decimal_units(1.23456789, 6).unwrap(), U256::from(1_234_568));
+        assert_eq!(decimal_units(0.0000004, 6).unwrap(), U256::ZERO);
+        assert_eq!(decimal_units(2.5, 0).unwrap(), U256::from(3));
+
+        assert!(decimal_units(-1.0, 18).is_err());
+        assert!(decimal_units(f64::NAN, 18).is_err());
+        assert!(decimal_units(f64::INFINITY, 6).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use super::{Result, Signer};
+use alloy::{
+    primitives::Address,
+    signers::{
+        k256::{
+            ecdsa::SigningKey,
+            elliptic_curve::rand_core::{OsRng, RngCore},
+        },
+        local::{
+            PrivateKeySigner,
+            coins_bip39::{English, Entropy, Mnemonic},
+        },
+    },
+};
+use zeroize::Zeroizing;
+
+const HD_PATH_PREFIX: &str = "m/44'/60'/0'/0/"; // BIP-44, Ethereum
+const HD_SEED_ENTROPY_LEN: usize = 32; // a 24 word mnemonic
+
+// a recovered private key, wiped when dropped. Deliberately neither Debug nor
+// Clone so it can't end up in logs or linger in copies
+pub struct SecretKey(Zeroizing<Vec<u8>>);
+
+impl SecretKey {
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+
+    pub fn address(&self) -> Result<Address> {
+        Ok(self.signer()?.address())
+    }
+
+    // only for handing the key to its owner, e.g. as an encrypted keystore
+    pub fn expose_secret(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_signer(self) -> Result<Signer> {
+        Ok(Signer::Local(self.signer()?))
+    }
+
+    fn signer(&self) -> Result<PrivateKeySigner> {
+        Ok(PrivateKeySigner::from_slice(&self.0)?)
+    }
+}
+
+// BIP-39 entropy, the root all addresses of a user are derived from
+pub fn new_hd_seed() -> SecretKey {
+    let mut entropy = vec![0u8; HD_SEED_ENTROPY_LEN];
+    OsRng.fill_bytes(&mut entropy);
+
+    SecretKey::from_bytes(entropy)
+}
+
+// the key of address `index` below m/44'/60'/0'/0
+pub fn derive_hd_key(seed: &SecretKey, index: u32) -> Result<SecretKey> {
+    let mnemonic =
+        Mnemonic::<English>::new_from_entropy(Entropy::from_slice(seed.expose_secret())?);
+    let derived = mnemonic.derive_key(format!("{HD_PATH_PREFIX}{index}").as_str(), None)?;
+    let key: &SigningKey = derived.as_ref();
+
+    Ok(SecretKey::from_bytes(key.to_bytes().to_vec()))
+}
+
+// recovers the key, signs with it and wipes it again, callers only ever see the signer
+pub async fn sign_with_user_key<T>(
+    key: impl Future<Output = Result<SecretKey>>,
+    sign: impl AsyncFnOnce(&Signer) -> Result<T>,
+) -> Result<T> {
+    let signer = key.await?.into_signer()?;
+
+    sign(&signer).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sign_with_user_key() {
+        let wallet = PrivateKeySigner::random();
+        let key = SecretKey::from_bytes(wallet.to_bytes().to_vec());
+        assert_eq!(key.address().unwrap(), wallet.address());
+
+        let signer_address =
+            sign_with_user_key(async { Ok(key) }, async |signer| Ok(signer.address()))
+                .await
+                .unwrap();
+        assert_eq!(signer_address, wallet.address());
+
+        // a failed recovery never reaches the signing step
+        let signed = sign_with_user_key(async { Err("recovery failed".into()) }, async |_| {
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })
+        .await;
+        assert!(signed.is_err());
+    }
+
+    #[test]
+    fn test_hd_derivation() {
+        // "abandon abandon ... about", the BIP-39 test mnemonic
+        let seed = SecretKey::from_bytes(vec![0u8; 16]);
+
+        assert_eq!(
+            derive_hd_key(&seed, 0).unwrap().address().unwrap(),
+            "0x9858EfFD232B4033E47d90003D41EC34EcaEda94"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert_eq!(
+            derive_hd_key(&seed, 1).unwrap().address().unwrap(),
+            "0x6Fac4D18c912343BF86fa7049364Dd4E424Ab9C0"
+                .parse::<Address>()
+                .unwrap()
+        );
+
+        let seed = new_hd_seed();
+        assert_ne!(
+            derive_hd_key(&seed, 0).unwrap().address().unwrap(),
+            derive_hd_key(&seed, 1).unwrap().address().unwrap()
+        );
+    }
+}
@@ -0,0 +1,623 @@
+use super::{
+    GTKContract, GTKProvider, Result, SignedOrder, Signer,
+    erc20::{self, IERC20, price_in_base_units},
+    send_signed_transaction,
+};
+use alloy::{
+    hex,
+    primitives::{Address, Bytes, U256},
+    providers::Provider,
+    sol,
+    sol_types::{Eip712Domain, eip712_domain},
+};
+use std::{env, str::FromStr};
+
+// GenesisMarketplace.sol, see the hardhat project
+sol!(
+    #[sol(rpc)]
+    interface GenesisMarketplace {
+        struct Order {
+            address maker;
+            address collection;
+            uint256 tokenId;
+            uint256 price;
+            address currency;
+            uint256 expiry;
+            uint256 nonce;
+            uint8 side;
+        }
+
+        function listings(uint256 tokenId) external view returns (address seller, uint256 price, address currency);
+        function bids(uint256 tokenId, address bidder) external view returns (uint256 amount, address currency);
+        function nonceUsed(address maker, uint256 nonce) external view returns (bool);
+        function feeRecipient() external view returns (address);
+        function feeBps() external view returns (uint96);
+        function list(uint256 tokenId, uint256 price, address currency) external;
+        function updatePrice(uint256 tokenId, uint256 price) external;
+        function cancel(uint256 tokenId) external;
+        function buy(uint256 tokenId) external payable;
+        function bid(uint256 tokenId, uint256 amount) external payable;
+        function withdrawBid(uint256 tokenId) external;
+        function acceptBid(uint256 tokenId, address bidder) external;
+        function fillOrder(Order order, bytes signature) external payable;
+        function cancelOrder(uint256 nonce) external;
+    }
+);
+
+#[derive(Clone)]
+pub struct MarketplaceContract {
+    contract: GenesisMarketplace::GenesisMarketplaceInstance<(), GTKProvider>,
+    nft: GTKContract,
+}
+
+impl MarketplaceContract {
+    // MARKETPLACE_CONTRACT_ADDRESS
+    pub fn new(nft: &GTKContract) -> Result<Self> {
+        let marketplace_address = env::var("MARKETPLACE_CONTRACT_ADDRESS")?;
+
+        Ok(Self::at(nft, Address::from_str(&marketplace_address)?))
+    }
+
+    pub(super) fn at(nft: &GTKContract, address: Address) -> Self {
+        Self {
+            contract: GenesisMarketplace::new(address, nft.contract.provider().clone()),
+            nft: nft.clone(),
+        }
+    }
+
+    // orders are bound to the exchange contract that settles them
+    pub async fn order_domain(&self) -> Result<Eip712Domain> {
+        let chain_id = self.provider().get_chain_id().await?;
+
+        Ok(eip712_domain! {
+            name: "GenesisMarketplace",
+            version: "1",
+            chain_id: chain_id,
+            verifying_contract: *self.contract.address(),
+        })
+    }
+
+    fn provider(&self) -> &GTKProvider {
+        self.contract.provider()
+    }
+
+    async fn send(&self, signer: &Signer, data: Bytes, value: U256) -> Result<()> {
+        send_signed_transaction(
+            self.provider(),
+            signer,
+            *self.contract.address(),
+            data,
+            value,
+        )
+        .await
+    }
+
+    // (recipient, basis points) the contract takes from every sale, None without a fee
+    pub async fn platform_fee(&self) -> Result<Option<(String, u16)>> {
+        let fee_bps: u16 = self.contract.feeBps().call().await?._0.try_into()?;
+        if fee_bps == 0 {
+            return Ok(None);
+        }
+
+        let recipient = self.contract.feeRecipient().call().await?._0;
+
+        Ok(Some((recipient.to_string(), fee_bps)))
+    }
+
+    pub async fn has_bid(&self, token_id: usize, bidder: &str) -> Result<bool> {
+        let bid = self
+            .contract
+            .bids(U256::from(token_id), Address::from_str(bidder)?)
+            .call()
+            .await?;
+
+        Ok(bid.amount > U256::ZERO)
+    }
+
+    // at least `price` held in escrow for the bidder, e.g. a bid placed from their own wallet
+    pub async fn has_escrowed(
+        &self,
+        token_id: usize,
+        bidder: &str,
+        price: f64,
+        currency: Option<&str>,
+    ) -> Result<bool> {
+        let (currency, amount) = price_in_base_units(self.provider(), price, currency).await?;
+        let bid = self
+            .contract
+            .bids(U256::from(token_id), Address::from_str(bidder)?)
+            .call()
+            .await?;
+
+        Ok(bid.currency == currency && bid.amount >= amount)
+    }
+
+    pub async fn is_listed(&self, token_id: usize) -> Result<bool> {
+        let listing = self.contract.listings(U256::from(token_id)).call().await?;
+
+        Ok(listing.seller != Address::ZERO)
+    }
+
+    // filled or cancelled on-chain
+    pub async fn is_nonce_used(&self, maker: &str, nonce: U256) -> Result<bool> {
+        Ok(self
+            .contract
+            .nonceUsed(Address::from_str(maker)?, nonce)
+            .call()
+            .await?
+            ._0)
+    }
+
+    // the marketplace pulls ERC-20 payments, so it is the spender the holder approves
+    pub async fn has_funds(&self, token: &str, holder: &str, amount: f64) -> Result<bool> {
+        let erc20 = erc20::erc20(self.provider(), token)?;
+        let (_, amount) = price_in_base_units(self.provider(), amount, Some(token)).await?;
+        let holder = Address::from_str(holder)?;
+
+        let balance = erc20.balanceOf(holder).call().await?._0;
+        let allowance = erc20
+            .allowance(holder, *self.contract.address())
+            .call()
+            .await?
+            ._0;
+
+        Ok(balance >= amount && allowance >= amount)
+    }
+
+    // lets the marketplace pull the amount of a custodial user's order once it is filled
+    pub async fn approve_payment(&self, signer: &Signer, token: &str, amount: f64) -> Result<()> {
+        let (currency, amount) = price_in_base_units(self.provider(), amount, Some(token)).await?;
+
+        self.prepare_payment(signer, currency, amount).await?;
+
+        Ok(())
+    }
+
+    // ERC-20 payments are pulled by the marketplace, native ETH is sent as value
+    async fn prepare_payment(
+        &self,
+        signer: &Signer,
+        currency: Address,
+        amount: U256,
+    ) -> Result<U256> {
+        if currency == Address::ZERO {
+            return Ok(amount);
+        }
+
+        let data = IERC20::new(currency, self.provider())
+            .approve(*self.contract.address(), amount)
+            .calldata()
+            .clone();
+
+        send_signed_transaction(self.provider(), signer, currency, data, U256::ZERO).await?;
+
+        Ok(U256::ZERO)
+    }
+
+    pub async fn list(
+        &self,
+        signer: &Signer,
+        token_id: usize,
+        price: f64,
+        currency: Option<&str>,
+    ) -> Result<()> {
+        let marketplace = *self.contract.address();
+
+        let approved = self
+            .nft
+            .contract
+            .isApprovedForAll(signer.address(), marketplace)
+            .call()
+            .await?
+            ._0;
+
+        if !approved {
+            let data = self
+                .nft
+                .contract
+                .setApprovalForAll(marketplace, true)
+                .calldata()
+                .clone();
+
+            send_signed_transaction(
+                self.provider(),
+                signer,
+                *self.nft.contract.address(),
+                data,
+                U256::ZERO,
+            )
+            .await?;
+        }
+
+        let (currency, price) = price_in_base_units(self.provider(), price, currency).await?;
+        let data = self
+            .contract
+            .list(U256::from(token_id), price, currency)
+            .calldata()
+            .clone();
+
+        self.send(signer, data, U256::ZERO).await
+    }
+
+    pub async fn update_price(&self, signer: &Signer, token_id: usize, price: f64) -> Result<()> {
+        let listing = self.contract.listings(U256::from(token_id)).call().await?;
+
+        let currency = (listing.currency != Address::ZERO).then(|| listing.currency.to_string());
+        let (_, price) = price_in_base_units(self.provider(), price, currency.as_deref()).await?;
+
+        let data = self
+            .contract
+            .updatePrice(U256::from(token_id), price)
+            .calldata()
+            .clone();
+
+        self.send(signer, data, U256::ZERO).await
+    }
+
+    pub async fn cancel(&self, signer: &Signer, token_id: usize) -> Result<()> {
+        let data = self
+            .contract
+            .cancel(U256::from(token_id))
+            .calldata()
+            .clone();
+
+        self.send(signer, data, U256::ZERO).await
+    }
+
+    pub async fn buy(&self, signer: &Signer, token_id: usize) -> Result<()> {
+        let listing = self.contract.listings(U256::from(token_id)).call().await?;
+
+        if listing.seller == Address::ZERO {
+            return Err("Token is not listed".into());
+        }
+
+        let value = self
+            .prepare_payment(signer, listing.currency, listing.price)
+            .await?;
+        let data = self.contract.buy(U256::from(token_id)).calldata().clone();
+
+        self.send(signer, data, value).await
+    }
+
+    pub async fn bid(
+        &self,
+        signer: &Signer,
+        token_id: usize,
+        price: f64,
+        currency: Option<&str>,
+    ) -> Result<()> {
+        let (currency, amount) = price_in_base_units(self.provider(), price, currency).await?;
+
+        // ERC-20 bids come out of the allowance given with `approve_payment`
+        let value = match currency {
+            Address::ZERO => amount,
+            _ => U256::ZERO,
+        };
+        let data = self
+            .contract
+            .bid(U256::from(token_id), amount)
+            .calldata()
+            .clone();
+
+        self.send(signer, data, value).await
+    }
+
+    pub async fn withdraw_bid(&self, signer: &Signer, token_id: usize) -> Result<()> {
+        let data = self
+            .contract
+            .withdrawBid(U256::from(token_id))
+            .calldata()
+            .clone();
+
+        self.send(signer, data, U256::ZERO).await
+    }
+
+    pub async fn accept_bid(&self, signer: &Signer, token_id: usize, bidder: &str) -> Result<()> {
+        let data = self
+            .contract
+            .acceptBid(U256::from(token_id), Address::from_str(bidder)?)
+            .calldata()
+            .clone();
+
+        self.send(signer, data, U256::ZERO).await
+    }
+
+    // invalidates a signed order of the signer, e.g. of a cancelled or repriced listing
+    pub async fn cancel_order(&self, signer: &Signer, nonce: U256) -> Result<()> {
+        let data = self.contract.cancelOrder(nonce).calldata().clone();
+
+        self.send(signer, data, U256::ZERO).await
+    }
+
+    // settles a signed order in one transaction. The signer takes the other side:
+    // buys a sell order, or sells the token to the maker of a buy order
+    pub async fn fill_order(&self, signer: &Signer, signed: &SignedOrder) -> Result<()> {
+        let order = &signed.order;
+
+        let value = match order.side {
+            super::ORDER_SIDE_SELL => {
+                self.prepare_payment(signer, order.currency, order.price)
+                    .await?
+            }
+            _ => U256::ZERO,
+        };
+
+        let data = self
+            .contract
+            .fillOrder(
+                GenesisMarketplace::Order {
+                    maker: order.maker,
+                    collection: order.collection,
+                    tokenId: order.tokenId,
+                    price: order.price,
+                    currency: order.currency,
+                    expiry: order.expiry,
+                    nonce: order.nonce,
+                    side: order.side,
+                },
+                hex::decode(&signed.signature)?.into(),
+            )
+            .calldata()
+            .clone();
+
+        self.send(signer, data, value).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{
+        ORDER_SIDE_BUY, ORDER_SIDE_SELL, new_order_nonce, sign_order,
+        testing::{deploy, funded_signer, local_chain},
+    };
+    use alloy::{
+        primitives::aliases::U96,
+        sol_types::{SolType, SolValue, sol_data},
+    };
+
+    sol!(
+        #[sol(rpc)]
+        interface IMockERC20 {
+            function mint(address to, uint256 amount) external;
+        }
+    );
+
+    // GenesisToken, a marketplace taking `fee_bps` for `fee_recipient` and a 6 decimal MockERC20
+    async fn deploy_marketplace(
+        fee_recipient: Address,
+        fee_bps: u16,
+    ) -> Result<(GTKProvider, MarketplaceContract, Address)> {
+        let (provider, owner) = local_chain()?;
+
+        let token = deploy(
+            &provider,
+            &owner,
+            "GenesisToken",
+            (owner.address(),).abi_encode_params(),
+        )
+        .await?;
+        let marketplace = deploy(
+            &provider,
+            &owner,
+            "GenesisMarketplace",
+            (owner.address(), token, fee_recipient, U96::from(fee_bps)).abi_encode_params(),
+        )
+        .await?;
+        let mock_erc20 = deploy(
+            &provider,
+            &owner,
+            "MockERC20",
+            <(sol_data::String, sol_data::String, sol_data::Uint<8>)>::abi_encode_params(&(
+                "Mock USD".to_string(),
+                "mUSD".to_string(),
+                6,
+            )),
+        )
+        .await?;
+
+        let nft = GTKContract::connect(provider.clone(), token, owner).await?;
+
+        Ok((
+            provider,
+            MarketplaceContract::at(&nft, marketplace),
+            mock_erc20,
+        ))
+    }
+
+    async fn balance_of(provider: &GTKProvider, token: Address, holder: Address) -> Result<U256> {
+        Ok(IERC20::new(token, provider)
+            .balanceOf(holder)
+            .call()
+            .await?
+            ._0)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil + compiled artifacts"]
+    async fn test_contract_offer_settles_atomically() -> Result<()> {
+        let fee_recipient = Address::repeat_byte(0xfe);
+        let (provider, marketplace, mock_erc20) = deploy_marketplace(fee_recipient, 250).await?;
+        let nft = &marketplace.nft;
+        let seller = funded_signer(&provider, &nft.owner).await?;
+        let buyer = funded_signer(&provider, &nft.owner).await?;
+
+        nft.mint_nft(&seller.address().to_string(), 1, "ipfs://token-1", None)
+            .await?;
+        let data = IMockERC20::new(mock_erc20, &provider)
+            .mint(buyer.address(), U256::from(100_000_000))
+            .calldata()
+            .clone();
+        send_signed_transaction(&provider, &nft.owner, mock_erc20, data, U256::ZERO).await?;
+
+        let data = nft
+            .contract
+            .setApprovalForAll(*marketplace.contract.address(), true)
+            .calldata()
+            .clone();
+        send_signed_transaction(
+            &provider,
+            &seller,
+            *nft.contract.address(),
+            data,
+            U256::ZERO,
+        )
+        .await?;
+
+        let token = mock_erc20.to_string();
+        let domain = marketplace.order_domain().await?;
+        let order = nft
+            .build_order(
+                &buyer.address().to_string(),
+                1,
+                10.0,
+                Some(&token),
+                (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as u64,
+                new_order_nonce(),
+                ORDER_SIDE_BUY,
+            )
+            .await?;
+        let offer = SignedOrder {
+            hash: String::new(),
+            signature: sign_order(&buyer, &order, &domain).await?,
+            order,
+        };
+
+        // without the buyer's allowance nothing moves, the token stays with the seller
+        assert!(
+            !marketplace
+                .has_funds(&token, &buyer.address().to_string(), 10.0)
+                .await?
+        );
+        assert!(marketplace.fill_order(&seller, &offer).await.is_err());
+        assert_eq!(nft.owner_of_token(1).await?, seller.address().to_string());
+        assert_eq!(
+            balance_of(&provider, mock_erc20, seller.address()).await?,
+            U256::ZERO
+        );
+
+        marketplace.approve_payment(&buyer, &token, 10.0).await?;
+        assert!(
+            marketplace
+                .has_funds(&token, &buyer.address().to_string(), 10.0)
+                .await?
+        );
+        marketplace.fill_order(&seller, &offer).await?;
+
+        assert_eq!(nft.owner_of_token(1).await?, buyer.address().to_string());
+        assert_eq!(
+            balance_of(&provider, mock_erc20, buyer.address()).await?,
+            U256::from(90_000_000)
+        );
+        assert_eq!(
+            balance_of(&provider, mock_erc20, seller.address()).await?,
+            U256::from(9_750_000)
+        );
+        assert_eq!(
+            balance_of(&provider, mock_erc20, fee_recipient).await?,
+            U256::from(250_000)
+        );
+
+        // the order can't be filled twice
+        assert!(marketplace.fill_order(&buyer, &offer).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil + compiled artifacts"]
+    async fn test_contract_marketplace_listing_and_bids() -> Result<()> {
+        use alloy::{primitives::utils::parse_ether, signers::local::PrivateKeySigner};
+
+        let fee_recipient = PrivateKeySigner::random().address();
+        let (provider, marketplace, _) = deploy_marketplace(fee_recipient, 250).await?;
+        let nft = &marketplace.nft;
+        let seller = funded_signer(&provider, &nft.owner).await?;
+        let bidder = funded_signer(&provider, &nft.owner).await?;
+        let buyer = funded_signer(&provider, &nft.owner).await?;
+
+        assert_eq!(
+            marketplace.platform_fee().await?,
+            Some((fee_recipient.to_string(), 250))
+        );
+
+        nft.mint_nft(&seller.address().to_string(), 1, "ipfs://token-1", None)
+            .await?;
+        marketplace.list(&seller, 1, 1.0, None).await?;
+        marketplace.update_price(&seller, 1, 2.0).await?;
+        let listing = marketplace.contract.listings(U256::from(1)).call().await?;
+        assert_eq!(listing.seller, seller.address());
+        assert_eq!(listing.price, parse_ether("2")?);
+
+        // escrowed bids can be withdrawn once
+        let bidder_address = bidder.address().to_string();
+        marketplace.bid(&bidder, 1, 0.5, None).await?;
+        assert!(marketplace.has_bid(1, &bidder_address).await?);
+        marketplace.withdraw_bid(&bidder, 1).await?;
+        assert!(!marketplace.has_bid(1, &bidder_address).await?);
+        assert!(marketplace.withdraw_bid(&bidder, 1).await.is_err());
+
+        marketplace.bid(&bidder, 1, 0.5, None).await?;
+        assert!(
+            marketplace
+                .accept_bid(&bidder, 1, &bidder_address)
+                .await
+                .is_err()
+        );
+        marketplace.accept_bid(&seller, 1, &bidder_address).await?;
+        assert_eq!(nft.owner_of_token(1).await?, bidder_address);
+        assert_eq!(
+            provider.get_balance(fee_recipient).await?,
+            parse_ether("0.0125")?
+        );
+
+        // a cancelled sell order can't be filled, a fresh one can
+        marketplace.list(&bidder, 1, 1.0, None).await?;
+        let domain = marketplace.order_domain().await?;
+        let expiry = (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as u64;
+        let mut sell_orders = Vec::new();
+        for _ in 0..2 {
+            let order = nft
+                .build_order(
+                    &bidder_address,
+                    1,
+                    1.0,
+                    None,
+                    expiry,
+                    new_order_nonce(),
+                    ORDER_SIDE_SELL,
+                )
+                .await?;
+            sell_orders.push(SignedOrder {
+                hash: String::new(),
+                signature: sign_order(&bidder, &order, &domain).await?,
+                order,
+            });
+        }
+
+        marketplace
+            .cancel_order(&bidder, sell_orders[0].order.nonce)
+            .await?;
+        assert!(
+            marketplace
+                .is_nonce_used(&bidder_address, sell_orders[0].order.nonce)
+                .await?
+        );
+        assert!(
+            marketplace
+                .fill_order(&buyer, &sell_orders[0])
+                .await
+                .is_err()
+        );
+        assert_eq!(nft.owner_of_token(1).await?, bidder_address);
+
+        marketplace.fill_order(&buyer, &sell_orders[1]).await?;
+        assert_eq!(nft.owner_of_token(1).await?, buyer.address().to_string());
+        assert_eq!(
+            provider.get_balance(fee_recipient).await?,
+            parse_ether("0.0375")?
+        );
+
+        Ok(())
+    }
+}
@@ -3,21 +3,31 @@ use GenesisToken::GenesisTokenInstance;
 
 use alloy::{
     consensus::{SignableTransaction, TxLegacy},
-    network::{EthereumWallet, TxSigner},
-    primitives::{Address, TxKind, U256},
+    primitives::{Address, Bytes, TxKind, U256, aliases::U96, utils::format_ether},
     providers::{
-        Provider, ProviderBuilder, RootProvider,
-        fillers::{FillProvider, JoinFill, WalletFiller},
+        Provider, ProviderBuilder, RootProvider, fillers::FillProvider,
         utils::JoinedRecommendedFillers,
     },
-    signers::local::PrivateKeySigner,
+    rpc::types::TransactionRequest,
     sol,
 };
-use std::{env, str::FromStr};
-
+use std::{env, str::FromStr, sync::Arc};
+
+mod erc20;
+mod keys;
+mod marketplace;
+mod orders;
+mod signatures;
+mod signer;
+#[cfg(test)]
+pub mod testing;
 mod types;
 mod utils;
 
+pub use keys::{SecretKey, derive_hd_key, sign_with_user_key};
+pub use marketplace::MarketplaceContract;
+pub use orders::*;
+pub use signer::{RemoteSigner, Signer};
 pub use types::*;
 pub use utils::*;
 
@@ -28,30 +38,90 @@ sol!(
     "../artifacts/contracts/GenesisToken.sol/GenesisToken.json"
 );
 
-type GTKProvider =
-    FillProvider<JoinFill<JoinedRecommendedFillers, WalletFiller<EthereumWallet>>, RootProvider>;
+// ERC-2981 extensions of GenesisToken. The committed artifact predates them and
+// can only be regenerated with solc (`npx hardhat compile`, as the Dockerfile
+// does), until then they are bound by hand and have to match GenesisToken.sol
+sol!(
+    #[sol(rpc)]
+    interface IGenesisRoyalties {
+        function safeMintWithRoyalty(address to, uint256 tokenId, string uri, address royaltyReceiver, uint96 royaltyFraction) external;
+        function royaltyInfo(uint256 tokenId, uint256 salePrice) external view returns (address receiver, uint256 royaltyAmount);
+    }
+);
+
+type GTKProvider = FillProvider<JoinedRecommendedFillers, RootProvider>;
+
+// the provider holds no keys, every transaction is signed by a `Signer` and sent raw
+async fn send_signed_transaction(
+    provider: &GTKProvider,
+    signer: &Signer,
+    to: Address,
+    input: Bytes,
+    value: U256,
+) -> Result<()> {
+    let signer_address = signer.address();
+
+    let request = TransactionRequest::default()
+        .from(signer_address)
+        .to(to)
+        .input(input.clone().into())
+        .value(value);
+
+    // Todo : implement transaction using eip-1559
+    let mut tx = TxLegacy {
+        chain_id: Some(provider.get_chain_id().await?),
+        nonce: provider.get_transaction_count(signer_address).await?,
+        gas_price: provider.get_gas_price().await?,
+        gas_limit: provider.estimate_gas(request).await?,
+        to: TxKind::Call(to),
+        input,
+        value,
+    };
+
+    let signature = signer.sign_transaction(&mut tx).await?;
+
+    let mut out = Vec::new();
+    tx.into_signed(signature).rlp_encode(&mut out);
+
+    let _pending_tx = provider.send_raw_transaction(&out).await?.watch().await?;
+
+    Ok(())
+}
 
 #[derive(Clone)]
 pub struct GTKContract {
     contract: GenesisTokenInstance<(), GTKProvider>,
-    owner_address: Address,
+    owner: Arc<Signer>, // only owner can mint nfts
 }
 
 impl GTKContract {
-    pub async fn new() -> Result<Self> {
+    // NETWORK_URL and NFT_CONTRACT_ADDRESS
+    pub async fn new(owner: Signer) -> Result<Self> {
         let nft_contract_address = env::var("NFT_CONTRACT_ADDRESS")?;
         let url = env::var("NETWORK_URL")?;
-        let owner_private_key: PrivateKeySigner = env::var("OWNER_PRIVATE_KEY")?.parse()?; // only owner can mint nfts
 
-        // adding addresses for signing
-        let wallet = EthereumWallet::from(owner_private_key.clone());
-        let provider = ProviderBuilder::new().wallet(wallet).on_http(url.parse()?);
+        let provider = ProviderBuilder::new().on_http(url.parse()?);
+
+        Self::connect(provider, Address::from_str(&nft_contract_address)?, owner).await
+    }
 
-        let contract = GenesisToken::new(Address::from_str(&nft_contract_address)?, provider);
+    async fn connect(provider: GTKProvider, address: Address, owner: Signer) -> Result<Self> {
+        let contract = GenesisToken::new(address, provider);
+
+        // a wrong key, e.g. recovered from mismatched shares, must not get as far as minting
+        let contract_owner = contract.owner().call().await?._0;
+        if owner.address() != contract_owner {
+            return Err(format!(
+                "owner signer {} is not the contract owner {}",
+                owner.address(),
+                contract_owner
+            )
+            .into());
+        }
 
         Ok(Self {
             contract,
-            owner_address: owner_private_key.address(),
+            owner: Arc::new(owner),
         })
     }
 
@@ -59,20 +129,67 @@ impl GTKContract {
         Ok(self.contract.name().call().await?._0)
     }
 
-    pub async fn mint_nft(&self, to: &str, token_id: usize, token_uri: &str) -> Result<()> {
-        self.contract
-            .safeMint(
-                Address::from_str(to)?,
-                U256::from(token_id),
-                token_uri.to_string(),
-            )
-            .from(self.owner_address)
-            .send()
-            .await?
-            .watch()
+    pub async fn chain_id(&self) -> Result<u64> {
+        Ok(self.contract.provider().get_chain_id().await?)
+    }
+
+    pub async fn mint_nft(
+        &self,
+        to: &str,
+        token_id: usize,
+        token_uri: &str,
+        royalty: Option<(&str, u16)>, // (receiver, basis points)
+    ) -> Result<()> {
+        let data = match royalty {
+            Some((receiver, royalty_bps)) => self
+                .royalties()
+                .safeMintWithRoyalty(
+                    Address::from_str(to)?,
+                    U256::from(token_id),
+                    token_uri.to_string(),
+                    Address::from_str(receiver)?,
+                    U96::from(royalty_bps),
+                )
+                .calldata()
+                .clone(),
+            None => self
+                .contract
+                .safeMint(
+                    Address::from_str(to)?,
+                    U256::from(token_id),
+                    token_uri.to_string(),
+                )
+                .calldata()
+                .clone(),
+        };
+
+        send_signed_transaction(
+            self.contract.provider(),
+            &self.owner,
+            *self.contract.address(),
+            data,
+            U256::ZERO,
+        )
+        .await
+    }
+
+    pub async fn royalty_info(&self, token_id: usize, sale_price: f64) -> Result<RoyaltyInfo> {
+        let sale_price = erc20::decimal_units(sale_price, erc20::ETHER_DECIMALS)?;
+
+        let royalty = self
+            .royalties()
+            .royaltyInfo(U256::from(token_id), sale_price)
+            .call()
             .await?;
 
-        Ok(())
+        Ok(RoyaltyInfo {
+            receiver: royalty.receiver.to_string(),
+            amount: format_ether(royalty.royaltyAmount).parse()?,
+        })
+    }
+
+    fn royalties(&self) -> IGenesisRoyalties::IGenesisRoyaltiesInstance<(), GTKProvider> {
+        IGenesisRoyalties::new(*self.contract.address(), self.contract.provider().clone())
     }
 
     pub async fn owner_of_token(&self, id: usize) -> Result<String> {
@@ -86,44 +203,25 @@ impl GTKContract {
             .to_string())
     }
 
-    pub async fn transfer_nft(&self, owner_pk: &Vec<u8>, to: &str, token_id: usize) -> Result<()> {
-        let signer = PrivateKeySigner::from_slice(&owner_pk)?;
-        let signer_address = signer.address();
-
-        // Todo : implement transaction using eip-1559
+    pub async fn transfer_nft(&self, signer: &Signer, to: &str, token_id: usize) -> Result<()> {
         let data = self
             .contract
-            .safeTransferFrom_0(signer_address, Address::from_str(to)?, U256::from(token_id))
+            .safeTransferFrom_0(
+                signer.address(),
+                Address::from_str(to)?,
+                U256::from(token_id),
+            )
             .calldata()
             .clone();
 
-        let provider = self.contract.provider();
-
-        let mut tx = TxLegacy {
-            chain_id: Some(provider.get_chain_id().await?),
-            nonce: provider.get_transaction_count(signer_address).await?,
-            gas_price: provider.get_gas_price().await?,
-            gas_limit: 80000,
-            to: TxKind::Call(self.contract.address().clone()),
-            input: data.clone(),
-            value: Default::default(),
-        };
-
-        let signature = signer.sign_transaction(&mut tx).await?;
-
-        let mut out = Vec::new();
-        tx.into_signed(signature).rlp_encode(&mut out);
-
-        let _pending_tx = self
-            .contract
-            .provider()
-            .send_raw_transaction(&out)
-            .await
-            .unwrap()
-            .watch()
-            .await?;
-
-        Ok(())
+        send_signed_transaction(
+            self.contract.provider(),
+            signer,
+            *self.contract.address(),
+            data,
+            U256::ZERO,
+        )
+        .await
     }
 
     pub async fn get_metadata(&self, token_id: usize) -> Result<Metadata> {
@@ -156,11 +254,13 @@ async fn test_contract() -> Result<()> {
         network::TxSigner,
         primitives::TxKind,
         providers::Provider,
+        signers::local::PrivateKeySigner,
     };
 
     dotenv::dotenv().ok();
 
-    let contract = GTKContract::new().await.unwrap().contract;
+    let owner = Signer::Local(env::var("TESTING_OWNER_PRIVATE_KEY")?.parse()?);
+    let contract = GTKContract::new(owner).await.unwrap().contract;
     let provider = contract.provider();
 
     let contract_owner = Address::from_str(&env::var("INITIAL_OWNER")?)?;
@@ -203,7 +303,7 @@ async fn test_contract() -> Result<()> {
         nonce: provider.get_transaction_count(test_acc1.address()).await?,
         gas_price: provider.get_gas_price().await?,
         gas_limit: 80000,
-        to: TxKind::Call(contract.address().clone()),
+        to: TxKind::Call(*contract.address()),
         input: data.clone(),
         value: Default::default(),
     };
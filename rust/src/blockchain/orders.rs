@@ -0,0 +1,117 @@
+use super::{GTKContract, Result, Signer};
+use alloy::{
+    hex,
+    primitives::{Address, B256, PrimitiveSignature, U256},
+    sol,
+    sol_types::{Eip712Domain, SolStruct},
+};
+use std::str::FromStr;
+
+sol!(
+    #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+    struct Order {
+        address maker;
+        address collection;
+        uint256 tokenId;
+        uint256 price; // in base units of the currency
+        address currency; // zero address for native ETH
+        uint256 expiry; // unix timestamp
+        uint256 nonce;
+        uint8 side;
+    }
+);
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SignedOrder {
+    pub order: Order,
+    pub hash: String,
+    pub signature: String,
+}
+
+pub const ORDER_SIDE_SELL: u8 = 0;
+pub const ORDER_SIDE_BUY: u8 = 1;
+
+impl GTKContract {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn build_order(
+        &self,
+        maker: &str,
+        token_id: usize,
+        price: f64,
+        currency: Option<&str>,
+        expiry: u64,
+        nonce: U256,
+        side: u8,
+    ) -> Result<Order> {
+        let (currency, price) =
+            super::erc20::price_in_base_units(self.contract.provider(), price, currency).await?;
+
+        Ok(Order {
+            maker: Address::from_str(maker)?,
+            collection: *self.contract.address(),
+            tokenId: U256::from(token_id),
+            price,
+            currency,
+            expiry: U256::from(expiry),
+            nonce,
+            side,
+        })
+    }
+}
+
+pub fn order_hash(order: &Order, domain: &Eip712Domain) -> B256 {
+    order.eip712_signing_hash(domain)
+}
+
+pub async fn sign_order(signer: &Signer, order: &Order, domain: &Eip712Domain) -> Result<String> {
+    let signature = signer.sign_hash(&order_hash(order, domain)).await?;
+
+    Ok(hex::encode_prefixed(signature.as_bytes()))
+}
+
+pub fn verify_order(order: &Order, domain: &Eip712Domain, signature: &str) -> Result<bool> {
+    let signature = PrimitiveSignature::from_str(signature)?;
+    let signer = signature.recover_address_from_prehash(&order_hash(order, domain))?;
+
+    Ok(signer == order.maker)
+}
+
+pub fn new_order_nonce() -> U256 {
+    U256::from_be_slice(uuid::Uuid::new_v4().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::{
+        primitives::utils::parse_ether, signers::local::PrivateKeySigner, sol_types::eip712_domain,
+    };
+
+    #[tokio::test]
+    async fn test_sign_and_verify_order() {
+        let signer = Signer::Local(PrivateKeySigner::random());
+        let domain = eip712_domain! {
+            name: "GenesisMarketplace",
+            version: "1",
+            chain_id: 31337,
+            verifying_contract: Address::repeat_byte(0x11),
+        };
+
+        let mut order = Order {
+            maker: signer.address(),
+            collection: Address::repeat_byte(0x22),
+            tokenId: U256::from(1),
+            price: parse_ether("1.5").unwrap(),
+            currency: Address::ZERO,
+            expiry: U256::from(2_000_000_000u64),
+            nonce: new_order_nonce(),
+            side: ORDER_SIDE_SELL,
+        };
+
+        let signature = sign_order(&signer, &order, &domain).await.unwrap();
+        assert!(verify_order(&order, &domain, &signature).unwrap());
+
+        order.price = parse_ether("0.1").unwrap();
+        assert!(!verify_order(&order, &domain, &signature).unwrap());
+    }
+}
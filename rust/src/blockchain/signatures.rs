@@ -0,0 +1,128 @@
+use super::{GTKContract, Result};
+use alloy::{
+    primitives::{Address, Bytes, FixedBytes, PrimitiveSignature, eip191_hash_message},
+    providers::Provider,
+    sol,
+};
+
+sol!(
+    #[sol(rpc)]
+    interface IERC1271 {
+        function isValidSignature(bytes32 hash, bytes signature) external view returns (bytes4 magicValue);
+    }
+);
+
+const EIP1271_MAGIC_VALUE: FixedBytes<4> = FixedBytes([0x16, 0x26, 0xba, 0x7e]);
+
+// signer of an EIP-191 personal message, None for malformed signatures
+fn recover_message_signer(message: &str, signature: &[u8]) -> Option<Address> {
+    PrimitiveSignature::try_from(signature)
+        .ok()?
+        .recover_address_from_msg(message)
+        .ok()
+}
+
+impl GTKContract {
+    // EOAs are checked with ecrecover, contract wallets through EIP-1271
+    pub async fn is_valid_message_signature(
+        &self,
+        signer: Address,
+        message: &str,
+        signature: &[u8],
+    ) -> Result<bool> {
+        if recover_message_signer(message, signature) == Some(signer) {
+            return Ok(true);
+        }
+
+        let provider = self.contract.provider();
+        if provider.get_code_at(signer).await?.is_empty() {
+            return Ok(false);
+        }
+
+        let magic_value = IERC1271::new(signer, provider)
+            .isValidSignature(
+                eip191_hash_message(message),
+                Bytes::copy_from_slice(signature),
+            )
+            .call()
+            .await
+            .map(|result| result.magicValue);
+
+        Ok(matches!(magic_value, Ok(value) if value == EIP1271_MAGIC_VALUE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::testing::{deploy, local_chain};
+    use alloy::{
+        signers::{SignerSync, local::PrivateKeySigner},
+        sol_types::SolValue,
+    };
+
+    #[tokio::test]
+    #[ignore = "needs anvil + compiled artifacts"]
+    async fn test_contract_wallet_signature() -> Result<()> {
+        let (provider, owner) = local_chain()?;
+        let token = deploy(
+            &provider,
+            &owner,
+            "GenesisToken",
+            (owner.address(),).abi_encode_params(),
+        )
+        .await?;
+        let wallet_key = PrivateKeySigner::random();
+        let wallet = deploy(
+            &provider,
+            &owner,
+            "MockERC1271Wallet",
+            (wallet_key.address(),).abi_encode_params(),
+        )
+        .await?;
+        let contract = GTKContract::connect(provider, token, owner).await?;
+
+        let message = "Sign in to Genesis";
+        let signature = wallet_key.sign_message_sync(message.as_bytes())?.as_bytes();
+        let other = PrivateKeySigner::random()
+            .sign_message_sync(message.as_bytes())?
+            .as_bytes();
+
+        // EOAs recover directly, the wallet only through isValidSignature
+        assert!(
+            contract
+                .is_valid_message_signature(wallet_key.address(), message, &signature)
+                .await?
+        );
+        assert!(
+            contract
+                .is_valid_message_signature(wallet, message, &signature)
+                .await?
+        );
+        assert!(
+            !contract
+                .is_valid_message_signature(wallet, message, &other)
+                .await?
+        );
+        assert!(
+            !contract
+                .is_valid_message_signature(wallet, "another message", &signature)
+                .await?
+        );
+        assert!(
+            !contract
+                .is_valid_message_signature(wallet, message, &signature[..64])
+                .await?
+        );
+
+        // an account without code isn't asked
+        let eoa = PrivateKeySigner::random().address();
+        assert!(
+            !contract
+                .is_valid_message_signature(eoa, message, &signature)
+                .await?
+        );
+
+        Ok(())
+    }
+}
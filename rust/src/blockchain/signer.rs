@@ -0,0 +1,238 @@
+use super::Result;
+use crate::audit_log;
+use alloy::{
+    consensus::{SignableTransaction, TxLegacy},
+    hex,
+    primitives::{Address, B256, PrimitiveSignature},
+    signers::{Signer as _, local::PrivateKeySigner},
+};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+// signs transactions and orders, wherever the key happens to live
+pub enum Signer {
+    // key held in this process, recovered from shares or decrypted from a keystore
+    Local(PrivateKeySigner),
+    // key held by a separate signing process, see `RemoteSigner`
+    Remote(RemoteSigner),
+}
+
+impl Signer {
+    // v3 keystore file, e.g. as written by `geth account new` or /me/keys/export
+    pub fn from_keystore(path: &str, password: &str) -> Result<Self> {
+        Ok(Self::Local(PrivateKeySigner::decrypt_keystore(
+            path, password,
+        )?))
+    }
+
+    pub fn address(&self) -> Address {
+        match self {
+            Self::Local(signer) => signer.address(),
+            Self::Remote(signer) => signer.address,
+        }
+    }
+
+    pub async fn sign_hash(&self, hash: &B256) -> Result<PrimitiveSignature> {
+        let signature = match self {
+            Self::Local(signer) => signer.sign_hash(hash).await.map_err(|err| err.into()),
+            Self::Remote(signer) => signer.sign_hash(hash).await,
+        };
+        audit_log::record_result(None, "key.sign", &self.address().to_string(), signature)
+    }
+
+    pub async fn sign_transaction(&self, tx: &mut TxLegacy) -> Result<PrimitiveSignature> {
+        // without a chain id the signature could be replayed on other chains
+        if tx.chain_id.is_none() {
+            return Err("refusing to sign a transaction without a chain id".into());
+        }
+
+        self.sign_hash(&tx.signature_hash()).await
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<String>,
+    error: Option<RpcError>,
+}
+
+// signing service speaking JSON-RPC 2.0 over HTTP(S) with bearer auth:
+//   signer_address  [key_id]       -> "0x<address>"
+//   signer_signHash [key_id, hash] -> "0x<65 byte r ‖ s ‖ v signature>"
+#[derive(Clone)]
+pub struct RemoteSigner {
+    client: Client,
+    url: String,
+    token: String,
+    key_id: String,
+    address: Address,
+}
+
+impl RemoteSigner {
+    // asks the service which address the key signs for
+    pub async fn connect(
+        client: &Client,
+        url: String,
+        token: String,
+        key_id: String,
+    ) -> Result<Self> {
+        let mut signer = Self {
+            client: client.clone(),
+            url,
+            token,
+            key_id,
+            address: Address::ZERO,
+        };
+        signer.address = signer
+            .call("signer_address", json!([signer.key_id]))
+            .await?
+            .parse()?;
+
+        Ok(signer)
+    }
+
+    async fn call(&self, method: &str, params: serde_json::Value) -> Result<String> {
+        let response = self
+            .client
+            .post(&self.url)
+            .bearer_auth(&self.token)
+            .json(&json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<RpcResponse>()
+            .await?;
+
+        match (response.result, response.error) {
+            (_, Some(error)) => {
+                Err(format!("signing service error {}: {}", error.code, error.message).into())
+            }
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(format!("signing service returned no result for {method}").into()),
+        }
+    }
+
+    async fn sign_hash(&self, hash: &B256) -> Result<PrimitiveSignature> {
+        let signature = self
+            .call("signer_signHash", json!([self.key_id, hash]))
+            .await?;
+        let signature = PrimitiveSignature::try_from(hex::decode(signature)?.as_slice())?;
+
+        // never send on a signature that isn't from the key we expect
+        if signature.recover_address_from_prehash(hash)? != self.address {
+            return Err("signing service signed with an unexpected key".into());
+        }
+
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpResponse, HttpServer, web};
+    use alloy::{
+        primitives::{TxKind, U256},
+        signers::SignerSync,
+    };
+    use serde_json::Value;
+
+    // local stand-in for the signing service, holding one key under "owner"
+    async fn stand_in(key: web::Data<PrivateKeySigner>, request: web::Json<Value>) -> HttpResponse {
+        let params = request["params"].as_array().cloned().unwrap_or_default();
+        if params.first().and_then(Value::as_str) != Some("owner") {
+            return HttpResponse::Ok().json(json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": {"code": -32602, "message": "unknown key"},
+            }));
+        }
+
+        let result = match request["method"].as_str() {
+            Some("signer_address") => key.address().to_string(),
+            Some("signer_signHash") => {
+                let hash: B256 = params[1].as_str().unwrap().parse().unwrap();
+                hex::encode_prefixed(key.sign_hash_sync(&hash).unwrap().as_bytes())
+            }
+            _ => return HttpResponse::BadRequest().finish(),
+        };
+
+        HttpResponse::Ok().json(json!({"jsonrpc": "2.0", "id": request["id"], "result": result}))
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer() {
+        let key = web::Data::new(PrivateKeySigner::random());
+        let address = key.address();
+
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(key.clone())
+                .route("/", web::post().to(stand_in))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}/", server.addrs()[0]);
+        tokio::spawn(server.run());
+
+        let client = Client::new();
+        let signer = Signer::Remote(
+            RemoteSigner::connect(&client, url.clone(), "token".into(), "owner".into())
+                .await
+                .unwrap(),
+        );
+        assert_eq!(signer.address(), address);
+
+        let mut tx = TxLegacy {
+            chain_id: Some(31337),
+            nonce: 0,
+            gas_price: 1,
+            gas_limit: 21000,
+            to: TxKind::Call(Address::ZERO),
+            value: U256::from(1),
+            ..Default::default()
+        };
+        let signature = signer.sign_transaction(&mut tx).await.unwrap();
+        assert_eq!(
+            signature
+                .recover_address_from_prehash(&tx.signature_hash())
+                .unwrap(),
+            address
+        );
+
+        tx.chain_id = None;
+        assert!(signer.sign_transaction(&mut tx).await.is_err());
+
+        assert!(
+            RemoteSigner::connect(&client, url, "token".into(), "user-1".into())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keystore_signer() {
+        use alloy::signers::k256::elliptic_curve::rand_core::OsRng;
+
+        let dir = std::env::temp_dir().join(format!("signer-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let (key, _) =
+            PrivateKeySigner::new_keystore(&dir, &mut OsRng, "passphrase", Some("owner.json"))
+                .unwrap();
+        let path = dir.join("owner.json");
+
+        let signer = Signer::from_keystore(path.to_str().unwrap(), "passphrase").unwrap();
+        assert_eq!(signer.address(), key.address());
+        assert!(Signer::from_keystore(path.to_str().unwrap(), "wrong").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
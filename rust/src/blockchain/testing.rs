@@ -0,0 +1,102 @@
+// helpers for the test_contract_* tests. They are ignored by default, they need
+// a local node (anvil or `npx hardhat node`) at NETWORK_URL with
+// TESTING_OWNER_PRIVATE_KEY as a funded account, and the contracts they deploy
+// compiled into ../artifacts, which only holds GenesisToken in the repository:
+//
+//   cd hardhat && npm ci && npx hardhat compile
+//   cd rust && cargo test -- --ignored test_contract_
+use super::{
+    GTKContract, GTKProvider, GenesisToken, MarketplaceContract, Result, Signer,
+    send_signed_transaction,
+};
+use alloy::{
+    consensus::{SignableTransaction, TxLegacy},
+    primitives::{Address, Bytes, TxKind, U256, utils::parse_ether},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{TransactionReceipt, TransactionRequest},
+    signers::local::PrivateKeySigner,
+};
+use std::{env, sync::Arc};
+
+pub fn local_chain() -> Result<(GTKProvider, Signer)> {
+    dotenv::dotenv().ok();
+
+    let provider = ProviderBuilder::new().on_http(env::var("NETWORK_URL")?.parse()?);
+    let owner = Signer::Local(env::var("TESTING_OWNER_PRIVATE_KEY")?.parse()?);
+
+    Ok((provider, owner))
+}
+
+// contracts behind a provider that is never called, for tests that need an
+// ActixContext but no chain
+pub fn unconnected_contracts() -> Result<(GTKContract, MarketplaceContract)> {
+    let provider = ProviderBuilder::new().on_http("http://127.0.0.1:9".parse()?);
+    let nft = GTKContract {
+        contract: GenesisToken::new(Address::ZERO, provider),
+        owner: Arc::new(Signer::Local(PrivateKeySigner::random())),
+    };
+    let marketplace = MarketplaceContract::at(&nft, Address::ZERO);
+
+    Ok((nft, marketplace))
+}
+
+// a fresh account with some ether for gas
+pub async fn funded_signer(provider: &GTKProvider, funder: &Signer) -> Result<Signer> {
+    let signer = Signer::Local(PrivateKeySigner::random());
+
+    send_signed_transaction(
+        provider,
+        funder,
+        signer.address(),
+        Bytes::new(),
+        parse_ether("10")?,
+    )
+    .await?;
+
+    Ok(signer)
+}
+
+// deploys `../artifacts/contracts/<name>.sol/<name>.json` with the abi encoded constructor args
+pub async fn deploy(
+    provider: &GTKProvider,
+    signer: &Signer,
+    name: &str,
+    args: Vec<u8>,
+) -> Result<Address> {
+    let artifact: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(format!(
+        "{}/../artifacts/contracts/{name}.sol/{name}.json",
+        env!("CARGO_MANIFEST_DIR")
+    ))?)?;
+    let bytecode = artifact["bytecode"]
+        .as_str()
+        .ok_or(format!("{name} artifact has no bytecode"))?;
+    let input: Bytes = [alloy::hex::decode(bytecode)?, args].concat().into();
+
+    let request = TransactionRequest::default()
+        .from(signer.address())
+        .input(input.clone().into());
+
+    let mut tx = TxLegacy {
+        chain_id: Some(provider.get_chain_id().await?),
+        nonce: provider.get_transaction_count(signer.address()).await?,
+        gas_price: provider.get_gas_price().await?,
+        gas_limit: provider.estimate_gas(request).await?,
+        to: TxKind::Create,
+        input,
+        value: U256::ZERO,
+    };
+    let signature = signer.sign_transaction(&mut tx).await?;
+
+    let mut out = Vec::new();
+    tx.into_signed(signature).rlp_encode(&mut out);
+
+    let receipt: TransactionReceipt = provider
+        .send_raw_transaction(&out)
+        .await?
+        .get_receipt()
+        .await?;
+
+    Ok(receipt
+        .contract_address
+        .ok_or(format!("{name} wasn't deployed"))?)
+}
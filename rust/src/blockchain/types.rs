@@ -5,3 +5,9 @@ pub struct Metadata {
     pub owner_address: String,
     pub token_uri: String,
 }
+
+#[derive(Serialize, Clone)]
+pub struct RoyaltyInfo {
+    pub receiver: String,
+    pub amount: f64,
+}
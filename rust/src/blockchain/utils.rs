@@ -1,6 +1,13 @@
-use super::Result;
-use alloy::signers::local::PrivateKeySigner;
+use super::{
+    Result,
+    keys::{SecretKey, derive_hd_key, new_hd_seed},
+};
+use alloy::primitives::Address;
 
-pub fn create_eth_account() -> Result<PrivateKeySigner> {
-    Ok(PrivateKeySigner::random())
+// a fresh HD wallet, returns its seed and first address
+pub fn create_eth_account() -> Result<(SecretKey, Address)> {
+    let seed = new_hd_seed();
+    let address = derive_hd_key(&seed, 0)?.address()?;
+
+    Ok((seed, address))
 }
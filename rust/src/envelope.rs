@@ -0,0 +1,283 @@
+use super::Result;
+use crate::secret_storage::HcpClient;
+use aes_gcm::{
+    Aes256Gcm,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+
+const NONCE_LEN: usize = 12;
+
+// key encryption keys by version, new data keys are always wrapped with `current`
+#[derive(Clone)]
+pub struct Keyring {
+    current: u32,
+    keys: Vec<(u32, [u8; 32])>,
+}
+
+// the Shamir shares kept in the user record, encrypted with a per-user data key which is in
+// turn wrapped by the KEK of `kek_version`
+#[derive(Debug, Clone)]
+pub struct EncryptedShares {
+    pub kek_version: u32,
+    pub wrapped_key: String,
+    pub shares: Vec<String>,
+}
+
+fn parse_key(hex_key: &str) -> Result<[u8; 32]> {
+    hex::decode(hex_key.trim())?
+        .try_into()
+        .map_err(|_| "key encryption keys must be 32 bytes".into())
+}
+
+fn encrypt(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<String> {
+    let cipher = Aes256Gcm::new(key.into());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| "encryption failed")?;
+
+    Ok(BASE64.encode([&nonce[..], &ciphertext].concat()))
+}
+
+fn decrypt(key: &[u8; 32], sealed: &str, aad: &[u8]) -> Result<Vec<u8>> {
+    let sealed = BASE64.decode(sealed)?;
+    if sealed.len() < NONCE_LEN {
+        return Err("ciphertext too short".into());
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into()?;
+    let cipher = Aes256Gcm::new(key.into());
+
+    Ok(cipher
+        .decrypt(
+            &nonce.into(),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| "decryption failed")?)
+}
+
+// ciphertexts are bound to the user and KEK version they were created for
+fn key_aad(user_id: &str, kek_version: u32) -> Vec<u8> {
+    format!("dek:{user_id}:v{kek_version}").into_bytes()
+}
+
+fn share_aad(user_id: &str, index: usize) -> Vec<u8> {
+    format!("share:{user_id}:{index}").into_bytes()
+}
+
+impl Keyring {
+    pub fn new(keys: Vec<(u32, [u8; 32])>, current: Option<u32>) -> Result<Self> {
+        let current = current
+            .or(keys.iter().map(|(version, _)| *version).max())
+            .ok_or("no key encryption keys configured")?;
+
+        if !keys.iter().any(|(version, _)| *version == current) {
+            return Err(format!("KEK version {current} not found").into());
+        }
+
+        Ok(Self { current, keys })
+    }
+
+    // one `version:hexkey` per line
+    pub fn from_file(path: &str, current: Option<u32>) -> Result<Self> {
+        let keys = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (version, key) = line.split_once(':').ok_or("invalid KEK line")?;
+                Ok((version.trim().parse()?, parse_key(key)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Self::new(keys, current)
+    }
+
+    // versions listed in KEK_VERSIONS, each stored as KEK_V<version>
+    pub async fn from_secret_store(
+        secret_manager: &HcpClient,
+        current: Option<u32>,
+    ) -> Result<Self> {
+        let mut keys = Vec::new();
+
+        for version in std::env::var("KEK_VERSIONS")?.split(',') {
+            let version: u32 = version.trim().parse()?;
+            let key = secret_manager
+                .get_secret(None, &format!("KEK_V{version}"))
+                .await?;
+            keys.push((version, parse_key(&key)?));
+        }
+
+        Self::new(keys, current)
+    }
+
+    // KEK_SOURCE selects `file` (KEK_FILE) or `secret_store`, KEK_CURRENT_VERSION
+    // pins the wrapping key, otherwise the newest version is used
+    pub async fn load(secret_manager: &HcpClient) -> Result<Self> {
+        let current = match std::env::var("KEK_CURRENT_VERSION") {
+            Ok(version) => Some(version.parse()?),
+            Err(_) => None,
+        };
+
+        match std::env::var("KEK_SOURCE").as_deref() {
+            Ok("secret_store") => Self::from_secret_store(secret_manager, current).await,
+            Ok("file") | Err(_) => Self::from_file(&std::env::var("KEK_FILE")?, current),
+            Ok(source) => Err(format!("unknown KEK_SOURCE {source}").into()),
+        }
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.current
+    }
+
+    fn key(&self, version: u32) -> Result<&[u8; 32]> {
+        self.keys
+            .iter()
+            .find(|(key_version, _)| *key_version == version)
+            .map(|(_, key)| key)
+            .ok_or(format!("KEK version {version} not available").into())
+    }
+
+    fn unwrap_data_key(&self, user_id: &str, encrypted: &EncryptedShares) -> Result<[u8; 32]> {
+        let kek = self.key(encrypted.kek_version)?;
+        let data_key = decrypt(
+            kek,
+            &encrypted.wrapped_key,
+            &key_aad(user_id, encrypted.kek_version),
+        )?;
+
+        data_key.try_into().map_err(|_| "invalid data key".into())
+    }
+
+    pub fn seal_shares(&self, user_id: &str, shares: &[&str]) -> Result<EncryptedShares> {
+        let data_key: [u8; 32] = Aes256Gcm::generate_key(&mut OsRng).into();
+
+        Ok(EncryptedShares {
+            kek_version: self.current,
+            wrapped_key: encrypt(
+                self.key(self.current)?,
+                &data_key,
+                &key_aad(user_id, self.current),
+            )?,
+            shares: shares
+                .iter()
+                .enumerate()
+                .map(|(index, share)| {
+                    encrypt(&data_key, share.as_bytes(), &share_aad(user_id, index))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    pub fn open_shares(&self, user_id: &str, encrypted: &EncryptedShares) -> Result<Vec<String>> {
+        let data_key = self.unwrap_data_key(user_id, encrypted)?;
+
+        encrypted
+            .shares
+            .iter()
+            .enumerate()
+            .map(|(index, share)| {
+                Ok(String::from_utf8(decrypt(
+                    &data_key,
+                    share,
+                    &share_aad(user_id, index),
+                )?)?)
+            })
+            .collect()
+    }
+
+    // moves the data key to the current KEK, the share ciphertexts stay as they are
+    pub fn rewrap(&self, user_id: &str, encrypted: &mut EncryptedShares) -> Result<bool> {
+        if encrypted.kek_version == self.current {
+            return Ok(false);
+        }
+
+        let data_key = self.unwrap_data_key(user_id, encrypted)?;
+
+        encrypted.wrapped_key = encrypt(
+            self.key(self.current)?,
+            &data_key,
+            &key_aad(user_id, self.current),
+        )?;
+        encrypted.kek_version = self.current;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open_shares() {
+        let keyring = Keyring::new(vec![(1, [7u8; 32])], None).unwrap();
+        let encrypted = keyring
+            .seal_shares("user-1", &["share-a", "share-b"])
+            .unwrap();
+
+        assert_eq!(encrypted.kek_version, 1);
+        assert!(!encrypted.shares[0].contains("share-a"));
+        assert_eq!(
+            keyring.open_shares("user-1", &encrypted).unwrap(),
+            vec!["share-a".to_string(), "share-b".to_string()]
+        );
+
+        // ciphertexts can't be moved to another user
+        assert!(keyring.open_shares("user-2", &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_rewrap_keeps_shares() {
+        let old = Keyring::new(vec![(1, [1u8; 32])], None).unwrap();
+        let mut encrypted = old.seal_shares("user-1", &["share-a", "share-b"]).unwrap();
+        let shares_before = encrypted.shares.clone();
+
+        let rotated = Keyring::new(vec![(1, [1u8; 32]), (2, [2u8; 32])], None).unwrap();
+        assert_eq!(rotated.current_version(), 2);
+        assert!(rotated.rewrap("user-1", &mut encrypted).unwrap());
+        assert!(!rotated.rewrap("user-1", &mut encrypted).unwrap());
+
+        assert_eq!(encrypted.kek_version, 2);
+        assert_eq!(encrypted.shares, shares_before);
+
+        // the old KEK can be retired once everything is rewrapped
+        let retired = Keyring::new(vec![(2, [2u8; 32])], None).unwrap();
+        assert_eq!(
+            retired.open_shares("user-1", &encrypted).unwrap(),
+            vec!["share-a".to_string(), "share-b".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_keyring_from_file() {
+        let path = std::env::temp_dir().join(format!("kek-{}", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            format!(
+                "# key encryption keys\n1:{}\n2:{}\n",
+                "11".repeat(32),
+                "22".repeat(32)
+            ),
+        )
+        .unwrap();
+
+        let keyring = Keyring::from_file(path.to_str().unwrap(), Some(1)).unwrap();
+        assert_eq!(keyring.current_version(), 1);
+        assert!(Keyring::from_file(path.to_str().unwrap(), Some(3)).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
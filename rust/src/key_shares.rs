@@ -0,0 +1,408 @@
+use super::Result;
+use crate::envelope::EncryptedShares;
+use alloy::signers::local::coins_bip39::{English, Wordlist};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+const DEFAULT_THRESHOLD: u8 = 3;
+const DEFAULT_LOCATIONS: &str = "user_store,user_backup,secret_store,recovery_service";
+const WORD_BITS: usize = 11;
+const CHECKSUM_LEN: usize = 2;
+const MIN_NAMING_KEY_LEN: usize = 32;
+
+// how new shares held outside the user record are named, see `share_name`
+pub const CURRENT_NAMING: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareLocation {
+    UserStore,
+    SecretStore,
+    UserBackup,
+    RecoveryService,
+}
+
+impl FromStr for ShareLocation {
+    type Err = String;
+
+    fn from_str(location: &str) -> std::result::Result<Self, Self::Err> {
+        match location.trim() {
+            "user_store" => Ok(Self::UserStore),
+            "secret_store" => Ok(Self::SecretStore),
+            "user_backup" => Ok(Self::UserBackup),
+            "recovery_service" => Ok(Self::RecoveryService),
+            other => Err(format!("unknown share location {other}")),
+        }
+    }
+}
+
+impl ShareLocation {
+    // backup shares are kept in the user record until the user takes them
+    pub fn in_user_record(self) -> bool {
+        matches!(self, Self::UserStore | Self::UserBackup)
+    }
+}
+
+// which share goes where, share `i` is held by `locations[i]`
+#[derive(Debug, Clone)]
+pub struct ShareScheme {
+    pub threshold: u8,
+    pub locations: Vec<ShareLocation>,
+}
+
+impl ShareScheme {
+    pub fn new(threshold: u8, locations: Vec<ShareLocation>) -> Result<Self> {
+        if locations.len() > u8::MAX as usize {
+            return Err("too many key shares".into());
+        }
+
+        if threshold < 2 || threshold as usize > locations.len() {
+            return Err(format!(
+                "invalid key share threshold {threshold} of {}",
+                locations.len()
+            )
+            .into());
+        }
+
+        // no single store may be able to recover a key on its own. Backup shares
+        // stay in the user record until the user takes them, so they count with it
+        for (store, held) in [
+            (
+                "user record",
+                locations.iter().filter(|l| l.in_user_record()).count(),
+            ),
+            (
+                "secret store",
+                locations
+                    .iter()
+                    .filter(|l| **l == ShareLocation::SecretStore)
+                    .count(),
+            ),
+            (
+                "recovery service",
+                locations
+                    .iter()
+                    .filter(|l| **l == ShareLocation::RecoveryService)
+                    .count(),
+            ),
+        ] {
+            if held >= threshold as usize {
+                return Err(format!("the {store} would hold {held} of {threshold} shares").into());
+            }
+        }
+
+        Ok(Self {
+            threshold,
+            locations,
+        })
+    }
+
+    // KEY_SHARE_THRESHOLD and KEY_SHARE_LOCATIONS (comma separated), by default
+    // 3-of-4 with the user store and backup shares, the secret store and the
+    // recovery service, so the user record never holds enough shares on its own
+    pub fn from_env() -> Result<Self> {
+        let threshold = match std::env::var("KEY_SHARE_THRESHOLD") {
+            Ok(threshold) => threshold.parse()?,
+            Err(_) => DEFAULT_THRESHOLD,
+        };
+
+        let locations = std::env::var("KEY_SHARE_LOCATIONS")
+            .unwrap_or(DEFAULT_LOCATIONS.to_string())
+            .split(',')
+            .map(ShareLocation::from_str)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Self::new(threshold, locations)
+    }
+
+    pub fn num_shares(&self) -> u8 {
+        self.locations.len() as u8
+    }
+
+    pub fn uses(&self, location: ShareLocation) -> bool {
+        self.locations.contains(&location)
+    }
+}
+
+// what the shares reconstruct
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretKind {
+    HdSeed,     // BIP-39 entropy, addresses are derived from it
+    PrivateKey, // a wallet key from before HD wallets, it only has address 0
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ShareHolder {
+    pub index: u8,
+    pub location: ShareLocation,
+    pub exported: bool, // a backup share handed to the user is no longer kept by us
+}
+
+impl ShareHolder {
+    pub fn in_user_record(&self) -> bool {
+        self.location.in_user_record() && !self.exported
+    }
+}
+
+// a user's wallet key, split `threshold` of `holders.len()`
+#[derive(Debug, Clone)]
+pub struct KeyShares {
+    pub generation: u32, // bumped on every reshare, old shares don't combine with new ones
+    pub naming: u8,      // `share_name` version the remote shares are stored under
+    pub secret_kind: SecretKind,
+    pub threshold: u8,
+    pub holders: Vec<ShareHolder>,
+    // a backup share the user exported belongs to an older generation and no
+    // longer recovers the key, the user has to export the current one
+    pub backup_replaced: bool,
+    // shares of the holders kept in the user record, in holder order
+    pub sealed: EncryptedShares,
+}
+
+// SHARE_NAMING_KEY, hex encoded, keys the share names
+pub fn naming_key_from_env() -> Result<Vec<u8>> {
+    let naming_key = hex::decode(std::env::var("SHARE_NAMING_KEY")?.trim())?;
+
+    if naming_key.len() < MIN_NAMING_KEY_LEN {
+        return Err(format!("SHARE_NAMING_KEY must be at least {MIN_NAMING_KEY_LEN} bytes").into());
+    }
+
+    Ok(naming_key)
+}
+
+// name of a share in the secret store or recovery service. v1 is an
+// HMAC-SHA256 of the user id, names don't reveal the user and can't be
+// guessed without the key
+pub fn share_name(
+    naming: u8,
+    naming_key: &[u8],
+    user_id: &str,
+    generation: u32,
+    index: u8,
+) -> Result<String> {
+    match naming {
+        1 => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(naming_key)?;
+            mac.update(b"share-name:v1:");
+            mac.update(user_id.as_bytes());
+
+            Ok(format!(
+                "K1_{}_{}_{}",
+                hex::encode(&mac.finalize().into_bytes()[..16]),
+                generation,
+                index
+            ))
+        }
+        _ => Err(format!("unknown share naming v{naming}").into()),
+    }
+}
+
+// the secret store name the original server kept a user's third share under,
+// `S<DefaultHasher(user id)>`. DefaultHasher isn't stable across Rust releases,
+// so the SipHash-1-3 with zero keys it ran then is spelled out here
+pub fn baseline_share_name(user_id: &str) -> String {
+    // hashing a str writes its bytes and a 0xff terminator
+    format!("S{}", sip13(&[user_id.as_bytes(), &[0xff]].concat()))
+}
+
+fn sip13(bytes: &[u8]) -> u64 {
+    let mut v: [u64; 4] = [
+        0x736f6d6570736575,
+        0x646f72616e646f6d,
+        0x6c7967656e657261,
+        0x7465646279746573,
+    ];
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    };
+
+    let chunks = bytes.chunks_exact(8);
+    let tail = chunks.remainder();
+    let last = tail
+        .iter()
+        .enumerate()
+        .fold((bytes.len() as u64) << 56, |last, (i, byte)| {
+            last | (*byte as u64) << (8 * i)
+        });
+
+    for m in chunks
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .chain([last])
+    {
+        v[3] ^= m;
+        round(&mut v);
+        v[0] ^= m;
+    }
+
+    v[2] ^= 0xff;
+    for _ in 0..3 {
+        round(&mut v);
+    }
+
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+// writes a share as BIP-39 words, 11 bits per word over length ‖ share ‖ checksum
+pub fn share_to_words(share: &str) -> Result<String> {
+    let share = share.as_bytes();
+    let length = u8::try_from(share.len()).map_err(|_| "share too long")?;
+    let checksum = Sha256::digest(share);
+
+    let bytes = [&[length], share, &checksum[..CHECKSUM_LEN]].concat();
+    let bits: Vec<bool> = bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1))
+        .collect();
+
+    Ok(bits
+        .chunks(WORD_BITS)
+        .map(|chunk| {
+            let index = (0..WORD_BITS).fold(0, |index, bit| {
+                index << 1 | chunk.get(bit).copied().unwrap_or(false) as usize
+            });
+            English::get(index)
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?
+        .join(" "))
+}
+
+pub fn share_from_words(words: &str) -> Result<String> {
+    let bits = words
+        .split_whitespace()
+        .map(|word| English::get_index(&word.to_lowercase()))
+        .collect::<std::result::Result<Vec<_>, _>>()?
+        .into_iter()
+        .flat_map(|index| (0..WORD_BITS).rev().map(move |bit| index >> bit & 1 == 1))
+        .collect::<Vec<_>>();
+
+    let bytes: Vec<u8> = bits
+        .chunks_exact(8)
+        .map(|byte| byte.iter().fold(0, |value, bit| value << 1 | *bit as u8))
+        .collect();
+
+    let (length, rest) = bytes.split_first().ok_or("empty recovery phrase")?;
+    let length = *length as usize;
+    if rest.len() < length + CHECKSUM_LEN {
+        return Err("recovery phrase is incomplete".into());
+    }
+
+    let (share, checksum) = rest.split_at(length);
+    if Sha256::digest(share)[..CHECKSUM_LEN] != checksum[..CHECKSUM_LEN] {
+        return Err("recovery phrase checksum mismatch".into());
+    }
+
+    Ok(String::from_utf8(share.to_vec())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ShareLocation::*;
+
+    #[test]
+    fn test_share_scheme_validation() {
+        assert!(ShareScheme::new(2, vec![UserStore, SecretStore, RecoveryService]).is_ok());
+        assert!(
+            ShareScheme::new(3, vec![UserStore, UserBackup, SecretStore, RecoveryService]).is_ok()
+        );
+        assert!(
+            ShareScheme::new(
+                3,
+                vec![
+                    UserStore,
+                    UserBackup,
+                    SecretStore,
+                    SecretStore,
+                    RecoveryService
+                ]
+            )
+            .is_ok()
+        );
+
+        // the user record alone could recover the key
+        assert!(ShareScheme::new(2, vec![UserStore, SecretStore, UserBackup]).is_err());
+        // the secret store alone could recover the key
+        assert!(ShareScheme::new(2, vec![UserStore, SecretStore, SecretStore]).is_err());
+        assert!(ShareScheme::new(4, vec![UserStore, SecretStore, RecoveryService]).is_err());
+        assert!(ShareScheme::new(1, vec![UserStore, SecretStore]).is_err());
+    }
+
+    #[test]
+    fn test_share_location_parsing() {
+        assert_eq!("user_backup".parse::<ShareLocation>(), Ok(UserBackup));
+        assert_eq!(" secret_store".parse::<ShareLocation>(), Ok(SecretStore));
+        assert!("hsm".parse::<ShareLocation>().is_err());
+        assert_eq!(
+            serde_json::to_string(&RecoveryService).unwrap(),
+            "\"recovery_service\""
+        );
+    }
+
+    #[test]
+    fn test_share_words_roundtrip() {
+        let shares = crate::utils::split_secret(&[42u8; 32], 2, 3).unwrap();
+        let words = share_to_words(&shares[0]).unwrap();
+
+        assert!(
+            words
+                .split(' ')
+                .all(|word| English::get_index(word).is_ok())
+        );
+        assert_eq!(share_from_words(&words).unwrap(), shares[0]);
+        assert_eq!(
+            share_from_words(&format!("  {}\n", words.to_uppercase())).unwrap(),
+            shares[0]
+        );
+
+        // a swapped word is caught by the checksum
+        let mut swapped: Vec<&str> = words.split(' ').collect();
+        swapped.swap(1, 2);
+        assert!(share_from_words(&swapped.join(" ")).is_err());
+        assert!(share_from_words("abandon zzz").is_err());
+    }
+
+    #[test]
+    fn test_share_names_are_stable() {
+        let naming_key = [7u8; 32];
+
+        // pinned so a dependency or toolchain upgrade can't silently orphan stored shares
+        assert_eq!(
+            share_name(1, &naming_key, "user-1", 0, 2).unwrap(),
+            "K1_48d3e26dcc1b48fd29cff3e1f36da8e2_0_2"
+        );
+        assert_ne!(
+            share_name(1, &naming_key, "user-1", 0, 2).unwrap(),
+            share_name(1, &[8u8; 32], "user-1", 0, 2).unwrap()
+        );
+        assert_ne!(
+            share_name(1, &naming_key, "user-1", 0, 2).unwrap(),
+            share_name(1, &naming_key, "user-2", 0, 2).unwrap()
+        );
+
+        assert!(share_name(0, &naming_key, "user-1", 0, 2).is_err());
+        assert!(share_name(2, &naming_key, "user-1", 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_baseline_share_names() {
+        // as DefaultHasher named them, pinned so a toolchain upgrade can't move them
+        assert_eq!(baseline_share_name(""), "S3476900567878811119");
+        assert_eq!(baseline_share_name("user-1"), "S13796674622764017104");
+        assert_eq!(
+            baseline_share_name("0b6e4c3a-3f0e-4d53-9a63-6f1f3c1f2a57"),
+            "S16712600166361937506"
+        );
+    }
+}
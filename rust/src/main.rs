@@ -1,6 +1,13 @@
 mod api;
+mod audit_log;
 mod blockchain;
+mod envelope;
+mod key_shares;
+mod owner_key;
+mod recovery_service;
 mod secret_storage;
+#[cfg(test)]
+mod testing;
 mod utils;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -10,7 +17,16 @@ async fn main() -> Result<()> {
     dotenv::dotenv().ok();
 
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-    api::start_server().await?;
+
+    match std::env::args().nth(1).as_deref() {
+        None | Some("serve") => api::start_server().await?,
+        Some("split-owner-key") => {
+            audit_log::open_from_env()?;
+            owner_key::split_owner_key().await?
+        }
+        Some("verify-audit-log") => audit_log::verify_cli(std::env::args().nth(2))?,
+        Some(other) => return Err(format!("unknown command {other}").into()),
+    }
 
     Ok(())
 }
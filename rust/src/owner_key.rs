@@ -0,0 +1,499 @@
+use super::Result;
+use crate::{
+    audit_log,
+    blockchain::{RemoteSigner, SecretKey, Signer},
+    key_shares::{self, CURRENT_NAMING},
+    recovery_service::RecoveryServiceClient,
+    secret_storage::HcpClient,
+    utils,
+};
+use reqwest::Client;
+use std::{
+    env,
+    io::{Read, Write},
+    os::unix::fs::OpenOptionsExt,
+    str::FromStr,
+};
+use zeroize::Zeroizing;
+
+const DEFAULT_OWNER_KEY_SECRET: &str = "owner_private_key";
+const OWNER_SHARE_ID: &str = "contract-owner"; // stands in for the user id in share names
+const OWNER_SHARE_THRESHOLD: u8 = 2;
+
+// where the contract owner key is loaded from. There is deliberately no
+// plaintext option and no default, startup fails unless one is configured
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OwnerKeySource {
+    Keystore,    // OWNER_KEYSTORE_PATH, OWNER_KEYSTORE_PASSWORD_FILE or OWNER_KEYSTORE_PASSWORD
+    SecretStore, // hex key in the secret store under OWNER_KEY_SECRET
+    Shares,      // 2-of-3 shares, see `OWNER_SHARE_HOLDERS`
+    Remote,      // REMOTE_SIGNER_URL, REMOTE_SIGNER_TOKEN and OWNER_KEY_ID
+}
+
+impl FromStr for OwnerKeySource {
+    type Err = String;
+
+    fn from_str(source: &str) -> std::result::Result<Self, Self::Err> {
+        match source.trim() {
+            "keystore" => Ok(Self::Keystore),
+            "secret_store" => Ok(Self::SecretStore),
+            "shares" => Ok(Self::Shares),
+            "remote" => Ok(Self::Remote),
+            other => Err(format!(
+                "unknown owner key source {other}, expected keystore, secret_store, shares or remote"
+            )),
+        }
+    }
+}
+
+// share `i` of the owner key is held by `OWNER_SHARE_HOLDERS[i]`, so no single
+// store, nor the operator alone, holds enough shares to recover it
+#[derive(Debug, Clone, Copy)]
+enum OwnerShareHolder {
+    SecretStore,
+    RecoveryService,
+    Operator, // a file handed to the operator, mounted at OWNER_KEY_SHARE_FILE
+}
+
+const OWNER_SHARE_HOLDERS: [OwnerShareHolder; 3] = [
+    OwnerShareHolder::SecretStore,
+    OwnerShareHolder::RecoveryService,
+    OwnerShareHolder::Operator,
+];
+
+fn owner_share_name(naming_key: &[u8], index: u8) -> Result<String> {
+    key_shares::share_name(CURRENT_NAMING, naming_key, OWNER_SHARE_ID, 0, index)
+}
+
+fn recovery_service(
+    recovery_service: Option<&RecoveryServiceClient>,
+) -> Result<&RecoveryServiceClient> {
+    Ok(recovery_service.ok_or("recovery service not configured")?)
+}
+
+// a passphrase file (e.g. a mounted docker secret) is preferred over the environment
+fn keystore_password() -> Result<Zeroizing<String>> {
+    match env::var("OWNER_KEYSTORE_PASSWORD_FILE") {
+        Ok(path) => Ok(Zeroizing::new(
+            std::fs::read_to_string(path)?.trim_end().to_string(),
+        )),
+        Err(_) => Ok(Zeroizing::new(env::var("OWNER_KEYSTORE_PASSWORD")?)),
+    }
+}
+
+fn secret_key_from_hex(key: &str) -> Result<SecretKey> {
+    let key = key.trim();
+    Ok(SecretKey::from_bytes(hex::decode(
+        key.strip_prefix("0x").unwrap_or(key),
+    )?))
+}
+
+async fn recover_owner_key(
+    secret_manager: &HcpClient,
+    recovery: Option<&RecoveryServiceClient>,
+    naming_key: &[u8],
+    share_file: Option<&str>,
+) -> Result<SecretKey> {
+    let mut shares = Zeroizing::new(Vec::new());
+
+    for (index, holder) in OWNER_SHARE_HOLDERS.iter().enumerate() {
+        if shares.len() == OWNER_SHARE_THRESHOLD as usize {
+            break;
+        }
+
+        let share = match holder {
+            OwnerShareHolder::SecretStore => match owner_share_name(naming_key, index as u8) {
+                Ok(name) => secret_manager.get_secret(Some(OWNER_SHARE_ID), &name).await,
+                Err(err) => Err(err),
+            },
+            OwnerShareHolder::RecoveryService => match owner_share_name(naming_key, index as u8) {
+                Ok(name) => match recovery_service(recovery) {
+                    Ok(recovery) => recovery.get_share(&name).await,
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(err),
+            },
+            OwnerShareHolder::Operator => share_file
+                .ok_or("OWNER_KEY_SHARE_FILE not set".into())
+                .and_then(|path| Ok(std::fs::read_to_string(path)?.trim().to_string())),
+        };
+
+        match share {
+            Ok(share) => shares.push(share),
+            Err(err) => println!("owner key share {index} ({holder:?}) unavailable: {err}"),
+        }
+    }
+
+    let secret = utils::recover_secret(&shares, OWNER_SHARE_THRESHOLD)
+        .map(|secret| SecretKey::from_bytes(Zeroizing::new(secret).to_vec()));
+
+    audit_log::record_result(
+        Some(OWNER_SHARE_ID),
+        "key.recover_secret",
+        OWNER_SHARE_ID,
+        secret,
+    )
+}
+
+// refuses to run next to a plaintext key rather than silently ignore it
+fn owner_key_source(plaintext_key: bool, source: Option<&str>) -> Result<OwnerKeySource> {
+    if plaintext_key {
+        return Err(
+            "OWNER_PRIVATE_KEY is set, remove the plaintext owner key from the environment".into(),
+        );
+    }
+
+    Ok(source
+        .ok_or("OWNER_KEY_SOURCE must be set to keystore, secret_store, shares or remote")?
+        .parse()?)
+}
+
+// OWNER_KEY_SOURCE selects where the owner key comes from
+pub async fn load_owner_signer(
+    client: &Client,
+    secret_manager: &HcpClient,
+    recovery: Option<&RecoveryServiceClient>,
+    naming_key: &[u8],
+) -> Result<Signer> {
+    let source = owner_key_source(
+        env::var_os("OWNER_PRIVATE_KEY").is_some(),
+        env::var("OWNER_KEY_SOURCE").ok().as_deref(),
+    )?;
+
+    match source {
+        OwnerKeySource::Keystore => {
+            Signer::from_keystore(&env::var("OWNER_KEYSTORE_PATH")?, &keystore_password()?)
+        }
+        OwnerKeySource::SecretStore => {
+            let name = env::var("OWNER_KEY_SECRET").unwrap_or(DEFAULT_OWNER_KEY_SECRET.to_string());
+            let key = Zeroizing::new(
+                secret_manager
+                    .get_secret(Some(OWNER_SHARE_ID), &name)
+                    .await?,
+            );
+            secret_key_from_hex(&key)?.into_signer()
+        }
+        OwnerKeySource::Shares => recover_owner_key(
+            secret_manager,
+            recovery,
+            naming_key,
+            env::var("OWNER_KEY_SHARE_FILE").ok().as_deref(),
+        )
+        .await?
+        .into_signer(),
+        OwnerKeySource::Remote => Ok(Signer::Remote(
+            RemoteSigner::connect(
+                client,
+                env::var("REMOTE_SIGNER_URL")?,
+                env::var("REMOTE_SIGNER_TOKEN")?,
+                env::var("OWNER_KEY_ID")?,
+            )
+            .await?,
+        )),
+    }
+}
+
+// readable by the operator only, an existing share file is never overwritten
+fn write_share_file(path: &str, share: &str) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(share.as_bytes())?;
+
+    Ok(file.sync_all()?)
+}
+
+async fn store_owner_share(
+    secret_manager: &HcpClient,
+    recovery: Option<&RecoveryServiceClient>,
+    naming_key: &[u8],
+    share_file: &str,
+    index: u8,
+    share: &str,
+) -> Result<()> {
+    match OWNER_SHARE_HOLDERS[index as usize] {
+        OwnerShareHolder::SecretStore => {
+            secret_manager
+                .create_secret(
+                    Some(OWNER_SHARE_ID),
+                    &owner_share_name(naming_key, index)?,
+                    share,
+                )
+                .await
+        }
+        OwnerShareHolder::RecoveryService => {
+            recovery_service(recovery)?
+                .store_share(&owner_share_name(naming_key, index)?, share)
+                .await
+        }
+        OwnerShareHolder::Operator => write_share_file(share_file, share),
+    }
+}
+
+async fn delete_owner_share(
+    secret_manager: &HcpClient,
+    recovery: Option<&RecoveryServiceClient>,
+    naming_key: &[u8],
+    share_file: &str,
+    index: u8,
+) -> Result<()> {
+    match OWNER_SHARE_HOLDERS[index as usize] {
+        OwnerShareHolder::SecretStore => {
+            secret_manager
+                .delete_secret(Some(OWNER_SHARE_ID), &owner_share_name(naming_key, index)?)
+                .await
+        }
+        OwnerShareHolder::RecoveryService => {
+            recovery_service(recovery)?
+                .delete_share(&owner_share_name(naming_key, index)?)
+                .await
+        }
+        OwnerShareHolder::Operator => Ok(std::fs::remove_file(share_file)?),
+    }
+}
+
+// every pair of shares must give back the key, OWNER_SHARE_THRESHOLD being 2
+fn check_owner_shares(shares: &[String], key: &SecretKey) -> Result<()> {
+    for first in 0..shares.len() {
+        for second in first + 1..shares.len() {
+            let pair = [shares[first].clone(), shares[second].clone()];
+            let secret = Zeroizing::new(utils::recover_secret(&pair, OWNER_SHARE_THRESHOLD)?);
+
+            if secret.as_slice() != key.expose_secret() {
+                return Err(
+                    format!("owner key shares {first} and {second} don't recover the key").into(),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// hands every share to its holder and recovers the key from them, if that fails
+// anywhere the shares stored so far are removed again
+async fn store_owner_shares(
+    secret_manager: &HcpClient,
+    recovery: Option<&RecoveryServiceClient>,
+    naming_key: &[u8],
+    share_file: &str,
+    key: &SecretKey,
+) -> Result<()> {
+    // checked up front, a rollback would otherwise delete the shares of an earlier split
+    if std::path::Path::new(share_file).exists() {
+        return Err(format!("{share_file} already exists").into());
+    }
+
+    let shares = Zeroizing::new(utils::split_secret(
+        key.expose_secret(),
+        OWNER_SHARE_THRESHOLD,
+        OWNER_SHARE_HOLDERS.len() as u8,
+    )?);
+    check_owner_shares(&shares, key)?;
+
+    let mut stored = Vec::new();
+    let mut result = Ok(());
+
+    for (index, share) in shares.iter().enumerate() {
+        result = store_owner_share(
+            secret_manager,
+            recovery,
+            naming_key,
+            share_file,
+            index as u8,
+            share,
+        )
+        .await;
+
+        if result.is_err() {
+            break;
+        }
+        stored.push(index as u8);
+    }
+
+    if result.is_ok() {
+        result =
+            match recover_owner_key(secret_manager, recovery, naming_key, Some(share_file)).await {
+                Ok(recovered) if recovered.expose_secret() == key.expose_secret() => Ok(()),
+                Ok(_) => Err("stored owner key shares recover a different key".into()),
+                Err(err) => Err(err),
+            };
+    }
+
+    if result.is_err() {
+        for index in stored {
+            if let Err(err) =
+                delete_owner_share(secret_manager, recovery, naming_key, share_file, index).await
+            {
+                println!("removing owner key share {index} failed: {err}");
+            }
+        }
+    }
+
+    result
+}
+
+// `split-owner-key`: reads the hex owner key from stdin, stores shares 0 and 1
+// in the secret store and recovery service and writes the operator share to
+// OWNER_KEY_SHARE_FILE. Afterwards run with OWNER_KEY_SOURCE=shares
+pub async fn split_owner_key() -> Result<()> {
+    let client = Client::new();
+    let secret_manager = HcpClient::from_env(&client).await?;
+    let recovery = RecoveryServiceClient::from_env(&client);
+    let naming_key = key_shares::naming_key_from_env()?;
+    let share_file = env::var("OWNER_KEY_SHARE_FILE")?;
+
+    let mut input = Zeroizing::new(String::new());
+    std::io::stdin().read_to_string(&mut input)?;
+    let key = secret_key_from_hex(&input)?;
+    let address = key.address()?;
+
+    store_owner_shares(
+        &secret_manager,
+        recovery.as_ref(),
+        &naming_key,
+        &share_file,
+        &key,
+    )
+    .await?;
+
+    println!(
+        "owner key of {address} split {OWNER_SHARE_THRESHOLD} of {}",
+        OWNER_SHARE_HOLDERS.len()
+    );
+    println!("operator share written to {share_file}, keep it off the server's disk");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::Method;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_owner_key_source_parsing() {
+        assert_eq!(
+            "keystore".parse::<OwnerKeySource>(),
+            Ok(OwnerKeySource::Keystore)
+        );
+        assert_eq!(
+            " shares".parse::<OwnerKeySource>(),
+            Ok(OwnerKeySource::Shares)
+        );
+        // there is no way back to a plaintext key
+        assert!("env".parse::<OwnerKeySource>().is_err());
+        assert!("".parse::<OwnerKeySource>().is_err());
+    }
+
+    #[test]
+    fn test_owner_share_names() {
+        let naming_key = [7u8; 32];
+
+        // owner shares are named apart from the shares of users
+        let name = owner_share_name(&naming_key, 1).unwrap();
+        assert!(name.ends_with("_0_1"));
+        assert_ne!(
+            name,
+            key_shares::share_name(CURRENT_NAMING, &naming_key, "user-1", 0, 1).unwrap()
+        );
+
+        let key = secret_key_from_hex(&format!("0x{}\n", "11".repeat(32))).unwrap();
+        assert_eq!(key.expose_secret(), [0x11; 32]);
+    }
+
+    #[test]
+    fn test_owner_key_source_fails_closed() {
+        assert!(owner_key_source(true, Some("keystore")).is_err());
+        assert!(owner_key_source(false, None).is_err());
+        assert!(owner_key_source(false, Some("env")).is_err());
+        assert_eq!(
+            owner_key_source(false, Some("remote")).unwrap(),
+            OwnerKeySource::Remote
+        );
+    }
+
+    fn share_file() -> String {
+        std::env::temp_dir()
+            .join(format!("owner-share-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_split_owner_key() {
+        let client = Client::new();
+        let (secret_manager, recovery, store) = crate::testing::share_stores(&client);
+        let naming_key = [7u8; 32];
+        let share_file = share_file();
+        let key = secret_key_from_hex(&"22".repeat(32)).unwrap();
+
+        store_owner_shares(
+            &secret_manager,
+            Some(&recovery),
+            &naming_key,
+            &share_file,
+            &key,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(store.len(), 2);
+        let mode = std::fs::metadata(&share_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // any two holders recover the key
+        let recovered = recover_owner_key(&secret_manager, None, &naming_key, Some(&share_file))
+            .await
+            .unwrap();
+        assert_eq!(recovered.expose_secret(), key.expose_secret());
+
+        // a second split doesn't touch the first one
+        assert!(
+            store_owner_shares(
+                &secret_manager,
+                Some(&recovery),
+                &naming_key,
+                &share_file,
+                &key
+            )
+            .await
+            .is_err()
+        );
+        assert_eq!(store.len(), 2);
+
+        std::fs::remove_file(share_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_split_owner_key_rolls_back() {
+        let client = Client::new();
+        let (secret_manager, recovery, store) = crate::testing::share_stores(&client);
+        let naming_key = [7u8; 32];
+        let share_file = share_file();
+        let key = secret_key_from_hex(&"22".repeat(32)).unwrap();
+
+        store.fail("/recovery", Method::PUT);
+        assert!(
+            store_owner_shares(
+                &secret_manager,
+                Some(&recovery),
+                &naming_key,
+                &share_file,
+                &key
+            )
+            .await
+            .is_err()
+        );
+        assert_eq!(store.len(), 0);
+        assert!(!std::path::Path::new(&share_file).exists());
+
+        // shares that don't recover the key are never stored
+        store.recover();
+        let shares = utils::split_secret(&[0x33; 32], OWNER_SHARE_THRESHOLD, 3).unwrap();
+        assert!(check_owner_shares(&shares, &key).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use super::Result;
+use reqwest::Client;
+use serde::Deserialize;
+
+// escrow service holding recovery shares outside of our own infrastructure,
+// shares are stored with PUT/GET/DELETE on `<url>/shares/<key>`
+#[derive(Clone)]
+pub struct RecoveryServiceClient {
+    client: Client,
+    url: String,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct StoredShare {
+    value: String,
+}
+
+impl RecoveryServiceClient {
+    pub fn new(client: &Client, url: String, token: String) -> Self {
+        Self {
+            client: client.clone(),
+            url: url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    // RECOVERY_SERVICE_URL and RECOVERY_SERVICE_TOKEN, None when not configured
+    pub fn from_env(client: &Client) -> Option<Self> {
+        let url = std::env::var("RECOVERY_SERVICE_URL").ok()?;
+        let token = std::env::var("RECOVERY_SERVICE_TOKEN").ok()?;
+
+        Some(Self::new(client, url, token))
+    }
+
+    pub async fn store_share(&self, key: &str, value: &str) -> Result<()> {
+        self.client
+            .put(format!("{}/shares/{}", self.url, key))
+            .bearer_auth(&self.token)
+            .json(&serde_json::json!({ "value": value }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    pub async fn get_share(&self, key: &str) -> Result<String> {
+        let share = self
+            .client
+            .get(format!("{}/shares/{}", self.url, key))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<StoredShare>()
+            .await?;
+
+        Ok(share.value)
+    }
+
+    pub async fn delete_share(&self, key: &str) -> Result<()> {
+        self.client
+            .delete(format!("{}/shares/{}", self.url, key))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
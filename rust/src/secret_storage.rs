@@ -1,4 +1,5 @@
 use super::Result;
+use crate::audit_log;
 use reqwest::{Client, Method, header};
 use serde::Deserialize;
 
@@ -68,7 +69,53 @@ impl HcpClient {
         })
     }
 
-    pub async fn create_secret(&self, key: &str, value: &str) -> Result<()> {
+    // a client for a local stand-in of the secrets api
+    #[cfg(test)]
+    pub fn with_endpoint(client: &Client, hcp_endpoint: String) -> Self {
+        Self {
+            client: client.clone(),
+            access_token: "token".to_string(),
+            expires_in: 0,
+            hcp_endpoint,
+            org_id: String::new(),
+            proj_id: String::new(),
+            app_name: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+        }
+    }
+
+    // HCP_CLIENT_ID, HCP_CLIENT_SECRET, HCP_ORG_ID, HCP_PROJ_ID and HCP_APP_NAME
+    pub async fn from_env(client: &Client) -> Result<Self> {
+        Self::new(
+            client,
+            std::env::var("HCP_CLIENT_ID")?,
+            std::env::var("HCP_CLIENT_SECRET")?,
+            std::env::var("HCP_ORG_ID")?,
+            std::env::var("HCP_PROJ_ID")?,
+            std::env::var("HCP_APP_NAME")?,
+        )
+        .await
+    }
+
+    // every access is audited, the secret names are the subjects and `user_id`
+    // whose secret it is, outside of requests there is no user to fall back on
+    pub async fn create_secret(&self, user_id: Option<&str>, key: &str, value: &str) -> Result<()> {
+        let created = self.write_secret(key, value).await;
+        audit_log::record_result(user_id, "secret.create", key, created)
+    }
+
+    pub async fn get_secret(&self, user_id: Option<&str>, key: &str) -> Result<String> {
+        let secret = self.open_secret(key).await;
+        audit_log::record_result(user_id, "secret.get", key, secret)
+    }
+
+    pub async fn delete_secret(&self, user_id: Option<&str>, key: &str) -> Result<()> {
+        let deleted = self.remove_secret(key).await;
+        audit_log::record_result(user_id, "secret.delete", key, deleted)
+    }
+
+    async fn write_secret(&self, key: &str, value: &str) -> Result<()> {
         let url = format!("{}/secret/kv", self.hcp_endpoint);
 
         let json = serde_json::json!({
@@ -87,7 +134,7 @@ impl HcpClient {
         Ok(())
     }
 
-    pub async fn get_secret(&self, key: &str) -> Result<String> {
+    async fn open_secret(&self, key: &str) -> Result<String> {
         let response = self
             .client
             .get(format!("{}/secrets/{}:open", self.hcp_endpoint, key))
@@ -104,7 +151,7 @@ impl HcpClient {
         }
     }
 
-    pub async fn delete_secret(&self, key: &str) -> Result<()> {
+    async fn remove_secret(&self, key: &str) -> Result<()> {
         self.client
             .delete(format!("{}/secrets/{}", self.hcp_endpoint, key))
             .bearer_auth(&self.access_token)
@@ -137,16 +184,22 @@ mod tests {
                 .unwrap();
 
         hcp_client
-            .create_secret("new_test_secret", "new_secret_value")
+            .create_secret(None, "new_test_secret", "new_secret_value")
             .await
             .unwrap();
 
-        let value = hcp_client.get_secret("new_test_secret").await.unwrap();
+        let value = hcp_client
+            .get_secret(None, "new_test_secret")
+            .await
+            .unwrap();
         assert_eq!(value, "new_secret_value");
 
-        hcp_client.delete_secret("new_test_secret").await.unwrap();
+        hcp_client
+            .delete_secret(None, "new_test_secret")
+            .await
+            .unwrap();
 
-        let value = hcp_client.get_secret("new_test_secret").await;
+        let value = hcp_client.get_secret(None, "new_test_secret").await;
         assert!(value.is_err());
     }
 }
@@ -0,0 +1,111 @@
+// stand-ins for the services holding key shares, for tests of the share
+// handling without the HCP secrets api or a recovery service
+use crate::{recovery_service::RecoveryServiceClient, secret_storage::HcpClient};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, http::Method, web};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Mutex};
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+// local stand-in for the secret store (under /hcp) and the recovery service
+// (under /recovery), requests matching `failing` are answered with 503 and
+// `on_write` runs before the next share is stored
+#[derive(Default)]
+pub struct StandIn {
+    pub shares: Mutex<HashMap<String, String>>,
+    pub failing: Mutex<Vec<(&'static str, Method)>>,
+    pub on_write: Mutex<Option<Hook>>,
+}
+
+impl StandIn {
+    pub fn fail(&self, prefix: &'static str, method: Method) {
+        self.failing.lock().unwrap().push((prefix, method));
+    }
+
+    pub fn recover(&self) {
+        self.failing.lock().unwrap().clear();
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.shares.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.shares.lock().unwrap().len()
+    }
+}
+
+async fn stand_in(req: HttpRequest, body: web::Bytes, store: web::Data<StandIn>) -> HttpResponse {
+    let path = req.path();
+    if store
+        .failing
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(prefix, method)| path.starts_with(prefix) && req.method() == method)
+    {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+
+    // stored as /hcp/<name> and /recovery/<key>
+    let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let key = match path {
+        "/hcp/secret/kv" => format!("/hcp/{}", body["name"].as_str().unwrap_or_default()),
+        path => path
+            .replacen("/hcp/secrets/", "/hcp/", 1)
+            .replacen("/recovery/shares/", "/recovery/", 1)
+            .trim_end_matches(":open")
+            .to_string(),
+    };
+
+    if matches!(*req.method(), Method::POST | Method::PUT) {
+        if let Some(hook) = store.on_write.lock().unwrap().take() {
+            hook();
+        }
+    }
+
+    let mut shares = store.shares.lock().unwrap();
+    let value = match *req.method() {
+        Method::POST | Method::PUT => {
+            let value = body["value"].as_str().unwrap_or_default().to_string();
+            shares.insert(key, value);
+            return HttpResponse::Ok().finish();
+        }
+        Method::DELETE => shares.remove(&key),
+        _ => shares.get(&key).cloned(),
+    };
+
+    match value {
+        Some(_) if req.method() == Method::DELETE => HttpResponse::Ok().finish(),
+        Some(value) if key.starts_with("/hcp/") => HttpResponse::Ok()
+            .json(serde_json::json!({"secret": {"static_version": {"value": value}}})),
+        Some(value) => HttpResponse::Ok().json(serde_json::json!({"value": value})),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// a secret store and recovery service client, both backed by a fresh stand-in
+pub fn share_stores(
+    client: &reqwest::Client,
+) -> (HcpClient, RecoveryServiceClient, web::Data<StandIn>) {
+    let store = web::Data::new(StandIn::default());
+    let app_store = store.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(app_store.clone())
+            .default_service(web::to(stand_in))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let base_url = format!("http://{}", server.addrs()[0]);
+    tokio::spawn(server.run());
+
+    (
+        HcpClient::with_endpoint(client, format!("{base_url}/hcp")),
+        RecoveryServiceClient::new(client, format!("{base_url}/recovery"), "token".to_string()),
+        store,
+    )
+}
@@ -1,16 +1,22 @@
 use super::Result;
 use ssss::SsssConfig;
 
-pub fn split_secret(secret: &[u8]) -> Result<Vec<String>> {
+// any `threshold` of the `num_shares` shares recover the secret
+pub fn split_secret(secret: &[u8], threshold: u8, num_shares: u8) -> Result<Vec<String>> {
     let mut config = SsssConfig::default();
-    config.set_num_shares(3);
-    config.set_threshold(3);
+    config.set_num_shares(num_shares);
+    config.set_threshold(threshold);
 
-    Ok(ssss::gen_shares(&config, &secret)?)
+    Ok(ssss::gen_shares(&config, secret)?)
 }
 
-pub fn recover_secret(shares: &[String]) -> Result<Vec<u8>> {
-    Ok(ssss::unlock(&shares)?)
+// ssss returns garbage rather than an error below the threshold, so check first
+pub fn recover_secret(shares: &[String], threshold: u8) -> Result<Vec<u8>> {
+    if shares.len() < threshold as usize {
+        return Err(format!("{} of {} shares available", shares.len(), threshold).into());
+    }
+
+    Ok(ssss::unlock(shares)?)
 }
 
 #[cfg(test)]
@@ -20,16 +26,38 @@ mod tests {
     #[test]
     fn test_secret_share() {
         let secret = b"secret";
-        let shares = split_secret(secret).unwrap();
-        let recovered_secret = recover_secret(&shares).unwrap();
+        let shares = split_secret(secret, 3, 3).unwrap();
+        let recovered_secret = recover_secret(&shares, 3).unwrap();
         assert_eq!(recovered_secret, secret);
     }
 
     #[test]
     fn test_secret_share_fail() {
         let secret = b"secret";
-        let shares = split_secret(secret).unwrap();
-        let recovered_secret = recover_secret(&shares[..2]).unwrap();
+        let shares = split_secret(secret, 3, 3).unwrap();
+        assert!(recover_secret(&shares[..2], 3).is_err());
+
+        // even when the threshold isn't passed in, too few shares don't give the secret
+        let recovered_secret = ssss::unlock(&shares[..2]).unwrap();
         assert_ne!(recovered_secret, secret);
     }
+
+    #[test]
+    fn test_any_threshold_subset_recovers() {
+        let secret = b"secret";
+        let shares = split_secret(secret, 3, 5).unwrap();
+
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let subset = [shares[a].clone(), shares[b].clone(), shares[c].clone()];
+                    assert_eq!(recover_secret(&subset, 3).unwrap(), secret);
+                }
+            }
+        }
+
+        let shares = split_secret(secret, 2, 3).unwrap();
+        assert_eq!(recover_secret(&shares[1..], 2).unwrap(), secret);
+        assert!(recover_secret(&shares[..1], 2).is_err());
+    }
 }